@@ -1,5 +1,6 @@
-function make_admin(ev, id) {
+function make_admin(ev) {
     let value = ev.checked;
+    let id = ev.dataset.uid;
 
     let xhr = new XMLHttpRequest();
     xhr.open("POST", "/api/setadmin", true);
@@ -10,8 +11,9 @@ function make_admin(ev, id) {
     xhr.send(`value=${value}&uid=${id}`);
 }
 
-function make_employee(ev, id) {
+function make_employee(ev) {
     let value = ev.checked;
+    let id = ev.dataset.uid;
 
     let xhr = new XMLHttpRequest();
     xhr.open("POST", "/api/setemployee", true);
@@ -21,3 +23,12 @@ function make_employee(ev, id) {
     xhr.setRequestHeader("Content-Type", "application/x-www-form-urlencoded");
     xhr.send(`value=${value}&uid=${id}`);
 }
+
+document.addEventListener('DOMContentLoaded', () => {
+    for (let elem of document.querySelectorAll('input.make-admin')) {
+        elem.addEventListener('input', () => make_admin(elem));
+    }
+    for (let elem of document.querySelectorAll('input.make-employee')) {
+        elem.addEventListener('input', () => make_employee(elem));
+    }
+});
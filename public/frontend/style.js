@@ -6,6 +6,27 @@ if (window['l10n_cache'] === undefined) {
     window['l10n_cache'] = {};
 }
 
+// requests which change something have to send the token the page was rendered with,
+// this covers the WebAssembly frontend as well
+(function () {
+    let open = XMLHttpRequest.prototype.open;
+    let send = XMLHttpRequest.prototype.send;
+
+    XMLHttpRequest.prototype.open = function (method, url) {
+        this.csrf_needed = !['GET', 'HEAD', 'OPTIONS'].includes(method.toUpperCase())
+            && new URL(url, window.location.href).origin === window.location.origin;
+        return open.apply(this, arguments);
+    };
+
+    XMLHttpRequest.prototype.send = function () {
+        let meta = document.querySelector('meta[name="csrf-token"]');
+        if (this.csrf_needed && meta !== null) {
+            this.setRequestHeader('X-CSRF-Token', meta.content);
+        }
+        return send.apply(this, arguments);
+    };
+})();
+
 togglenav = {
     hidden: false,
     width: undefined,
@@ -44,6 +65,15 @@ function l10n(which, onload) {
     }
 }
 
+// the script is loaded asynchronously, so the page may be parsed already
+function on_load(callback) {
+    if (document.readyState === 'loading') {
+        document.addEventListener('DOMContentLoaded', callback);
+    } else {
+        callback();
+    }
+}
+
 function toggleNav(id) {
     if (togglenav.hidden) {
         togglenav.hidden = false;
@@ -63,3 +93,13 @@ function toggleNav(id) {
     }
     return false;
 }
+
+on_load(() => {
+    let toggle = document.getElementById('togglenav');
+    if (toggle !== null) {
+        toggle.addEventListener('click', (ev) => {
+            ev.preventDefault();
+            toggleNav('nav');
+        });
+    }
+});
@@ -1,6 +1,7 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
 use std::path::Path;
 
-use actix_http::HttpMessage;
 use actix_web::{get, http, post, web, HttpRequest, HttpResponse, Responder};
 use tokio::fs;
 
@@ -8,14 +9,26 @@ use actix_identity::Identity;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
-use crate::error::Result;
-use crate::template;
+use crate::assets;
+use crate::audit::{Action, Event};
+use crate::error::{Error, Result};
+use crate::frontmatter;
+use crate::i18n::{Lang, Language, Plural};
+use crate::path::PublicPath;
+use crate::repo::NewArticle;
+use crate::template::{self, Context};
+use crate::token::Scope;
 use crate::web::ServerData;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ArticleData {
     title: String,
     article: String,
+    #[serde(default)]
+    lang: String,
+    // id of the article this one is a translation of, empty if none
+    #[serde(default)]
+    translation_of: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,6 +38,12 @@ pub struct SaveDeleteData {
     delete: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NewLanguageData {
+    code: String,
+    language: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ApiDraftData {
     id: i32,
@@ -42,7 +61,7 @@ pub struct SetEmployeeData {
     uid: i32,
 }
 
-fn pathify(string: &str) -> (String, String) {
+pub(crate) fn pathify(string: &str) -> (String, String) {
     let public_path = format!(
         "articles/{}",
         string.replace(|ch: char| !ch.is_alphanumeric(), "-")
@@ -51,6 +70,21 @@ fn pathify(string: &str) -> (String, String) {
     (public_path, private_path)
 }
 
+// whether the article `id` or one of its translations is already in `lang`
+// `web::article_path` could only pick one of two translations into the same language
+pub(crate) async fn translated(data: &ServerData<'_>, id: i32, lang: &str) -> Result<bool> {
+    let original = match data.repo.article(id).await? {
+        Some(article) => article,
+        None => return Ok(false),
+    };
+    Ok(data
+        .repo
+        .translations(&original.path)
+        .await?
+        .iter()
+        .any(|article| article.lang.as_deref() == Some(lang)))
+}
+
 fn draftify(user: &str, string: &str) -> String {
     let public_path = format!(
         "drafts/{}",
@@ -63,19 +97,17 @@ fn draftify(user: &str, string: &str) -> String {
 #[get("/account/me.html")]
 pub async fn me<'a>(
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
+    let lang = data.language(&lang);
     if identity.identity().is_some() {
         let mut body = fs::read_to_string("public/account/me.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -86,9 +118,9 @@ pub async fn me<'a>(
     } else {
         let mut body = fs::read_to_string("private/forbidden.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -100,25 +132,18 @@ pub async fn me<'a>(
 #[get("/account/admin.html")]
 pub async fn admin_panel<'a>(
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    if let Some(username) = identity.identity() {
-        let admin = data.client.query_opt(
-            "select id from admins where uid = \
-             (select id as uid from users where username = $1)",
-            &[&username]
-        ).await?;
-        if admin.is_none() {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if !user.admin {
             let mut body = fs::read_to_string("private/forbidden.html").await?;
             template::search_replace_recursive(
-                &identity,
-                &data.client,
-                &data.lang[&lang],
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
                 &mut body,
                 &[],
             )
@@ -126,11 +151,60 @@ pub async fn admin_panel<'a>(
             return Ok(HttpResponse::Forbidden().body(body));
         }
 
+        // the search of the panel
         let mut body = fs::read_to_string("public/account/admin.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[req.query_string().to_string()],
+        )
+        .await?;
+        Ok(HttpResponse::Ok()
+            .header(http::header::CONTENT_TYPE, "text/html")
+            .body(body))
+    } else {
+        let mut body = fs::read_to_string("private/forbidden.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[],
+        )
+        .await?;
+        Ok(HttpResponse::Forbidden().body(body))
+    }
+}
+
+#[get("/account/translations.html")]
+pub async fn translations<'a>(
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if !user.admin {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            return Ok(HttpResponse::Forbidden().body(body));
+        }
+
+        let mut body = fs::read_to_string("public/account/translations.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -141,9 +215,9 @@ pub async fn admin_panel<'a>(
     } else {
         let mut body = fs::read_to_string("private/forbidden.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -152,63 +226,190 @@ pub async fn admin_panel<'a>(
     }
 }
 
-#[post("/account/draft.html")]
-pub async fn save<'a>(
-    draft_data: web::Json<SaveDeleteData>,
+#[post("/account/translations.html")]
+pub async fn save_translations<'a>(
+    translation_data: web::Form<HashMap<String, String>>,
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    if let Some(username) = identity.identity() {
-        let uid = data
-            .client
-            .query_one(
-                "select users.id as uid from users where username = $1",
-                &[&username],
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if !user.admin {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
             )
             .await?;
-        let uid = uid.get::<_, i32>("uid");
-        let user = data
-            .client
-            .query_opt(
-                "select employees.id from employees where employees.uid = $1",
-                &[&uid],
+            return Ok(HttpResponse::Forbidden().body(body));
+        }
+
+        // fields are named `code:key` or `code:key:plural`, empty ones are removed
+        // `new:code` fields belong to the key in `new_key`
+        // plain texts are set first, they become the `other` form if a language gets plural forms
+        let translation_data = translation_data.into_inner();
+        let new_key = translation_data
+            .get("new_key")
+            .map(|key| key.trim())
+            .unwrap_or("");
+        let mut langs = data
+            .lang
+            .raw()
+            .into_iter()
+            .map(|(code, lang)| {
+                let empty = Language::new(lang.code().to_string(), lang.language().to_string());
+                (code, empty)
+            })
+            .collect::<BTreeMap<_, _>>();
+        let mut fields = Vec::new();
+        for (name, text) in &translation_data {
+            if text.is_empty() {
+                continue;
+            }
+            let mut parts = name.splitn(3, ':');
+            let (code, key, plural) = match (parts.next(), parts.next(), parts.next()) {
+                (Some("new"), Some(code), None) if !new_key.is_empty() => (code, new_key, None),
+                (Some("new"), _, _) => continue,
+                (Some(code), Some(key), plural) => (code, key, plural),
+                _ => continue,
+            };
+            let plural = match plural {
+                Some(plural) => match Plural::from_name(plural) {
+                    Some(plural) => Some(plural),
+                    None => continue,
+                },
+                None => None,
+            };
+            fields.push((code, key, plural, text));
+        }
+        fields.sort_by_key(|(_, _, plural, _)| plural.is_some());
+        for (code, key, plural, text) in fields {
+            if let Some(lang) = langs.get_mut(code) {
+                lang.set(key, plural, text.to_string());
+            }
+        }
+        data.lang.save(langs, &data.config.i18n).await?;
+        Event::new(Some(&user), Action::SaveTranslations)
+            .record(&data, &req)
+            .await?;
+
+        Ok(HttpResponse::SeeOther()
+            .header("Location", "/account/translations.html")
+            .finish())
+    } else {
+        let mut body = fs::read_to_string("private/forbidden.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[],
+        )
+        .await?;
+        Ok(HttpResponse::Forbidden().body(body))
+    }
+}
+
+#[post("/account/languages.html")]
+pub async fn new_language<'a>(
+    language_data: web::Form<NewLanguageData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if !user.admin {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
             )
             .await?;
-        if user.is_some() {
+            return Ok(HttpResponse::Forbidden().body(body));
+        }
+
+        let language_data = language_data.into_inner();
+        let code = language_data.code.trim().to_lowercase();
+        // the code ends up in a file name
+        let valid = !code.is_empty()
+            && code.len() <= 8
+            && code.chars().all(|ch| ch.is_ascii_lowercase() || ch == '-');
+        if !valid || data.lang.contains(&code) {
+            return Err(Error::IllegalResource(code));
+        }
+        let name = language_data.language.trim().to_string();
+        let event = Event::new(Some(&user), Action::AddLanguage)
+            .target(&code)
+            .after(&name);
+        let language = Language::new(code, name);
+        data.lang.add(&*data.repo, language, &data.config.i18n).await?;
+        event.record(&data, &req).await?;
+
+        Ok(HttpResponse::SeeOther()
+            .header("Location", "/account/translations.html")
+            .finish())
+    } else {
+        let mut body = fs::read_to_string("private/forbidden.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[],
+        )
+        .await?;
+        Ok(HttpResponse::Forbidden().body(body))
+    }
+}
+
+#[post("/account/draft.html")]
+pub async fn save<'a>(
+    draft_data: web::Json<SaveDeleteData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if user.employee {
             let draft_data = draft_data.into_inner();
             let title = draft_data.title;
             let article = draft_data.article;
             if draft_data.delete {
-                let mut private = draftify(&username, &title);
+                let mut private = draftify(&user.username, &title);
                 private.push_str(".md");
-                data
-                    .client
-                    .execute(
-                        "delete from drafts where path = $2 and title = $1 and author = $3",
-                        &[&title, &private, &uid],
-                    )
+                data.repo.delete_draft(user.id, &private, &title).await?;
+                Event::new(Some(&user), Action::DeleteDraft)
+                    .target(&private)
+                    .before(&title)
+                    .record(&data, &req)
                     .await?;
             } else {
-                let mut private = draftify(&username, &title);
+                let mut private = draftify(&user.username, &title);
                 private.push_str(".md");
-                let existing = data
-                    .client
-                    .query(
-                        "select * from drafts where path = $2 and title != $1 and author = $3",
-                        &[&title, &private, &uid],
-                    )
-                    .await?;
-                if !existing.is_empty() {
+                // titles which only differ in punctuation end up in the same file
+                let existing = data.repo.draft_by_path(user.id, &private).await?;
+                let taken = existing
+                    .and_then(|found| found.title)
+                    .map(|existing| existing != title)
+                    .unwrap_or(false);
+                if taken {
                     let mut body = fs::read_to_string("private/exists.html").await?;
                     template::search_replace_recursive(
-                        &identity,
-                        &data.client,
-                        &data.lang[&lang],
+                        &Context::new(&req, &identity),
+                        &data,
+                        &lang,
                         &mut body,
                         &[format!("article {}", title)],
                     )
@@ -221,37 +422,15 @@ pub async fn save<'a>(
                     .expect("`draftify()` didn't return a proper path");
                 fs::create_dir_all(directory).await?;
                 fs::write(&private, article).await?;
-                let existing = data
-                    .client
-                    .query_opt(
-                        "select id from drafts where path = $1",
-                        &[&private],
-                    )
-                    .await?;
-                if let Some(row) = existing {
-                    let id = row.get::<_, i32>("id");
-                    data.client
-                        .execute(
-                            "update drafts set title = $1 where id = $2",
-                            &[&title, &id],
-                        )
-                        .await?;
-                } else {
-                    data.client
-                        .execute(
-                            "insert into drafts (path, title, author) values ($1, $2, $3)",
-                            &[&private, &title, &uid],
-                        )
-                        .await?;
-                }
+                data.repo.save_draft(user.id, &private, &title).await?;
             }
             Ok(HttpResponse::Ok().finish())
         } else {
             let mut body = fs::read_to_string("private/forbidden.html").await?;
             template::search_replace_recursive(
-                &identity,
-                &data.client,
-                &data.lang[&lang],
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
                 &mut body,
                 &[],
             )
@@ -261,9 +440,9 @@ pub async fn save<'a>(
     } else {
         let mut body = fs::read_to_string("private/forbidden.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -275,17 +454,12 @@ pub async fn save<'a>(
 #[post("/api/setadmin")]
 pub async fn api_setadmin<'a>(
     admin_data: web::Form<SetAdminData>,
-    _req: HttpRequest,
+    req: HttpRequest,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    if let Some(username) = identity.identity() {
-        let admin = data.client.query_opt(
-            "select id from admins where uid = \
-             (select id as uid from users where username = $1)",
-            &[&username]
-        ).await?;
-        if admin.is_none() {
+    if let Some(user) = data.api_user(&req, &identity, Some(Scope::Users)).await? {
+        if !user.admin {
             let body = json!({
                 "success": false,
                 "reason": "forbidden"
@@ -297,31 +471,28 @@ pub async fn api_setadmin<'a>(
                .body(body));
         }
 
-        if admin_data.value {
-            let existing = data
-                .client
-                .query_opt("select id from admins where uid = $1", &[&admin_data.uid])
-                .await?;
-            if existing.is_some() {
-                let body = json!({
-                    "success": false,
-                    "reason": "bad request"
-                });
-                let body = serde_json::to_string(&body).unwrap();
-
-                return Ok(HttpResponse::BadRequest()
-                   .header(http::header::CONTENT_TYPE, "application/json")
-                   .body(body));
+        let before = data.repo.user(admin_data.uid).await?.map(|user| user.admin);
+        let changed = data.repo.set_admin(admin_data.uid, admin_data.value).await?;
+        if changed {
+            let mut event = Event::new(Some(&user), Action::SetAdmin)
+                .target(format!("user {}", admin_data.uid))
+                .after(admin_data.value);
+            if let Some(before) = before {
+                event = event.before(before);
             }
-            data
-                .client
-                .execute("insert into admins (uid) values ($1)", &[&admin_data.uid])
-                .await?;
-        } else {
-            data
-                .client
-                .execute("delete from admins where uid = $1", &[&admin_data.uid])
-                .await?;
+            event.record(&data, &req).await?;
+        }
+        // making someone an admin twice is a mistake, removing them twice isn't
+        if !changed && admin_data.value {
+            let body = json!({
+                "success": false,
+                "reason": "bad request"
+            });
+            let body = serde_json::to_string(&body).unwrap();
+
+            return Ok(HttpResponse::BadRequest()
+               .header(http::header::CONTENT_TYPE, "application/json")
+               .body(body));
         }
 
         let body = json!({
@@ -348,17 +519,12 @@ pub async fn api_setadmin<'a>(
 #[post("/api/setemployee")]
 pub async fn api_setemployee<'a>(
     employee_data: web::Form<SetEmployeeData>,
-    _req: HttpRequest,
+    req: HttpRequest,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    if let Some(username) = identity.identity() {
-        let admin = data.client.query_opt(
-            "select id from admins where uid = \
-             (select id as uid from users where username = $1)",
-            &[&username]
-        ).await?;
-        if admin.is_none() {
+    if let Some(user) = data.api_user(&req, &identity, Some(Scope::Users)).await? {
+        if !user.admin {
             let body = json!({
                 "success": false,
                 "reason": "forbidden"
@@ -370,31 +536,34 @@ pub async fn api_setemployee<'a>(
                .body(body));
         }
 
-        if employee_data.value {
-            let existing = data
-                .client
-                .query_opt("select id from employees where uid = $1", &[&employee_data.uid])
-                .await?;
-            if existing.is_some() {
-                let body = json!({
-                    "success": false,
-                    "reason": "bad request"
-                });
-                let body = serde_json::to_string(&body).unwrap();
-
-                return Ok(HttpResponse::BadRequest()
-                   .header(http::header::CONTENT_TYPE, "application/json")
-                   .body(body));
+        let before = data
+            .repo
+            .user(employee_data.uid)
+            .await?
+            .map(|user| user.employee);
+        let changed = data
+            .repo
+            .set_employee(employee_data.uid, employee_data.value)
+            .await?;
+        if changed {
+            let mut event = Event::new(Some(&user), Action::SetEmployee)
+                .target(format!("user {}", employee_data.uid))
+                .after(employee_data.value);
+            if let Some(before) = before {
+                event = event.before(before);
             }
-            data
-                .client
-                .execute("insert into employees (uid) values ($1)", &[&employee_data.uid])
-                .await?;
-        } else {
-            data
-                .client
-                .execute("delete from employees where uid = $1", &[&employee_data.uid])
-                .await?;
+            event.record(&data, &req).await?;
+        }
+        if !changed && employee_data.value {
+            let body = json!({
+                "success": false,
+                "reason": "bad request"
+            });
+            let body = serde_json::to_string(&body).unwrap();
+
+            return Ok(HttpResponse::BadRequest()
+               .header(http::header::CONTENT_TYPE, "application/json")
+               .body(body));
         }
 
         let body = json!({
@@ -422,31 +591,19 @@ pub async fn api_setemployee<'a>(
 pub async fn api_draft<'a>(
     draft_data: web::Query<ApiDraftData>,
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    if let Some(username) = identity.identity() {
-        let uid = data
-            .client
-            .query_one(
-                "select users.id as uid from users where username = $1",
-                &[&username],
-            )
-            .await?;
-        let uid = uid.get::<_, i32>("uid");
-        let article = data
-            .client
-            .query_one(
-                "select path, title from drafts where id = $1 and author = $2",
-                &[&draft_data.id, &uid],
-                )
-            .await?;
-        let content = fs::read_to_string(article.get::<_, &str>("path")).await?;
-        let title = article.get::<_, &str>("title");
+    let lang = data.language(&lang);
+    if let Some(user) = data.api_user(&req, &identity, Some(Scope::Drafts)).await? {
+        let stored = data
+            .repo
+            .draft(draft_data.id, user.id)
+            .await?
+            .ok_or_else(|| Error::ResourceNotFound(format!("draft {}", draft_data.id)))?;
+        let content = fs::read_to_string(&stored.path).await?;
+        let title = stored.title.unwrap_or_default();
         let body = json!({
             "content": content,
             "title": title
@@ -458,9 +615,9 @@ pub async fn api_draft<'a>(
     } else {
         let mut body = fs::read_to_string("private/forbidden.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -473,67 +630,69 @@ pub async fn api_draft<'a>(
 pub async fn new<'a>(
     auth_data: web::Form<ArticleData>,
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    if let Some(username) = identity.identity() {
-        let uid = data
-            .client
-            .query_one(
-                "select users.id as uid from users where username = $1",
-                &[&username],
-            )
-            .await?;
-        let uid = uid.get::<_, i32>("uid");
-        let user = data
-            .client
-            .query_opt(
-                "select employees.id from employees where employees.uid = $1",
-                &[&uid],
-            )
-            .await?;
-        if user.is_some() {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if user.employee {
             let auth_data = auth_data.into_inner();
             let title = auth_data.title;
             let article = auth_data.article;
+            let article_lang = if data.lang.contains(&auth_data.lang) {
+                Some(auth_data.lang)
+            } else {
+                None
+            };
+            let translation_of = auth_data.translation_of.parse::<i32>().ok();
             let (mut public, mut private) = pathify(&title);
             public.push_str(".md");
             private.push_str(".md");
-            let existing = data
-                .client
-                .query(
-                    "select * from articles where title = $1 or path = $2",
-                    &[&title, &private],
-                )
-                .await?;
-            if !existing.is_empty() {
+            let existing = if data.repo.article_by_title(&title).await?.is_some()
+                || data.repo.article_by_path(&public).await?.is_some()
+            {
+                Some(format!("article {}", title))
+            } else {
+                match (translation_of, &article_lang) {
+                    (Some(id), Some(code)) if translated(&data, id, code).await? => {
+                        Some(format!("translation {} of article {}", code, id))
+                    }
+                    _ => None,
+                }
+            };
+            if let Some(existing) = existing {
                 let mut body = fs::read_to_string("private/exists.html").await?;
                 template::search_replace_recursive(
-                    &identity,
-                    &data.client,
-                    &data.lang[&lang],
+                    &Context::new(&req, &identity),
+                    &data,
+                    &lang,
                     &mut body,
-                    &[format!("article {}", title)],
+                    &[existing],
                 )
                 .await?;
                 return Ok(HttpResponse::BadRequest().body(body));
             }
             fs::write(&private, article).await?;
-            data.client.execute("insert into articles (path, title, cdate, author) values ($1, $2, current_date, $3)", &[&public, &title, &uid]).await?;
+            data.repo
+                .create_article(NewArticle {
+                    path: public.clone(),
+                    title: title.clone(),
+                    author: user.id,
+                    date: None,
+                    lang: article_lang,
+                    translation_of,
+                })
+                .await?;
+            Event::new(Some(&user), Action::Publish)
+                .target(&public)
+                .after(&title)
+                .record(&data, &req)
+                .await?;
 
-            let mut draft_path = draftify(&username, &title);
+            let mut draft_path = draftify(&user.username, &title);
             draft_path.push_str(".md");
-            data
-                .client
-                .execute(
-                    "delete from drafts where title = $1 and path = $2 and author = $3",
-                    &[&title, &draft_path, &uid],
-                )
-                .await?;
+            data.repo.delete_draft(user.id, &draft_path, &title).await?;
 
             Ok(HttpResponse::SeeOther()
                 .header("Location", format!("/{}", public))
@@ -541,9 +700,9 @@ pub async fn new<'a>(
         } else {
             let mut body = fs::read_to_string("private/forbidden.html").await?;
             template::search_replace_recursive(
-                &identity,
-                &data.client,
-                &data.lang[&lang],
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
                 &mut body,
                 &[],
             )
@@ -553,9 +712,9 @@ pub async fn new<'a>(
     } else {
         let mut body = fs::read_to_string("private/forbidden.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -567,28 +726,18 @@ pub async fn new<'a>(
 #[get("/account/editor.html")]
 pub async fn editor<'a>(
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    if let Some(username) = identity.identity() {
-        let user = data
-            .client
-            .query_opt(
-                "select employees.id from employees where employees.uid = \
-             (select users.id as uid from users where username = $1)",
-                &[&username],
-            )
-            .await?;
-        if user.is_some() {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if user.employee {
             let mut body = fs::read_to_string("public/account/editor.html").await?;
             template::search_replace_recursive(
-                &identity,
-                &data.client,
-                &data.lang[&lang],
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
                 &mut body,
                 &[],
             )
@@ -599,9 +748,9 @@ pub async fn editor<'a>(
         } else {
             let mut body = fs::read_to_string("private/forbidden.html").await?;
             template::search_replace_recursive(
-                &identity,
-                &data.client,
-                &data.lang[&lang],
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
                 &mut body,
                 &[],
             )
@@ -611,9 +760,9 @@ pub async fn editor<'a>(
     } else {
         let mut body = fs::read_to_string("private/forbidden.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -622,55 +771,91 @@ pub async fn editor<'a>(
     }
 }
 
-#[get("/account/draft/{draft}.md")]
-pub async fn draft<'a>(
+#[get("/account/translate/{id}.html")]
+pub async fn translate<'a>(
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
-    info: web::Path<String>,
+    info: web::Path<i32>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    if let Some(username) = identity.identity() {
-        let uid = data
-            .client
-            .query_one(
-                "select users.id as uid from users where username = $1",
-                &[&username],
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if user.employee {
+            let id = info.into_inner();
+            let article = data
+                .repo
+                .article(id)
+                .await?
+                .ok_or_else(|| Error::ResourceNotFound(format!("article {}", id)))?;
+            let path = PublicPath::try_from(article.path.as_str())?;
+            let text = fs::read_to_string(&path).await?;
+            // the translation gets its own front matter
+            // and its own title, `new` rejects titles which are already taken
+            let (_, content) = frontmatter::split(&text)?;
+            let mut body = fs::read_to_string("public/account/editor.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[content.to_string(), String::new(), id.to_string()],
             )
             .await?;
-        let uid = uid.get::<_, i32>("uid");
-        let user = data
-            .client
-            .query_opt(
-                "select employees.id from employees where employees.uid = $1",
-                &[&uid],
+            Ok(HttpResponse::Ok()
+                .header(http::header::CONTENT_TYPE, "text/html")
+                .body(body))
+        } else {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
             )
             .await?;
-        if user.is_some() {
-            let path = format!("private/{}/drafts/{}.md", username, info);
-            let existing = data
-                .client
-                .query_opt(
-                    "select title from drafts where author = $1 and path = $2",
-                    &[&uid, &path],
-                )
-                .await?;
+            Ok(HttpResponse::Forbidden().body(body))
+        }
+    } else {
+        let mut body = fs::read_to_string("private/forbidden.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[],
+        )
+        .await?;
+        Ok(HttpResponse::Forbidden().body(body))
+    }
+}
+
+#[get("/account/draft/{draft}.md")]
+pub async fn draft<'a>(
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<String>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if user.employee {
+            let path = format!("private/{}/drafts/{}.md", user.username, info);
+            let existing = data.repo.draft_by_path(user.id, &path).await?;
             if let Some(existing) = existing {
                 let mut body = fs::read_to_string("public/account/editor.html").await?;
                 let content = fs::read_to_string(path).await?;
-                let title = existing.get::<_, Option<&str>>("title");
-                let args = if let Some(title) = title {
-                    vec![content, title.to_string()]
+                let args = if let Some(title) = existing.title {
+                    vec![content, title]
                 } else {
                     vec![content]
                 };
                 template::search_replace_recursive(
-                    &identity,
-                    &data.client,
-                    &data.lang[&lang],
+                    &Context::new(&req, &identity),
+                    &data,
+                    &lang,
                     &mut body,
                     &args,
                 )
@@ -686,9 +871,9 @@ pub async fn draft<'a>(
         } else {
             let mut body = fs::read_to_string("private/forbidden.html").await?;
             template::search_replace_recursive(
-                &identity,
-                &data.client,
-                &data.lang[&lang],
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
                 &mut body,
                 &[],
             )
@@ -698,9 +883,9 @@ pub async fn draft<'a>(
     } else {
         let mut body = fs::read_to_string("private/forbidden.html").await?;
         template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
             &mut body,
             &[],
         )
@@ -711,14 +896,11 @@ pub async fn draft<'a>(
 
 #[get("/account/{script}.wasm")]
 pub async fn wasm<'a>(
-    _req: HttpRequest,
+    req: HttpRequest,
     _identity: Identity,
-    _data: web::Data<ServerData<'a>>,
+    data: web::Data<ServerData<'a>>,
     info: web::Path<String>,
 ) -> Result<impl Responder> {
-    let path = format!("public/frontend/{}.wasm", info);
-    let script = fs::read(path).await?;
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "application/wasm")
-        .body(script))
+    let path = PublicPath::try_from(format!("frontend/{}.wasm", info))?;
+    assets::serve(&req, &data, &path).await
 }
@@ -0,0 +1,707 @@
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::fmt::{self, Display};
+use std::io::ErrorKind;
+use std::time::UNIX_EPOCH;
+
+use actix_identity::Identity;
+use actix_web::http::{header, StatusCode};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse, ResponseError};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::fs;
+
+use crate::account;
+use crate::audit::{Action, Event};
+use crate::error::Error;
+use crate::path::PublicPath;
+use crate::repo::{Article, ArticleUpdate, NewArticle, User};
+use crate::template;
+use crate::token::Scope;
+use crate::web::ServerData;
+
+const PER_PAGE: usize = 20;
+const MAX_PER_PAGE: usize = 100;
+
+// every error of the API is answered with
+// `{"error": {"status": 404, "message": "..."}}`
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new<S: Into<String>>(status: StatusCode, message: S) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn bad_request<S: Into<String>>(message: S) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    fn not_found(id: &str) -> Self {
+        Self::new(StatusCode::NOT_FOUND, format!("article {:?} doesn't exist", id))
+    }
+}
+
+impl Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.status, self.message)
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(err: Error) -> ApiError {
+        let status = err.status_code();
+        if status.is_server_error() {
+            // the details are for the log, not for the client
+            eprintln!("API error: {}", err);
+            ApiError::new(status, "internal server error")
+        } else {
+            ApiError::new(status, err.to_string())
+        }
+    }
+}
+
+impl From<std::io::Error> for ApiError {
+    fn from(err: std::io::Error) -> ApiError {
+        Error::from(err).into()
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let body = json!({
+            "error": {
+                "status": self.status.as_u16(),
+                "message": self.message,
+            }
+        });
+        HttpResponse::build(self.status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+    }
+}
+
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
+
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct ListData {
+    // only articles of this user
+    author: Option<i32>,
+    lang: Option<String>,
+    tag: Option<String>,
+    // only articles with this in their title, ignoring case
+    q: Option<String>,
+    // `date`, `modified`, `title` or `id`, descending with a leading `-`
+    sort: String,
+    // starts at 1
+    page: usize,
+    per_page: usize,
+}
+
+impl Default for ListData {
+    fn default() -> Self {
+        Self {
+            author: None,
+            lang: None,
+            tag: None,
+            q: None,
+            sort: "-date".to_string(),
+            page: 1,
+            per_page: PER_PAGE,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateData {
+    title: String,
+    markdown: String,
+    #[serde(default)]
+    lang: Option<String>,
+    #[serde(default)]
+    translation_of: Option<i32>,
+}
+
+// fields which are left out keep their current value
+#[derive(Debug, Deserialize)]
+pub struct UpdateData {
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    markdown: Option<String>,
+    #[serde(default)]
+    lang: Option<String>,
+}
+
+fn json<T: DeserializeOwned>(body: &[u8]) -> ApiResult<T> {
+    serde_json::from_slice(body)
+        .map_err(|err| ApiError::bad_request(format!("invalid request body: {}", err)))
+}
+
+fn respond(status: StatusCode, body: Value) -> HttpResponse {
+    HttpResponse::build(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(body.to_string())
+}
+
+fn article_json(article: &Article) -> Value {
+    let modified = article
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    json!({
+        "id": article.id,
+        "path": article.path,
+        "url": template::href(article),
+        "title": article.title,
+        "author": article.author,
+        "date": article.date,
+        "modified": modified,
+        "summary": article.summary,
+        "tags": article.tags,
+        "lang": article.lang,
+        "cover": article.cover,
+        "slug": article.slug,
+        "translation_of": article.translation_of,
+    })
+}
+
+// the same check as `account::new`, only employees may publish articles
+// tokens need the `articles` scope
+async fn employee(
+    data: &ServerData<'_>,
+    req: &HttpRequest,
+    identity: &Identity,
+) -> ApiResult<User> {
+    match data.api_user(req, identity, Some(Scope::Articles)).await? {
+        Some(user) if user.employee => Ok(user),
+        Some(_) => Err(Error::AuthorizationFailed.into()),
+        None => Err(Error::AuthenticationFailed.into()),
+    }
+}
+
+// employees may only change their own articles, admins all of them
+async fn editor(
+    data: &ServerData<'_>,
+    req: &HttpRequest,
+    identity: &Identity,
+    article: &Article,
+) -> ApiResult<User> {
+    let user = employee(data, req, identity).await?;
+    if article.author == Some(user.id) || user.admin {
+        Ok(user)
+    } else {
+        Err(Error::AuthorizationFailed.into())
+    }
+}
+
+async fn find(data: &ServerData<'_>, id: &str) -> ApiResult<Article> {
+    let parsed = id.parse::<i32>().map_err(|_| ApiError::not_found(id))?;
+    data.repo
+        .article(parsed)
+        .await?
+        .ok_or_else(|| ApiError::not_found(id))
+}
+
+fn check_lang(data: &ServerData<'_>, lang: &Option<String>) -> ApiResult<()> {
+    match lang {
+        Some(code) if !data.lang.contains(code) => Err(ApiError::bad_request(format!(
+            "language {:?} doesn't exist",
+            code
+        ))),
+        _ => Ok(()),
+    }
+}
+
+fn sort(articles: &mut [Article], sort: &str) -> ApiResult<()> {
+    let (descending, key) = match sort.strip_prefix('-') {
+        Some(key) => (true, key),
+        None => (false, sort),
+    };
+    let compare: fn(&Article, &Article) -> Ordering = match key {
+        "date" => |a, b| a.date.cmp(&b.date),
+        "modified" => |a, b| a.modified.cmp(&b.modified),
+        "title" => |a, b| a.title.to_lowercase().cmp(&b.title.to_lowercase()),
+        "id" => |_, _| Ordering::Equal,
+        _ => return Err(ApiError::bad_request(format!("can't sort by {:?}", key))),
+    };
+    // articles which compare equal keep the order of their ids
+    articles.sort_by(|a, b| compare(a, b).then(a.id.cmp(&b.id)));
+    if descending {
+        articles.reverse();
+    }
+    Ok(())
+}
+
+#[get("/api/v1/articles")]
+pub async fn list<'a>(
+    req: HttpRequest,
+    data: web::Data<ServerData<'a>>,
+) -> ApiResult<HttpResponse> {
+    let query = web::Query::<ListData>::from_query(req.query_string())
+        .map_err(|err| ApiError::bad_request(format!("invalid query: {}", err)))?
+        .into_inner();
+    if query.page < 1 || query.per_page < 1 || query.per_page > MAX_PER_PAGE {
+        return Err(ApiError::bad_request(format!(
+            "`page` starts at 1 and `per_page` has to be between 1 and {}",
+            MAX_PER_PAGE
+        )));
+    }
+    let q = query.q.as_ref().map(|q| q.to_lowercase());
+    let mut articles = data
+        .repo
+        .articles()
+        .await?
+        .into_iter()
+        .filter(|article| query.author.is_none_or(|author| article.author == Some(author)))
+        .filter(|article| query.lang.is_none() || article.lang == query.lang)
+        .filter(|article| query.tag.as_ref().is_none_or(|tag| article.tags.contains(tag)))
+        .filter(|article| {
+            q.as_ref()
+                .is_none_or(|q| article.title.to_lowercase().contains(q))
+        })
+        .collect::<Vec<_>>();
+    sort(&mut articles, &query.sort)?;
+    let total = articles.len();
+    let page = articles
+        .iter()
+        .skip((query.page - 1) * query.per_page)
+        .take(query.per_page)
+        .map(article_json)
+        .collect::<Vec<_>>();
+    Ok(respond(
+        StatusCode::OK,
+        json!({
+            "articles": page,
+            "page": query.page,
+            "per_page": query.per_page,
+            "total": total,
+        }),
+    ))
+}
+
+#[get("/api/v1/articles/{id}")]
+pub async fn show<'a>(
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let article = find(&data, &info).await?;
+    let path = PublicPath::try_from(article.path.as_str())?;
+    let markdown = fs::read_to_string(&path).await?;
+    let (_, html) = template::markdown(&markdown)?;
+    let mut body = article_json(&article);
+    body["markdown"] = markdown.into();
+    body["html"] = html.into();
+    Ok(respond(StatusCode::OK, body))
+}
+
+#[post("/api/v1/articles")]
+pub async fn create<'a>(
+    body: web::Bytes,
+    req: HttpRequest,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> ApiResult<HttpResponse> {
+    let user = employee(&data, &req, &identity).await?;
+    let create = json::<CreateData>(&body)?;
+    let title = create.title.trim().to_string();
+    if title.is_empty() {
+        return Err(ApiError::bad_request("the title is empty"));
+    }
+    check_lang(&data, &create.lang)?;
+    if let Some(id) = create.translation_of {
+        if data.repo.article(id).await?.is_none() {
+            return Err(ApiError::bad_request(format!(
+                "translated article {} doesn't exist",
+                id
+            )));
+        }
+        if let Some(code) = &create.lang {
+            if account::translated(&data, id, code).await? {
+                return Err(ApiError::new(
+                    StatusCode::CONFLICT,
+                    format!("article {} is already translated into {:?}", id, code),
+                ));
+            }
+        }
+    }
+    let (mut public, mut private) = account::pathify(&title);
+    public.push_str(".md");
+    private.push_str(".md");
+    let existing = data.repo.article_by_title(&title).await?.is_some()
+        || data.repo.article_by_path(&public).await?.is_some();
+    if existing {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("an article titled {:?} already exists", title),
+        ));
+    }
+    fs::write(&private, create.markdown).await?;
+    let id = data
+        .repo
+        .create_article(NewArticle {
+            path: public,
+            title,
+            author: user.id,
+            date: None,
+            lang: create.lang,
+            translation_of: create.translation_of,
+        })
+        .await?;
+    let article = data
+        .repo
+        .article(id)
+        .await?
+        .ok_or_else(|| ApiError::not_found(&id.to_string()))?;
+    Event::new(Some(&user), Action::CreateArticle)
+        .target(&article.path)
+        .after(&article.title)
+        .record(&data, &req)
+        .await?;
+    Ok(HttpResponse::Created()
+        .header(header::LOCATION, format!("/api/v1/articles/{}", id))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(article_json(&article).to_string()))
+}
+
+#[put("/api/v1/articles/{id}")]
+pub async fn update<'a>(
+    body: web::Bytes,
+    req: HttpRequest,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let article = find(&data, &info).await?;
+    let user = editor(&data, &req, &identity, &article).await?;
+    let update = json::<UpdateData>(&body)?;
+    let title = update.title.map(|title| title.trim().to_string());
+    if let Some(title) = &title {
+        if title.is_empty() {
+            return Err(ApiError::bad_request("the title is empty"));
+        }
+        let taken = data
+            .repo
+            .article_by_title(title)
+            .await?
+            .is_some_and(|other| other.id != article.id);
+        if taken {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("an article titled {:?} already exists", title),
+            ));
+        }
+    }
+    check_lang(&data, &update.lang)?;
+    // the path stays the same, links to the article keep working
+    if let Some(markdown) = update.markdown {
+        let path = PublicPath::try_from(article.path.as_str())?;
+        fs::write(&path, markdown).await?;
+    }
+    let mut event = Event::new(Some(&user), Action::UpdateArticle).target(&article.path);
+    if let Some(title) = &title {
+        event = event.before(&article.title).after(title);
+    }
+    data.repo
+        .update_article(
+            article.id,
+            ArticleUpdate {
+                title,
+                lang: update.lang,
+            },
+        )
+        .await?;
+    event.record(&data, &req).await?;
+    let article = find(&data, &info).await?;
+    Ok(respond(StatusCode::OK, article_json(&article)))
+}
+
+#[delete("/api/v1/articles/{id}")]
+pub async fn remove<'a>(
+    req: HttpRequest,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<String>,
+) -> ApiResult<HttpResponse> {
+    let article = find(&data, &info).await?;
+    let user = editor(&data, &req, &identity, &article).await?;
+    let path = PublicPath::try_from(article.path.as_str())?;
+    match fs::remove_file(&path).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+        _ => {}
+    }
+    data.repo.delete_article(article.id).await?;
+    Event::new(Some(&user), Action::DeleteArticle)
+        .target(&article.path)
+        .before(&article.title)
+        .record(&data, &req)
+        .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/api/v1/openapi.json")]
+pub async fn openapi() -> HttpResponse {
+    respond(StatusCode::OK, spec())
+}
+
+// describes the endpoints above, keep it in sync with them
+fn spec() -> Value {
+    let error = json!({
+        "description": "see the message",
+        "content": {
+            "application/json": {
+                "schema": { "$ref": "#/components/schemas/Error" }
+            }
+        }
+    });
+    let id = json!({
+        "name": "id",
+        "in": "path",
+        "required": true,
+        "schema": { "type": "integer" }
+    });
+    let article = |description: &str| {
+        json!({
+            "description": description,
+            "content": {
+                "application/json": {
+                    "schema": { "$ref": "#/components/schemas/Article" }
+                }
+            }
+        })
+    };
+    let query = |name: &str, schema: Value, description: &str| {
+        json!({
+            "name": name,
+            "in": "query",
+            "required": false,
+            "schema": schema,
+            "description": description
+        })
+    };
+    // writing needs a token with the `articles` scope or the identity cookie
+    let security = json!([{ "token": [] }, { "cookie": [] }]);
+    let paths = json!({
+        "/api/v1/articles": {
+            "get": {
+                "summary": "list articles",
+                "parameters": [
+                    query("author", json!({ "type": "integer" }), "id of the author"),
+                    query("lang", json!({ "type": "string" }), "language code"),
+                    query("tag", json!({ "type": "string" }), "tag from the front matter"),
+                    query("q", json!({ "type": "string" }), "part of the title, ignoring case"),
+                    query(
+                        "sort",
+                        json!({
+                            "type": "string",
+                            "enum": [
+                                "date", "-date", "modified", "-modified",
+                                "title", "-title", "id", "-id"
+                            ],
+                            "default": "-date"
+                        }),
+                        "a leading `-` sorts in descending order"
+                    ),
+                    query(
+                        "page",
+                        json!({ "type": "integer", "minimum": 1, "default": 1 }),
+                        "page number"
+                    ),
+                    query(
+                        "per_page",
+                        json!({
+                            "type": "integer",
+                            "minimum": 1,
+                            "maximum": MAX_PER_PAGE,
+                            "default": PER_PAGE
+                        }),
+                        "articles per page"
+                    )
+                ],
+                "responses": {
+                    "200": {
+                        "description": "one page of articles",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/ArticleList" }
+                            }
+                        }
+                    },
+                    "400": error
+                }
+            },
+            "post": {
+                "summary": "publish an article, only for employees",
+                "security": security,
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/NewArticle" }
+                        }
+                    }
+                },
+                "responses": {
+                    "201": article("the published article, its URL is in `Location`"),
+                    "400": error,
+                    "401": error,
+                    "403": error,
+                    "409": error
+                }
+            }
+        },
+        "/api/v1/articles/{id}": {
+            "parameters": [id],
+            "get": {
+                "summary": "an article with its Markdown and HTML",
+                "responses": {
+                    "200": {
+                        "description": "the article",
+                        "content": {
+                            "application/json": {
+                                "schema": { "$ref": "#/components/schemas/ArticleContent" }
+                            }
+                        }
+                    },
+                    "404": error
+                }
+            },
+            "put": {
+                "summary": "change an article, only for its author and admins",
+                "security": security,
+                "requestBody": {
+                    "required": true,
+                    "content": {
+                        "application/json": {
+                            "schema": { "$ref": "#/components/schemas/ArticleUpdate" }
+                        }
+                    }
+                },
+                "responses": {
+                    "200": article("the changed article"),
+                    "400": error,
+                    "401": error,
+                    "403": error,
+                    "404": error,
+                    "409": error
+                }
+            },
+            "delete": {
+                "summary": "delete an article, only for its author and admins",
+                "security": security,
+                "responses": {
+                    "204": { "description": "the article was deleted" },
+                    "401": error,
+                    "403": error,
+                    "404": error
+                }
+            }
+        }
+    });
+    let schemas = json!({
+        "Article": {
+            "type": "object",
+            "properties": {
+                "id": { "type": "integer" },
+                "path": { "type": "string" },
+                "url": { "type": "string" },
+                "title": { "type": "string" },
+                "author": {
+                    "type": "integer",
+                    "nullable": true,
+                    "description": "null once the author deleted their account"
+                },
+                "date": { "type": "string", "format": "date" },
+                "modified": { "type": "integer", "description": "seconds since the epoch" },
+                "summary": { "type": "string", "nullable": true },
+                "tags": { "type": "array", "items": { "type": "string" } },
+                "lang": { "type": "string", "nullable": true },
+                "cover": { "type": "string", "nullable": true },
+                "slug": { "type": "string", "nullable": true },
+                "translation_of": { "type": "integer", "nullable": true }
+            }
+        },
+        "ArticleContent": {
+            "allOf": [
+                { "$ref": "#/components/schemas/Article" },
+                {
+                    "type": "object",
+                    "properties": {
+                        "markdown": { "type": "string", "description": "with its front matter" },
+                        "html": { "type": "string" }
+                    }
+                }
+            ]
+        },
+        "ArticleList": {
+            "type": "object",
+            "properties": {
+                "articles": {
+                    "type": "array",
+                    "items": { "$ref": "#/components/schemas/Article" }
+                },
+                "page": { "type": "integer" },
+                "per_page": { "type": "integer" },
+                "total": { "type": "integer" }
+            }
+        },
+        "NewArticle": {
+            "type": "object",
+            "required": ["title", "markdown"],
+            "properties": {
+                "title": { "type": "string" },
+                "markdown": { "type": "string" },
+                "lang": { "type": "string" },
+                "translation_of": { "type": "integer" }
+            }
+        },
+        "ArticleUpdate": {
+            "type": "object",
+            "properties": {
+                "title": { "type": "string" },
+                "markdown": { "type": "string" },
+                "lang": { "type": "string" }
+            }
+        },
+        "Error": {
+            "type": "object",
+            "properties": {
+                "error": {
+                    "type": "object",
+                    "properties": {
+                        "status": { "type": "integer" },
+                        "message": { "type": "string" }
+                    }
+                }
+            }
+        }
+    });
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "circus articles",
+            "version": "1"
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas,
+            "securitySchemes": {
+                "token": { "type": "http", "scheme": "bearer" },
+                "cookie": { "type": "apiKey", "in": "cookie", "name": "auth-cookie" }
+            }
+        }
+    })
+}
@@ -0,0 +1,158 @@
+use std::path::Path;
+
+use actix_web::http::header::{self, HttpDate};
+use actix_web::{HttpRequest, HttpResponse};
+use tokio::fs;
+
+use crate::cache::{self, Asset};
+use crate::compress::Encoding;
+use crate::config::AssetConfig;
+use crate::error::{Error, Result};
+use crate::path::PublicPath;
+use crate::web::ServerData;
+
+// files without a known extension are sent as `application/octet-stream`
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("css", "text/css"),
+    ("js", "text/javascript"),
+    ("wasm", "application/wasm"),
+    ("json", "application/json"),
+    ("txt", "text/plain"),
+    ("xml", "application/xml"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("svg", "image/svg+xml"),
+    ("webp", "image/webp"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("mp3", "audio/mpeg"),
+    ("ogg", "audio/ogg"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+pub fn mime_type(path: &Path) -> &'static str {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| {
+            MIME_TYPES
+                .iter()
+                .find(|(known, _)| known.eq_ignore_ascii_case(ext))
+                .map(|(_, mime)| *mime)
+        })
+        .unwrap_or("application/octet-stream")
+}
+
+// whether a file may be served as is
+// templates, articles and translations have to go through their handlers,
+// and hidden files like public/.git/ are never served
+pub fn servable(path: &Path, config: &AssetConfig) -> bool {
+    let hidden = path.components().any(|component| {
+        component
+            .as_os_str()
+            .to_str()
+            .map(|name| name.starts_with('.') && name != "." && name != "..")
+            .unwrap_or(true)
+    });
+    let allowed = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| config.extensions.iter().any(|allowed| allowed.eq_ignore_ascii_case(ext)))
+        .unwrap_or(false);
+    !hidden && allowed
+}
+
+// resolves symlinks and makes sure the file is still in public/
+pub async fn confine(path: &PublicPath<'_>) -> Result<()> {
+    let root = fs::canonicalize(PublicPath::new()).await?;
+    let file = fs::canonicalize(path).await?;
+    if file.starts_with(&root) {
+        Ok(())
+    } else {
+        Err(Error::IllegalResource(path.to_string()))
+    }
+}
+
+enum Range {
+    Full,
+    Partial(usize, usize),
+    Unsatisfiable,
+}
+
+// a single byte range, `bytes=start-end`, `bytes=start-` or `bytes=-suffix`
+// several ranges at once are answered with the whole file
+fn parse_range(header: &str, len: usize) -> Range {
+    let spec = match header.trim().splitn(2, '=').collect::<Vec<_>>()[..] {
+        ["bytes", spec] if !spec.contains(',') => spec.trim(),
+        _ => return Range::Full,
+    };
+    let mut bounds = spec.splitn(2, '-');
+    let (start, end) = match (bounds.next(), bounds.next()) {
+        (Some(start), Some(end)) => (start.trim(), end.trim()),
+        _ => return Range::Full,
+    };
+    let (start, end) = match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+        (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        _ => return Range::Full,
+    };
+    if len == 0 || start >= len {
+        Range::Unsatisfiable
+    } else {
+        Range::Partial(start, end)
+    }
+}
+
+// sends a file from public/, honoring `Range` requests
+pub async fn serve(
+    req: &HttpRequest,
+    data: &ServerData<'_>,
+    path: &PublicPath<'_>,
+) -> Result<HttpResponse> {
+    if !servable(path, &data.config.assets) {
+        return Err(Error::IllegalResource(path.to_string()));
+    }
+    confine(path).await?;
+    let asset = data.assets.get(path).await?;
+    let content_type = mime_type(path);
+    let headers = req.headers();
+    // `If-Range` only allows a partial response if the file didn't change
+    let current = headers
+        .get(header::IF_RANGE)
+        .and_then(|tag| tag.to_str().ok())
+        .map(|tag| tag == asset.etag(Encoding::Identity))
+        .unwrap_or(true);
+    let range = match headers.get(header::RANGE).and_then(|range| range.to_str().ok()) {
+        Some(header) if current => parse_range(header, asset.body.len()),
+        _ => Range::Full,
+    };
+    match range {
+        Range::Full => Ok(cache::asset(req, &asset, content_type)),
+        Range::Partial(start, end) => Ok(partial(&asset, content_type, start, end)),
+        Range::Unsatisfiable => Ok(HttpResponse::RangeNotSatisfiable()
+            .header(header::CONTENT_RANGE, format!("bytes */{}", asset.body.len()))
+            .finish()),
+    }
+}
+
+fn partial(asset: &Asset, content_type: &str, start: usize, end: usize) -> HttpResponse {
+    HttpResponse::PartialContent()
+        .header(header::ETAG, asset.etag(Encoding::Identity))
+        .header(header::LAST_MODIFIED, HttpDate::from(asset.modified).to_string())
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(
+            header::CONTENT_RANGE,
+            format!("bytes {}-{}/{}", start, end, asset.body.len()),
+        )
+        .header(header::CONTENT_TYPE, content_type)
+        .body(asset.body.slice(start..=end))
+}
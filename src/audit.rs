@@ -0,0 +1,565 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_identity::Identity;
+use actix_web::http::HeaderMap;
+use actix_web::{get, http, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::fs;
+
+use crate::error::{Error, Result};
+use crate::i18n::Lang;
+use crate::repo::{self, AuditEntry, AuditFilter, NewAuditEntry, User};
+use crate::template::{self, escape, Context};
+use crate::token::Scope;
+use crate::web::ServerData;
+
+const PER_PAGE: usize = 50;
+
+const DAY: u64 = 24 * 60 * 60;
+
+// what the audit log records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Login,
+    LoginFailed,
+    Logout,
+    ChangeEmail,
+    ChangePassword,
+    UpdateProfile,
+    // a password chosen after an admin asked for it
+    ResetPassword,
+    SetAdmin,
+    SetEmployee,
+    // through the editor
+    Publish,
+    // through the API
+    CreateArticle,
+    UpdateArticle,
+    DeleteArticle,
+    DeleteDraft,
+    CreateToken,
+    RevokeToken,
+    SaveTranslations,
+    AddLanguage,
+    // by admins, see `users`
+    UpdateUser,
+    SetStatus,
+    ForcePasswordReset,
+    DeleteUser,
+    // by users about their own account, see `gdpr`
+    ExportData,
+    RequestDeletion,
+    CancelDeletion,
+}
+
+impl Action {
+    pub const ALL: [Action; 25] = [
+        Action::Login,
+        Action::LoginFailed,
+        Action::Logout,
+        Action::ChangeEmail,
+        Action::ChangePassword,
+        Action::UpdateProfile,
+        Action::ResetPassword,
+        Action::SetAdmin,
+        Action::SetEmployee,
+        Action::Publish,
+        Action::CreateArticle,
+        Action::UpdateArticle,
+        Action::DeleteArticle,
+        Action::DeleteDraft,
+        Action::CreateToken,
+        Action::RevokeToken,
+        Action::SaveTranslations,
+        Action::AddLanguage,
+        Action::UpdateUser,
+        Action::SetStatus,
+        Action::ForcePasswordReset,
+        Action::DeleteUser,
+        Action::ExportData,
+        Action::RequestDeletion,
+        Action::CancelDeletion,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Login => "login",
+            Action::LoginFailed => "login_failed",
+            Action::Logout => "logout",
+            Action::ChangeEmail => "change_email",
+            Action::ChangePassword => "change_password",
+            Action::UpdateProfile => "update_profile",
+            Action::ResetPassword => "reset_password",
+            Action::SetAdmin => "set_admin",
+            Action::SetEmployee => "set_employee",
+            Action::Publish => "publish",
+            Action::CreateArticle => "create_article",
+            Action::UpdateArticle => "update_article",
+            Action::DeleteArticle => "delete_article",
+            Action::DeleteDraft => "delete_draft",
+            Action::CreateToken => "create_token",
+            Action::RevokeToken => "revoke_token",
+            Action::SaveTranslations => "save_translations",
+            Action::AddLanguage => "add_language",
+            Action::UpdateUser => "update_user",
+            Action::SetStatus => "set_status",
+            Action::ForcePasswordReset => "force_password_reset",
+            Action::DeleteUser => "delete_user",
+            Action::ExportData => "export_data",
+            Action::RequestDeletion => "request_deletion",
+            Action::CancelDeletion => "cancel_deletion",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|action| action.name() == name)
+    }
+}
+
+// an entry of the audit log before it is recorded
+pub(crate) struct Event(NewAuditEntry);
+
+impl Event {
+    pub(crate) fn new(actor: Option<&User>, action: Action) -> Self {
+        Self(NewAuditEntry {
+            actor: actor.map(|user| user.id),
+            action: action.name().to_string(),
+            target: None,
+            before: None,
+            after: None,
+            ip: None,
+        })
+    }
+
+    pub(crate) fn target<T: ToString>(mut self, target: T) -> Self {
+        self.0.target = Some(target.to_string());
+        self
+    }
+
+    pub(crate) fn before<T: ToString>(mut self, before: T) -> Self {
+        self.0.before = Some(before.to_string());
+        self
+    }
+
+    pub(crate) fn after<T: ToString>(mut self, after: T) -> Self {
+        self.0.after = Some(after.to_string());
+        self
+    }
+
+    // with the address of the client, see `client_ip`
+    pub(crate) async fn record(mut self, data: &ServerData<'_>, req: &HttpRequest) -> Result<()> {
+        self.0.ip = client_ip(req, &data.config.server.trusted_proxies).map(|ip| ip.to_string());
+        self.record_by_server(data).await
+    }
+
+    // for what the server does by itself, without a request
+    pub(crate) async fn record_by_server(self, data: &ServerData<'_>) -> Result<()> {
+        data.repo.audit(self.0).await
+    }
+}
+
+// the address the request came from, anyone can send forwarding headers, so they
+// only count when it is a trusted proxy
+// every proxy appends the address it got the request from, the last one which isn't
+// trusted is the client
+pub(crate) fn client_ip(req: &HttpRequest, trusted: &[IpAddr]) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    if !trusted.contains(&peer) {
+        return Some(peer);
+    }
+    let hops = forwarded(req.headers());
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        match hop {
+            Some(ip) if trusted.contains(ip) => client = *ip,
+            Some(ip) => return Some(*ip),
+            // obfuscated or malformed, the proxies before it can't be told apart
+            None => break,
+        }
+    }
+    Some(client)
+}
+
+// the hops of `Forwarded`, or of `X-Forwarded-For` if there is none, the client first
+fn forwarded(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let values = |name| {
+        headers
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    let forwarded = values(http::header::FORWARDED);
+    if !forwarded.is_empty() {
+        forwarded
+            .into_iter()
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| {
+                        let mut pair = pair.splitn(2, '=');
+                        Some((pair.next()?.trim(), pair.next()?.trim()))
+                    })
+                    .find(|(key, _)| key.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| node_ip(node))
+            })
+            .collect()
+    } else {
+        values(http::header::HeaderName::from_static("x-forwarded-for"))
+            .into_iter()
+            .map(node_ip)
+            .collect()
+    }
+}
+
+// `192.0.2.1`, `"192.0.2.1:4711"`, `"[2001:db8::1]:4711"` or `2001:db8::1`
+fn node_ip(node: &str) -> Option<IpAddr> {
+    let node = node.trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        let end = rest.find(']')?;
+        return rest[..end].parse::<Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    node.parse().ok().or_else(|| {
+        let (ip, _port) = node.split_at(node.rfind(':')?);
+        ip.parse().ok()
+    })
+}
+
+// the filter of the audit page and the exports, empty fields match everything
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditQuery {
+    // the username of the actor
+    actor: String,
+    action: String,
+    // a part of the target
+    target: String,
+    // `yyyy-mm-dd`, both days are included
+    since: String,
+    until: String,
+    // starts at 1
+    page: usize,
+}
+
+impl AuditQuery {
+    // the query of the audit page as a query string
+    pub(crate) fn parse(query: &str) -> Result<Self> {
+        serde_urlencoded::from_str(query).map_err(|err| Error::InvalidQuery(err.to_string()))
+    }
+
+    fn page(&self) -> usize {
+        self.page.max(1)
+    }
+
+    fn link(&self, path: &str, page: usize) -> String {
+        let query = AuditQuery {
+            page,
+            ..self.clone()
+        };
+        let query = serde_urlencoded::to_string(&query).unwrap_or_else(|_| String::new());
+        format!("{}?{}", path, escape(&query))
+    }
+
+    // `None` if nothing can match, because the actor doesn't exist
+    async fn filter(&self, data: &ServerData<'_>) -> Result<Option<AuditFilter>> {
+        let mut filter = AuditFilter::default();
+        if !self.actor.is_empty() {
+            match data.repo.user_by_name(&self.actor).await? {
+                Some(user) => filter.actor = Some(user.id),
+                None => return Ok(None),
+            }
+        }
+        if !self.action.is_empty() {
+            filter.action = Some(self.action.clone());
+        }
+        if !self.target.is_empty() {
+            filter.target = Some(self.target.clone());
+        }
+        let day = |date: &str| {
+            repo::parse_date(date)
+                .ok_or_else(|| Error::InvalidQuery(format!("{:?} isn't a yyyy-mm-dd date", date)))
+        };
+        if !self.since.is_empty() {
+            filter.since = Some(day(&self.since)?);
+        }
+        if !self.until.is_empty() {
+            filter.until = Some(day(&self.until)? + Duration::from_secs(DAY));
+        }
+        Ok(Some(filter))
+    }
+
+    async fn entries(
+        &self,
+        data: &ServerData<'_>,
+        limit: Option<usize>,
+        offset: usize,
+    ) -> Result<Vec<AuditEntry>> {
+        match self.filter(data).await? {
+            Some(filter) => {
+                data.repo
+                    .audit_log(&AuditFilter {
+                        limit,
+                        offset,
+                        ..filter
+                    })
+                    .await
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+// `yyyy-mm-ddThh:mm:ssZ`
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let secs = secs % DAY;
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        repo::date(time),
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+// usernames of the actors, deleted users are shown by their id
+async fn usernames(data: &ServerData<'_>) -> Result<HashMap<i32, String>> {
+    Ok(data
+        .repo
+        .users()
+        .await?
+        .into_iter()
+        .map(|user| (user.id, user.username))
+        .collect())
+}
+
+fn actor(names: &HashMap<i32, String>, actor: Option<i32>) -> String {
+    match actor {
+        Some(id) => names
+            .get(&id)
+            .cloned()
+            .unwrap_or_else(|| format!("#{}", id)),
+        None => String::new(),
+    }
+}
+
+// the filter form, a page of entries and the links to the other pages
+pub(crate) async fn table(data: &ServerData<'_>, query: &AuditQuery) -> Result<String> {
+    let page = query.page();
+    let mut entries = query
+        .entries(data, Some(PER_PAGE + 1), (page - 1) * PER_PAGE)
+        .await?;
+    let more = entries.len() > PER_PAGE;
+    entries.truncate(PER_PAGE);
+    let names = usernames(data).await?;
+
+    let mut html = "<form class=\"audit-filter\" action=\"/account/audit.html\" method=\"get\">\n".to_string();
+    // the labels are spelled out for `l10n-check`
+    for (name, value, kind, label) in &[
+        ("actor", &query.actor, "text", "{{{l10n(audit_actor)}}}"),
+        ("target", &query.target, "text", "{{{l10n(audit_target)}}}"),
+        ("since", &query.since, "date", "{{{l10n(audit_since)}}}"),
+        ("until", &query.until, "date", "{{{l10n(audit_until)}}}"),
+    ] {
+        write!(html, "<label class=\"label\" for=\"audit-{}\">{}:</label>\n", name, label).expect("couldn't write to string");
+        write!(html, "<input type=\"{}\" id=\"audit-{1}\" name=\"{1}\" value=\"{2}\"/>\n", kind, name, escape(value)).expect("couldn't write to string");
+    }
+    write!(html, "<label class=\"label\" for=\"audit-action\">{{{{{{l10n(audit_action)}}}}}}:</label>\n").expect("couldn't write to string");
+    write!(html, "<select id=\"audit-action\" name=\"action\">\n").expect("couldn't write to string");
+    write!(html, "<option value=\"\">{{{{{{l10n(audit_all)}}}}}}</option>\n").expect("couldn't write to string");
+    for action in Action::ALL.iter() {
+        let selected = if query.action == action.name() { " selected=\"selected\"" } else { "" };
+        write!(html, "<option value=\"{0}\"{1}>{0}</option>\n", action.name(), selected).expect("couldn't write to string");
+    }
+    write!(html, "</select>\n").expect("couldn't write to string");
+    write!(html, "<input type=\"submit\" value=\"{{{{{{l10n(audit_filter)}}}}}}\"/>\n").expect("couldn't write to string");
+    write!(html, "</form>\n").expect("couldn't write to string");
+    write!(html, "<p><a href=\"{}\">{{{{{{l10n(audit_export_csv)}}}}}}</a> ", query.link("/account/audit.csv", 1)).expect("couldn't write to string");
+    write!(html, "<a href=\"{}\">{{{{{{l10n(audit_export_json)}}}}}}</a></p>\n", query.link("/account/audit.json", 1)).expect("couldn't write to string");
+
+    if entries.is_empty() {
+        write!(html, "<p>{{{{{{l10n(audit_empty)}}}}}}</p>\n").expect("couldn't write to string");
+        return Ok(html);
+    }
+    write!(html, "<table class=\"audit\">\n").expect("couldn't write to string");
+    write!(html, "<tr>\n").expect("couldn't write to string");
+    for column in &[
+        "{{{l10n(audit_time)}}}",
+        "{{{l10n(audit_actor)}}}",
+        "{{{l10n(audit_action)}}}",
+        "{{{l10n(audit_target)}}}",
+        "{{{l10n(audit_before)}}}",
+        "{{{l10n(audit_after)}}}",
+        "{{{l10n(audit_ip)}}}",
+    ] {
+        write!(html, "<th>{}</th>\n", column).expect("couldn't write to string");
+    }
+    write!(html, "</tr>\n").expect("couldn't write to string");
+    for entry in &entries {
+        write!(html, "<tr>\n").expect("couldn't write to string");
+        write!(html, "<td>{}</td>\n", timestamp(entry.time)).expect("couldn't write to string");
+        write!(html, "<td>{}</td>\n", escape(&actor(&names, entry.actor))).expect("couldn't write to string");
+        write!(html, "<td>{}</td>\n", escape(&entry.action)).expect("couldn't write to string");
+        for value in &[&entry.target, &entry.before, &entry.after, &entry.ip] {
+            write!(html, "<td>{}</td>\n", value.as_deref().map(escape).unwrap_or_default()).expect("couldn't write to string");
+        }
+        write!(html, "</tr>\n").expect("couldn't write to string");
+    }
+    write!(html, "</table>\n").expect("couldn't write to string");
+    write!(html, "<nav class=\"pages\">\n").expect("couldn't write to string");
+    if page > 1 {
+        write!(html, "<a href=\"{}\">{{{{{{l10n(audit_previous)}}}}}}</a>\n", query.link("/account/audit.html", page - 1)).expect("couldn't write to string");
+    }
+    if more {
+        write!(html, "<a href=\"{}\">{{{{{{l10n(audit_next)}}}}}}</a>\n", query.link("/account/audit.html", page + 1)).expect("couldn't write to string");
+    }
+    write!(html, "</nav>\n").expect("couldn't write to string");
+    Ok(html)
+}
+
+// quotes a CSV field if it has to be, and keeps spreadsheets from running formulas in it
+fn csv_field(field: &str) -> String {
+    let field = if field.starts_with(['=', '+', '-', '@']) {
+        format!("'{}", field)
+    } else {
+        field.to_string()
+    };
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+fn csv(entries: &[AuditEntry], names: &HashMap<i32, String>) -> String {
+    let mut csv = "id,time,actor_id,actor,action,target,before,after,ip\r\n".to_string();
+    for entry in entries {
+        let fields = [
+            entry.id.to_string(),
+            timestamp(entry.time),
+            entry.actor.map(|id| id.to_string()).unwrap_or_default(),
+            actor(names, entry.actor),
+            entry.action.clone(),
+            entry.target.clone().unwrap_or_default(),
+            entry.before.clone().unwrap_or_default(),
+            entry.after.clone().unwrap_or_default(),
+            entry.ip.clone().unwrap_or_default(),
+        ];
+        let fields = fields.iter().map(|field| csv_field(field)).collect::<Vec<_>>();
+        write!(csv, "{}\r\n", fields.join(",")).expect("couldn't write to string");
+    }
+    csv
+}
+
+fn json(entries: &[AuditEntry], names: &HashMap<i32, String>) -> String {
+    let entries = entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "id": entry.id,
+                "time": timestamp(entry.time),
+                "actor_id": entry.actor,
+                "actor": entry.actor.map(|id| actor(names, Some(id))),
+                "action": entry.action,
+                "target": entry.target,
+                "before": entry.before,
+                "after": entry.after,
+                "ip": entry.ip,
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string(&entries).unwrap()
+}
+
+#[get("/account/audit.html")]
+pub async fn audit_page<'a>(
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if !user.admin {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            return Ok(HttpResponse::Forbidden().body(body));
+        }
+
+        let mut body = fs::read_to_string("public/account/audit.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[req.query_string().to_string()],
+        )
+        .await?;
+        Ok(HttpResponse::Ok()
+            .header(http::header::CONTENT_TYPE, "text/html")
+            .body(body))
+    } else {
+        let mut body = fs::read_to_string("private/forbidden.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[],
+        )
+        .await?;
+        Ok(HttpResponse::Forbidden().body(body))
+    }
+}
+
+// writes the entries in one of the export formats
+type Render = fn(&[AuditEntry], &HashMap<i32, String>) -> String;
+
+// `/account/audit.csv` or `/account/audit.json`, with the filter of the audit page
+#[get("/account/audit.{format}")]
+pub async fn export<'a>(
+    format: web::Path<String>,
+    query: web::Query<AuditQuery>,
+    req: HttpRequest,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let (content_type, render): (_, Render) =
+        match format.as_str() {
+            "csv" => ("text/csv; charset=utf-8", csv),
+            "json" => ("application/json", json),
+            _ => return Err(Error::ResourceNotFound(format!("audit.{}", format))),
+        };
+    match data.api_user(&req, &identity, Some(Scope::Users)).await? {
+        Some(user) if user.admin => {
+            // all pages at once
+            let entries = query.entries(&data, None, 0).await?;
+            let names = usernames(&data).await?;
+            Ok(HttpResponse::Ok()
+                .header(http::header::CONTENT_TYPE, content_type)
+                .header(
+                    http::header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"audit-log.{}\"", format),
+                )
+                .header(http::header::CACHE_CONTROL, "no-store")
+                .body(render(&entries, &names)))
+        }
+        Some(_) => Err(Error::AuthorizationFailed),
+        None => Err(Error::AuthenticationFailed),
+    }
+}
@@ -1,14 +1,23 @@
-use actix_http::HttpMessage;
-use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use std::time::SystemTime;
+
+use actix_web::http::StatusCode;
+use actix_web::{post, web, HttpRequest, HttpResponse, Responder};
 use tokio::fs;
 
 use actix_identity::Identity;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
-use tokio_postgres as psql;
 
+use crate::audit::{Action, Event};
 use crate::error::{Error, Result};
-use crate::template;
+use crate::flash;
+use crate::form::{self, FieldError, Form};
+use crate::gdpr;
+use crate::i18n::Lang;
+use crate::repo::{NewUser, User, UserStatus};
+use crate::template::{self, Context};
+use crate::users;
+use crate::validate;
 use crate::web::ServerData;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -24,6 +33,14 @@ pub struct UpdatePasswordData {
     new_password2: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResetPasswordData {
+    username: String,
+    old_password: String,
+    new_password: String,
+    new_password2: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateData {
     firstname: String,
@@ -40,6 +57,12 @@ pub struct AuthData {
     password: String,
 }
 
+// the pages whose forms are sent to the handlers below, rendered again when something is wrong
+const CREATE: &str = "public/create.html";
+const LOGIN: &str = "public/login.html";
+const ACCOUNT: &str = "public/account/me.html";
+const RESET: &str = "private/reset.html";
+
 pub fn salt() -> [u8; 32] {
     random()
 }
@@ -48,29 +71,36 @@ pub fn salt() -> [u8; 32] {
 pub async fn change_email<'a>(
     auth_data: web::Form<UpdateEmailData>,
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    match identity.identity() {
-        Some(username) => {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(user) => {
             let auth_data = auth_data.into_inner();
-            let email = auth_data.email;
-            if !email.contains('@') {
-                return Err(Error::InvalidCreateUser(
-                    "e-mail is not an e-mail".to_string(),
-                ));
+            let email = auth_data.email.trim().to_string();
+            let mut form = Form::default().value("email", &email);
+            let user = checked(user, &auth_data.password, "password", &mut form)?;
+            if let Some(error) = validate::email(&email) {
+                form.error("email", error);
             }
-            let _userdata = query(&username, &auth_data.password, &data).await?;
-            data.client
-                .execute(
-                    "update users set email = $1 where username = $2",
-                    &[&email, &username],
-                )
+            let user = match user {
+                Some(user) if form.is_valid() => user,
+                _ => {
+                    let status = status(&user);
+                    return form::redisplay(ACCOUNT, &req, &identity, &data, &lang, form, status)
+                        .await;
+                }
+            };
+            data.repo.set_email(user.id, &email).await?;
+            Event::new(Some(&user), Action::ChangeEmail)
+                .target(format!("user {}", user.id))
+                .before(&user.email)
+                .after(&email)
+                .record(&data, &req)
                 .await?;
+            flash::set(&req, "flash_email_changed");
             Ok(HttpResponse::SeeOther()
                 .header("Location", "/account/me.html")
                 .finish())
@@ -78,9 +108,9 @@ pub async fn change_email<'a>(
         None => {
             let mut body = fs::read_to_string("private/forbidden.html").await?;
             template::search_replace_recursive(
-                &identity,
-                &data.client,
-                &data.lang[&lang],
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
                 &mut body,
                 &[],
             )
@@ -94,32 +124,51 @@ pub async fn change_email<'a>(
 pub async fn change_password<'a>(
     auth_data: web::Form<UpdatePasswordData>,
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    match identity.identity() {
-        Some(username) => {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(user) => {
             let auth_data = auth_data.into_inner();
-            let _userdata = query(&username, &auth_data.old_password, &data).await?;
-            if auth_data.new_password.is_empty() {
-                return Err(Error::InvalidCreateUser("password is empty".to_string()));
+            let mut form = Form::default();
+            let user = checked(user, &auth_data.old_password, "old_password", &mut form)?;
+            if let Some(user) = &user {
+                if let Some(error) = validate::password(
+                    &data.config.validation,
+                    &data.common_passwords,
+                    &auth_data.new_password,
+                    &user.username,
+                    &user.email,
+                ) {
+                    form.error("new_password", error);
+                }
             }
             if auth_data.new_password != auth_data.new_password2 {
-                return Err(Error::PasswordMismatch);
+                form.error(
+                    "new_password2",
+                    FieldError::new("validation_password_mismatch"),
+                );
             }
+            let user = match user {
+                Some(user) if form.is_valid() => user,
+                _ => {
+                    let status = status(&user);
+                    return form::redisplay(ACCOUNT, &req, &identity, &data, &lang, form, status)
+                        .await;
+                }
+            };
             let salt = salt();
             let pwhash =
                 argon2::hash_encoded(auth_data.new_password.as_bytes(), &salt, &data.argon)?;
-            data.client
-                .execute(
-                    "update users set pwhash = $1 where username = $2",
-                    &[&pwhash, &username],
-                )
+            data.repo.set_pwhash(user.id, &pwhash).await?;
+            // the hashes themselves stay out of the log
+            Event::new(Some(&user), Action::ChangePassword)
+                .target(format!("user {}", user.id))
+                .record(&data, &req)
                 .await?;
+            flash::set(&req, "flash_password_changed");
             Ok(HttpResponse::SeeOther()
                 .header("Location", "/account/me.html")
                 .finish())
@@ -127,9 +176,9 @@ pub async fn change_password<'a>(
         None => {
             let mut body = fs::read_to_string("private/forbidden.html").await?;
             template::search_replace_recursive(
-                &identity,
-                &data.client,
-                &data.lang[&lang],
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
                 &mut body,
                 &[],
             )
@@ -139,122 +188,245 @@ pub async fn change_password<'a>(
     }
 }
 
+// the logged in user if `password` is theirs, an error on `field` of the form otherwise
+fn checked(user: User, password: &str, field: &str, form: &mut Form) -> Result<Option<User>> {
+    if argon2::verify_encoded(&user.pwhash, password.as_bytes())? {
+        Ok(Some(user))
+    } else {
+        form.error(field, FieldError::new("validation_password_wrong"));
+        Ok(None)
+    }
+}
+
+// a wrong password is told apart from the rest of what can be wrong with a form
+fn status(user: &Option<User>) -> StatusCode {
+    match user {
+        Some(_) => StatusCode::BAD_REQUEST,
+        None => StatusCode::UNAUTHORIZED,
+    }
+}
+
 #[post("/auth/create.html")]
 pub async fn create<'a>(
     auth_data: web::Form<CreateData>,
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
+    let lang = data.language(&lang);
     let auth_data = auth_data.into_inner();
-    let firstname = if auth_data.firstname.is_empty() {
-        None
-    } else {
-        Some(auth_data.firstname)
-    };
-    let lastname = if auth_data.lastname.is_empty() {
-        None
-    } else {
-        Some(auth_data.lastname)
-    };
-    if auth_data.username.is_empty() {
-        return Err(Error::InvalidCreateUser("username is empty".to_string()));
-    }
-    if auth_data.password.is_empty() {
-        return Err(Error::InvalidCreateUser("password is empty".to_string()));
+    let config = &data.config.validation;
+    let username = auth_data.username.trim().to_string();
+    let email = auth_data.email.trim().to_string();
+    let mut form = Form::default()
+        .value("firstname", &auth_data.firstname)
+        .value("lastname", &auth_data.lastname)
+        .value("username", &username)
+        .value("email", &email);
+
+    if let Some(error) = validate::username(config, &username) {
+        form.error("username", error);
+    } else if data.repo.username_taken(&username).await? {
+        form.error("username", FieldError::new("validation_username_taken"));
     }
-    let username = auth_data.username;
-    let email = auth_data.email;
-    let existing = data
-        .client
-        .query_opt("select * from users where username = $1", &[&username])
-        .await?;
-    if let Some(_existing) = existing {
-        let mut body = fs::read_to_string("private/exists.html").await?;
-        template::search_replace_recursive(
-            &identity,
-            &data.client,
-            &data.lang[&lang],
-            &mut body,
-            &[format!("user {}", username)],
-        )
-        .await?;
-        return Ok(HttpResponse::BadRequest().body(body));
+    if let Some(error) = validate::email(&email) {
+        form.error("email", error);
     }
-    if !email.contains('@') {
-        return Err(Error::InvalidCreateUser(
-            "e-mail is not an e-mail".to_string(),
-        ));
+    if let Some(error) = validate::password(
+        config,
+        &data.common_passwords,
+        &auth_data.password,
+        &username,
+        &email,
+    ) {
+        form.error("password", error);
     }
     if auth_data.password != auth_data.password2 {
-        return Err(Error::PasswordMismatch);
+        form.error("password2", FieldError::new("validation_password_mismatch"));
+    }
+    let banned = data.repo.email_banned(&email).await?;
+    if banned {
+        form.error("email", FieldError::new("validation_email_banned"));
     }
+    if !form.is_valid() {
+        let status = if banned {
+            StatusCode::FORBIDDEN
+        } else {
+            StatusCode::BAD_REQUEST
+        };
+        return form::redisplay(CREATE, &req, &identity, &data, &lang, form, status).await;
+    }
+
     let salt = salt();
     let pwhash = argon2::hash_encoded(auth_data.password.as_bytes(), &salt, &data.argon)?;
-    match (firstname, lastname) {
-        (Some(first), Some(last)) => {
-            data.client.execute("insert into users (firstname, lastname, username, email, pwhash) values ($1, $2, $3, $4, $5)", &[&first, &last, &username, &email, &pwhash]).await?;
+    let created = data
+        .repo
+        .create_user(NewUser {
+            username,
+            pwhash,
+            email,
+            firstname: users::optional(auth_data.firstname),
+            lastname: users::optional(auth_data.lastname),
+        })
+        .await;
+    match created {
+        Ok(_) => {
+            flash::set(&req, "flash_account_created");
+            Ok(HttpResponse::SeeOther().header("Location", "/").finish())
         }
-        (Some(first), None) => {
-            data.client.execute("insert into users (firstname, username, email, pwhash) values ($1, $2, $3, $4)", &[&first, &username, &email, &pwhash]).await?;
-        }
-        (None, Some(last)) => {
-            data.client
-                .execute(
-                    "insert into users (lastname, username, email, pwhash) values ($1, $2, $3, $4)",
-                    &[&last, &username, &email, &pwhash],
-                )
-                .await?;
-        }
-        (None, None) => {
-            data.client
-                .execute(
-                    "insert into users (username, email, pwhash) values ($1, $2, $3)",
-                    &[&username, &email, &pwhash],
-                )
-                .await?;
+        // someone else registered the name in the meantime
+        Err(Error::Conflict(_)) => {
+            form.error("username", FieldError::new("validation_username_taken"));
+            let status = StatusCode::BAD_REQUEST;
+            form::redisplay(CREATE, &req, &identity, &data, &lang, form, status).await
         }
+        Err(err) => Err(err),
     }
-    Ok(HttpResponse::SeeOther().header("Location", "/").finish())
 }
 
 #[post("/auth/login.html")]
 pub async fn login<'a>(
     auth_data: web::Form<AuthData>,
-    _req: HttpRequest,
+    req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
+    let lang = data.language(&lang);
     let auth_data = auth_data.into_inner();
-    let _userdata = query(&auth_data.username, &auth_data.password, &data).await?;
+    let mut form = Form::default().value("username", &auth_data.username);
+    // the status is only told to someone who knows the password
+    let user = match query(&auth_data.username, &auth_data.password, &data).await {
+        Ok(user) if user.status == UserStatus::Active => user,
+        result => {
+            // nobody is logged in, the target is the name that was tried
+            Event::new(None, Action::LoginFailed)
+                .target(&auth_data.username)
+                .record(&data, &req)
+                .await?;
+            let (error, status) = match result {
+                Ok(_) => ("login_disabled", StatusCode::FORBIDDEN),
+                Err(Error::AuthenticationFailed) => ("login_failed", StatusCode::UNAUTHORIZED),
+                Err(err) => return Err(err),
+            };
+            form.error("password", FieldError::new(error));
+            return form::redisplay(LOGIN, &req, &identity, &data, &lang, form, status).await;
+        }
+    };
+    // logging in during the grace period keeps the account
+    if let Some(time) = user.delete_after {
+        if time <= SystemTime::now() {
+            // only waiting for the next purge
+            gdpr::delete(&data, &user).await?;
+            form.error("password", FieldError::new("login_failed"));
+            let status = StatusCode::UNAUTHORIZED;
+            return form::redisplay(LOGIN, &req, &identity, &data, &lang, form, status).await;
+        }
+        data.repo.set_delete_after(user.id, None).await?;
+        Event::new(Some(&user), Action::CancelDeletion)
+            .target(format!("user {}", user.id))
+            .record(&data, &req)
+            .await?;
+    }
+    if user.reset_password {
+        let form = Form::default().value("username", &user.username);
+        return form::redisplay(RESET, &req, &identity, &data, &lang, form, StatusCode::OK).await;
+    }
+    Event::new(Some(&user), Action::Login)
+        .target(format!("user {}", user.id))
+        .record(&data, &req)
+        .await?;
     let username = auth_data.username;
     identity.remember(username);
     Ok(HttpResponse::SeeOther().header("Location", "/").finish())
 }
 
-#[get("/auth/logout.html")]
+// the new password of a user an admin asked to choose one, see `login`
+#[post("/auth/reset-password.html")]
+pub async fn reset_password<'a>(
+    auth_data: web::Form<ResetPasswordData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    let auth_data = auth_data.into_inner();
+    let mut form = Form::default().value("username", &auth_data.username);
+    let user = match query(&auth_data.username, &auth_data.old_password, &data).await {
+        Ok(user) if user.status == UserStatus::Active => user,
+        result => {
+            let (error, status) = match result {
+                Ok(_) => ("login_disabled", StatusCode::FORBIDDEN),
+                Err(Error::AuthenticationFailed) => {
+                    ("validation_password_wrong", StatusCode::UNAUTHORIZED)
+                }
+                Err(err) => return Err(err),
+            };
+            form.error("old_password", FieldError::new(error));
+            return form::redisplay(RESET, &req, &identity, &data, &lang, form, status).await;
+        }
+    };
+    // the form is only shown to users an admin asked to choose a new password
+    if !user.reset_password {
+        return Err(Error::AuthorizationFailed);
+    }
+    if auth_data.new_password == auth_data.old_password {
+        form.error("new_password", FieldError::new("validation_password_unchanged"));
+    } else if let Some(error) = validate::password(
+        &data.config.validation,
+        &data.common_passwords,
+        &auth_data.new_password,
+        &user.username,
+        &user.email,
+    ) {
+        form.error("new_password", error);
+    }
+    if auth_data.new_password != auth_data.new_password2 {
+        form.error("new_password2", FieldError::new("validation_password_mismatch"));
+    }
+    if !form.is_valid() {
+        let status = StatusCode::BAD_REQUEST;
+        return form::redisplay(RESET, &req, &identity, &data, &lang, form, status).await;
+    }
+    let salt = salt();
+    let pwhash = argon2::hash_encoded(auth_data.new_password.as_bytes(), &salt, &data.argon)?;
+    data.repo.set_pwhash(user.id, &pwhash).await?;
+    data.repo.set_reset_password(user.id, false).await?;
+    Event::new(Some(&user), Action::ResetPassword)
+        .target(format!("user {}", user.id))
+        .record(&data, &req)
+        .await?;
+    identity.remember(user.username);
+    Ok(HttpResponse::SeeOther().header("Location", "/").finish())
+}
+
+#[post("/auth/logout.html")]
 pub async fn logout<'a>(
-    _req: HttpRequest,
+    req: HttpRequest,
     identity: Identity,
-    _data: web::Data<ServerData<'a>>,
+    data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
+    if let Some(user) = data.user(&identity).await? {
+        Event::new(Some(&user), Action::Logout)
+            .target(format!("user {}", user.id))
+            .record(&data, &req)
+            .await?;
+    }
     identity.forget();
     Ok(HttpResponse::SeeOther().header("Location", "/").finish())
 }
 
-async fn query<'a>(username: &str, password: &str, data: &ServerData<'a>) -> Result<psql::Row> {
-    let userdata = data
-        .client
-        .query_one("select * from users where username = $1", &[&username])
-        .await?;
-    let pwhash = userdata.get::<_, &str>("pwhash");
-    let res = argon2::verify_encoded(pwhash, password.as_bytes())?;
+pub(crate) async fn query<'a>(username: &str, password: &str, data: &ServerData<'a>) -> Result<User> {
+    let user = data
+        .repo
+        .user_by_name(username)
+        .await?
+        .ok_or(Error::AuthenticationFailed)?;
+    let res = argon2::verify_encoded(&user.pwhash, password.as_bytes())?;
     if res {
-        Ok(userdata)
+        Ok(user)
     } else {
         Err(Error::AuthenticationFailed)
     }
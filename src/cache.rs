@@ -0,0 +1,264 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::http::header::{self, HttpDate};
+use actix_web::web::Bytes;
+use actix_web::{HttpRequest, HttpResponse};
+use tokio::fs;
+
+use crate::compress::{self, Encoding};
+use crate::config::CompressionConfig;
+use crate::error::Result;
+use crate::headers;
+
+// assets requested by their content hash never change
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+
+// a static file as it was when it was last read
+#[derive(Debug)]
+pub struct Asset {
+    pub body: Bytes,
+    // precompressed variants, if they are worth it
+    pub gzip: Option<Bytes>,
+    pub brotli: Option<Bytes>,
+    // content hash, used for the ETag and in `?v=` URLs
+    pub hash: String,
+    pub modified: SystemTime,
+}
+
+impl Asset {
+    // every encoding has its own strong ETag
+    pub fn etag(&self, encoding: Encoding) -> String {
+        format!("\"{}{}\"", self.hash, encoding.suffix())
+    }
+
+    // the best variant out of those the visitor accepts
+    pub fn encoded(&self, accepted: &[Encoding]) -> (Encoding, &Bytes) {
+        accepted
+            .iter()
+            .find_map(|encoding| match encoding {
+                Encoding::Brotli => self.brotli.as_ref().map(|body| (Encoding::Brotli, body)),
+                Encoding::Gzip => self.gzip.as_ref().map(|body| (Encoding::Gzip, body)),
+                Encoding::Identity => None,
+            })
+            .unwrap_or((Encoding::Identity, &self.body))
+    }
+}
+
+// static files kept in memory, shared by all workers
+#[derive(Debug, Clone, Default)]
+pub struct Assets {
+    cache: Arc<RwLock<HashMap<PathBuf, Arc<Asset>>>>,
+    compression: CompressionConfig,
+}
+
+impl Assets {
+    pub fn new(compression: CompressionConfig) -> Self {
+        Self {
+            cache: Arc::default(),
+            compression,
+        }
+    }
+
+    // reads and compresses every file in a directory up front, so the
+    // first visitors don't have to wait for it
+    pub async fn preload<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let mut dirs = vec![dir.as_ref().to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = fs::read_dir(dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(path);
+                } else {
+                    self.get(path).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the compressed variant of a file, if it is compressible and large enough
+    // for the compression to pay off
+    fn compress(&self, path: &Path, body: &[u8], encoding: Encoding) -> Result<Option<Bytes>> {
+        if !compress::compressible(path) || body.len() < self.compression.min_size {
+            return Ok(None);
+        }
+        let level = match encoding {
+            Encoding::Brotli => self.compression.brotli_quality,
+            _ => self.compression.gzip_level,
+        };
+        let compressed = encoding.compress(body, level)?;
+        if compressed.len() < body.len() {
+            Ok(Some(Bytes::from(compressed)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // the file at `path`, only read again if it changed on disk
+    pub async fn get<P: AsRef<Path>>(&self, path: P) -> Result<Arc<Asset>> {
+        let path = path.as_ref();
+        let modified = fs::metadata(path).await?.modified()?;
+        let cached = self
+            .cache
+            .read()
+            .expect("asset cache lock is poisoned")
+            .get(path)
+            .cloned();
+        match cached {
+            Some(asset) if asset.modified == modified => Ok(asset),
+            _ => {
+                let body = fs::read(path).await?;
+                let asset = Arc::new(Asset {
+                    gzip: self.compress(path, &body, Encoding::Gzip)?,
+                    brotli: self.compress(path, &body, Encoding::Brotli)?,
+                    hash: hash(&body),
+                    body: Bytes::from(body),
+                    modified,
+                });
+                self.cache
+                    .write()
+                    .expect("asset cache lock is poisoned")
+                    .insert(path.to_path_buf(), asset.clone());
+                Ok(asset)
+            }
+        }
+    }
+}
+
+pub fn hash(bytes: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn weak_eq(a: &str, b: &str) -> bool {
+    a.trim_start_matches("W/") == b.trim_start_matches("W/")
+}
+
+fn seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+// whether the visitor's cached copy is still up to date
+// `If-None-Match` takes precedence over `If-Modified-Since`
+pub fn fresh(req: &HttpRequest, etag: &str, modified: Option<SystemTime>) -> bool {
+    let headers = req.headers();
+    if let Some(tags) = headers.get(header::IF_NONE_MATCH) {
+        return match tags.to_str() {
+            Ok(tags) => tags
+                .split(',')
+                .map(str::trim)
+                .any(|tag| tag == "*" || weak_eq(tag, etag)),
+            Err(_) => false,
+        };
+    }
+    let since = headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| since.parse::<HttpDate>().ok())
+        .map(SystemTime::from);
+    match (since, modified) {
+        // HTTP dates only have a precision of seconds
+        (Some(since), Some(modified)) => seconds(modified) <= seconds(since),
+        _ => false,
+    }
+}
+
+// sends a static asset, which may be cached forever if it was requested by
+// its content hash and has to be revalidated otherwise
+pub fn asset(req: &HttpRequest, asset: &Asset, content_type: &str) -> HttpResponse {
+    let (encoding, body) = asset.encoded(&Encoding::accepted(req));
+    let etag = asset.etag(encoding);
+    let version = format!("v={}", asset.hash);
+    let cache_control = if req.query_string().split('&').any(|pair| pair == version) {
+        IMMUTABLE
+    } else {
+        "no-cache"
+    };
+    let fresh = fresh(req, &etag, Some(asset.modified));
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .header(header::ETAG, etag)
+        .header(header::LAST_MODIFIED, HttpDate::from(asset.modified).to_string())
+        .header(header::CACHE_CONTROL, cache_control)
+        .header(header::ACCEPT_RANGES, "bytes")
+        .header(header::VARY, "Accept-Encoding");
+    if encoding != Encoding::Identity {
+        response.header(header::CONTENT_ENCODING, encoding.name());
+    }
+    if fresh {
+        response.finish()
+    } else {
+        response
+            .header(header::CONTENT_TYPE, content_type)
+            .body(body.clone())
+    }
+}
+
+// sends a rendered page, or `304 Not Modified` if the visitor already has it
+// pages differ per visitor, so they are always revalidated
+pub async fn page(
+    req: &HttpRequest,
+    config: &CompressionConfig,
+    body: String,
+    files: &[&str],
+    modified: Option<SystemTime>,
+) -> Result<HttpResponse> {
+    // the nonce is new on every request, but scripts with an outdated one
+    // still load from the site itself, so it doesn't make the page different
+    let etag = match headers::nonce(req) {
+        Some(nonce) => hash(body.replace(&nonce, "").as_bytes()),
+        None => hash(body.as_bytes()),
+    };
+    let etag = format!("W/\"{}\"", etag);
+    // the files the page was rendered from may be newer than its articles
+    let mut modified = modified.unwrap_or(UNIX_EPOCH);
+    for file in files {
+        modified = modified.max(fs::metadata(file).await?.modified()?);
+    }
+    let fresh = fresh(req, &etag, Some(modified));
+    let mut response = if fresh {
+        HttpResponse::NotModified()
+    } else {
+        HttpResponse::Ok()
+    };
+    response
+        .header(header::ETAG, etag)
+        .header(header::LAST_MODIFIED, HttpDate::from(modified).to_string())
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::VARY, "Cookie, Accept-Language, Accept-Encoding");
+    if fresh {
+        return Ok(response.finish());
+    }
+    response.header(header::CONTENT_TYPE, "text/html");
+    let encoding = if body.len() < config.min_size {
+        None
+    } else {
+        Encoding::accepted(req).into_iter().next()
+    };
+    match encoding {
+        Some(encoding) => {
+            let level = match encoding {
+                Encoding::Brotli => config.page_brotli_quality,
+                _ => config.page_gzip_level,
+            };
+            let body = encoding.compress(body.as_bytes(), level)?;
+            Ok(response
+                .header(header::CONTENT_ENCODING, encoding.name())
+                .body(body))
+        }
+        None => Ok(response.body(body)),
+    }
+}
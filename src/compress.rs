@@ -0,0 +1,102 @@
+use std::io::Write;
+use std::path::Path;
+
+use actix_web::http::header;
+use actix_web::HttpRequest;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::error::Result;
+
+// files with these extensions are worth compressing, unlike e.g. images
+const COMPRESSIBLE: &[&str] = &["html", "css", "js", "wasm", "svg", "json", "xml", "txt", "md"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    // the name used in `Accept-Encoding` and `Content-Encoding`
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+        }
+    }
+
+    // appended to ETags and file names of compressed variants
+    pub fn suffix(self) -> &'static str {
+        match self {
+            Encoding::Identity => "",
+            Encoding::Gzip => ".gz",
+            Encoding::Brotli => ".br",
+        }
+    }
+
+    // the compressed encodings the visitor accepts, most preferred first
+    // brotli wins ties, as it compresses better
+    pub fn accepted(req: &HttpRequest) -> Vec<Self> {
+        let header = match req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|header| header.to_str().ok())
+        {
+            Some(header) => header,
+            None => return Vec::new(),
+        };
+        let mut any = None;
+        let mut quality = vec![(Encoding::Brotli, None), (Encoding::Gzip, None)];
+        for item in header.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or("");
+            let q = parts
+                .find(|part| part.starts_with("q="))
+                .and_then(|q| q["q=".len()..].parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if name == "*" {
+                any = Some(q);
+            }
+            for (encoding, quality) in &mut quality {
+                if encoding.name() == name || (*encoding == Encoding::Gzip && name == "x-gzip") {
+                    *quality = Some(q);
+                }
+            }
+        }
+        let mut accepted = quality
+            .into_iter()
+            .filter_map(|(encoding, q)| q.or(any).map(|q| (encoding, q)))
+            .filter(|(_, q)| *q > 0.0)
+            .collect::<Vec<_>>();
+        // stable, so brotli stays in front of gzip with the same quality
+        accepted.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        accepted.into_iter().map(|(encoding, _)| encoding).collect()
+    }
+
+    pub fn compress(self, bytes: &[u8], level: u32) -> Result<Vec<u8>> {
+        match self {
+            Encoding::Identity => Ok(bytes.to_vec()),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+                encoder.write_all(bytes)?;
+                Ok(encoder.finish()?)
+            }
+            Encoding::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, level, 22);
+                encoder.write_all(bytes)?;
+                encoder.flush()?;
+                Ok(encoder.into_inner())
+            }
+        }
+    }
+}
+
+pub fn compressible(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| COMPRESSIBLE.contains(&ext))
+        .unwrap_or(false)
+}
@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::error::Result;
+
+pub const CONFIG_PATH: &str = "circus.ron";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub db: DbConfig,
+    pub preview: PreviewConfig,
+    pub i18n: I18nConfig,
+    pub export: ExportConfig,
+    pub compression: CompressionConfig,
+    pub assets: AssetConfig,
+    pub accounts: AccountConfig,
+    pub validation: ValidationConfig,
+}
+
+impl Config {
+    // reads the config file, falling back to the defaults if there is none
+    pub async fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            let text = fs::read_to_string(path).await?;
+            Ok(ron::de::from_str(&text)?)
+        } else {
+            Ok(Self::default())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    // with TLS, this listener only redirects to HTTPS
+    pub bind: String,
+    pub tls: Option<TlsConfig>,
+    pub headers: HeadersConfig,
+    // signs the flash cookie and encrypts the login cookie, see `flash::Flash` and `app`
+    // a random one is chosen at startup if empty, logins and notices don't survive restarts then
+    pub secret: String,
+    // reverse proxies whose `Forwarded` and `X-Forwarded-For` headers are believed,
+    // see `audit::client_ip`
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8080".to_string(),
+            tls: None,
+            headers: HeadersConfig::default(),
+            secret: String::new(),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+// security headers of every response, see `headers::SecurityHeaders`
+// empty values leave a header out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HeadersConfig {
+    // `{nonce}` is replaced with the nonce of the request
+    pub csp: String,
+    // appended to the policy, `'none'` forbids framing the site
+    pub frame_ancestors: String,
+    pub referrer_policy: String,
+    pub permissions_policy: String,
+    // the first route whose prefix matches the path overrides what it sets
+    pub routes: Vec<RouteHeaders>,
+}
+
+impl Default for HeadersConfig {
+    fn default() -> Self {
+        Self {
+            csp: "default-src 'self'; \
+                  script-src 'self' 'nonce-{nonce}' 'wasm-unsafe-eval'; \
+                  img-src 'self' data: https:; \
+                  object-src 'none'; \
+                  base-uri 'self'; \
+                  form-action 'self'; \
+                  report-uri /csp-report"
+                .to_string(),
+            frame_ancestors: "'none'".to_string(),
+            referrer_policy: "strict-origin-when-cross-origin".to_string(),
+            permissions_policy: "camera=(), microphone=(), geolocation=(), payment=(), usb=()"
+                .to_string(),
+            // JSON is never rendered as a page
+            routes: vec![RouteHeaders {
+                prefix: "/api/".to_string(),
+                csp: Some("default-src 'none'".to_string()),
+                ..RouteHeaders::default()
+            }],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RouteHeaders {
+    pub prefix: String,
+    pub csp: Option<String>,
+    pub frame_ancestors: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub permissions_policy: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub bind: String,
+    // PEM files, reloaded on SIGHUP
+    pub cert: String,
+    pub key: String,
+    // for the `Strict-Transport-Security` header
+    pub hsts_max_age: u64,
+    pub hsts_subdomains: bool,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:8443".to_string(),
+            cert: "tls/cert.pem".to_string(),
+            key: "tls/key.pem".to_string(),
+            hsts_max_age: 31536000,
+            hsts_subdomains: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DbConfig {
+    pub host: String,
+    pub port: u16,
+    pub dbname: String,
+    pub user: String,
+    // `disable`, `prefer`, `require` or `verify-full`, like libpq's
+    pub sslmode: String,
+    // PEM bundle of the CAs to trust besides the system's
+    pub ca_file: Option<String>,
+    // PKCS#12 archive with the client certificate and its key
+    pub client_identity: Option<String>,
+    pub client_identity_password: String,
+    // connections per worker
+    pub pool_size: usize,
+    // how long a request waits for a free connection
+    pub checkout_timeout_ms: u64,
+    // 0 disables the timeout
+    pub statement_timeout_ms: u64,
+    // idle connections are checked before they are used after this long
+    pub check_after_secs: u64,
+    // reconnecting waits `backoff_ms`, doubling up to `max_backoff_ms`
+    pub connect_retries: u32,
+    pub backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 5432,
+            dbname: "circus".to_string(),
+            user: "circus".to_string(),
+            sslmode: "prefer".to_string(),
+            ca_file: None,
+            client_identity: None,
+            client_identity_password: String::new(),
+            pool_size: 4,
+            checkout_timeout_ms: 10_000,
+            statement_timeout_ms: 30_000,
+            check_after_secs: 30,
+            connect_retries: 5,
+            backoff_ms: 100,
+            max_backoff_ms: 5_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PreviewConfig {
+    // template of a single preview, see `template::preview`
+    pub template: String,
+    // length of excerpts for articles without a summary
+    pub excerpt_words: usize,
+    // used to estimate the reading time
+    pub words_per_minute: usize,
+}
+
+impl Default for PreviewConfig {
+    fn default() -> Self {
+        Self {
+            template: "public/template/preview.html".to_string(),
+            excerpt_words: 50,
+            words_per_minute: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct I18nConfig {
+    // used when none of the visitor's languages are available
+    pub default_language: String,
+    // where missing keys are looked up, e.g. `"pl": ["en", "de"]`
+    // languages without a chain fall back to the default language
+    pub fallbacks: HashMap<String, Vec<String>>,
+}
+
+impl Default for I18nConfig {
+    fn default() -> Self {
+        Self {
+            default_language: "de".to_string(),
+            fallbacks: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportConfig {
+    // where `export` writes the static mirror to
+    pub directory: String,
+    // prefixed to the links in the feeds and the sitemap
+    pub base_url: String,
+    // pages in public/ which only work with the server, e.g. forms
+    pub dynamic: Vec<String>,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            directory: "export".to_string(),
+            base_url: "http://localhost:8080".to_string(),
+            dynamic: vec!["login".to_string(), "create".to_string()],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompressionConfig {
+    // responses smaller than this many bytes are sent uncompressed
+    pub min_size: usize,
+    // static assets are compressed once, so they get the best compression
+    pub gzip_level: u32,
+    pub brotli_quality: u32,
+    // rendered pages are compressed on every request
+    pub page_gzip_level: u32,
+    pub page_brotli_quality: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            gzip_level: 9,
+            brotli_quality: 11,
+            page_gzip_level: 6,
+            page_brotli_quality: 5,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AssetConfig {
+    // only files with these extensions are served from public/ as they are
+    pub extensions: Vec<String>,
+    // rendered when a directory is requested
+    pub index: String,
+}
+
+impl Default for AssetConfig {
+    fn default() -> Self {
+        Self {
+            extensions: [
+                "css", "js", "wasm", "json", "txt", "pdf", "png", "jpg", "jpeg", "gif", "svg",
+                "webp", "ico", "woff", "woff2", "ttf", "otf", "mp3", "ogg", "mp4", "webm",
+            ]
+            .iter()
+            .map(|ext| ext.to_string())
+            .collect(),
+            index: "index.html".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccountConfig {
+    // how long users can change their mind after asking for their account to be deleted
+    pub deletion_grace_days: u64,
+    // how often the server looks for accounts whose grace period is over
+    pub purge_interval_secs: u64,
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            deletion_grace_days: 30,
+            purge_interval_secs: 3600,
+        }
+    }
+}
+
+// what users may choose when they register, see `validate`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    // usernames are made of ASCII letters, digits and these, starting with a letter or digit
+    pub username_chars: String,
+    // in characters
+    pub username_min: usize,
+    pub username_max: usize,
+    // compared ignoring case, e.g. names visitors could take for the staff
+    pub reserved_usernames: Vec<String>,
+    pub password_min: usize,
+    // hashing very long passwords takes long
+    pub password_max: usize,
+    // one password per line, compared ignoring case
+    // e.g. a list of breached passwords, everything is allowed if the file doesn't exist
+    pub common_passwords: String,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            username_chars: "-_.".to_string(),
+            username_min: 3,
+            username_max: 32,
+            reserved_usernames: [
+                "admin", "administrator", "root", "system", "circus", "staff", "support",
+                "moderator", "webmaster", "postmaster", "hostmaster", "abuse", "security",
+                "info", "api", "account", "accounts", "auth", "authors", "articles", "login",
+                "logout", "register", "me", "null", "undefined", "anonymous",
+            ]
+            .iter()
+            .map(|name| name.to_string())
+            .collect(),
+            password_min: 8,
+            password_max: 1024,
+            common_passwords: "private/common-passwords.txt".to_string(),
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::cookie::{Cookie, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::web::BytesMut;
+use actix_web::{HttpMessage, HttpRequest};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::StreamExt;
+
+use crate::error::Error;
+use crate::headers;
+use crate::token;
+
+// double submit: the token is kept in a cookie and has to be sent back with
+// every request that changes something, which other sites can't do
+const COOKIE: &str = "csrf-token";
+const HEADER: &str = "x-csrf-token";
+const FIELD: &str = "csrf_token";
+
+// the same as the limit of `FormConfig` in `app`
+const FORM_LIMIT: usize = 1 << 20;
+
+// the token of the current request, see `token`
+#[derive(Debug, Clone)]
+struct CsrfToken(String);
+
+// the token forms and scripts have to send back
+pub fn token(req: &HttpRequest) -> Option<String> {
+    req.extensions().get::<CsrfToken>().map(|token| token.0.clone())
+}
+
+fn generate() -> String {
+    let bytes: [u8; 32] = rand::random();
+    token::hex(&bytes)
+}
+
+fn valid(token: &str) -> bool {
+    token.len() == 64 && token.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// compares in constant time so the token can't be guessed byte by byte
+fn equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a
+            .bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// `host:port` of a URL like `https://example.com:8443/path`
+fn authority(url: &str) -> Option<&str> {
+    let start = url.find("://")? + "://".len();
+    let rest = &url[start..];
+    let end = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    Some(&rest[..end])
+}
+
+// the request has to come from a page of this site
+fn same_origin(req: &ServiceRequest) -> Result<(), Error> {
+    let headers = req.headers();
+    let source = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+        .ok_or_else(|| Error::Csrf("neither Origin nor Referer is set".to_string()))?;
+    let source = source.to_str().unwrap_or("");
+    let host = req.connection_info().host().to_string();
+    match authority(source) {
+        Some(authority) if authority.eq_ignore_ascii_case(&host) => Ok(()),
+        _ => Err(Error::Csrf(format!("{:?} isn't {:?}", source, host))),
+    }
+}
+
+// the `csrf_token` field of a form, the body is put back for the handler
+async fn form_token(req: &mut ServiceRequest) -> Result<Option<String>, actix_web::Error> {
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return Ok(None);
+    }
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > FORM_LIMIT {
+            return Err(Error::Csrf("the form is too large".to_string()).into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+    let token = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+        .ok()
+        .and_then(|fields| {
+            fields
+                .into_iter()
+                .find(|(name, _)| name == FIELD)
+                .map(|(_, value)| value)
+        });
+    let (_, mut replay) = actix_http::h1::Payload::create(true);
+    replay.unread_data(body);
+    req.set_payload(replay.into());
+    Ok(token)
+}
+
+// checks requests that change something, safe methods only get a token
+async fn check(req: &mut ServiceRequest, token: &str) -> Result<(), actix_web::Error> {
+    if req.method().is_safe() {
+        return Ok(());
+    }
+    // browsers send reports on their own, and they don't change anything
+    if req.path() == headers::REPORT_PATH {
+        return Ok(());
+    }
+    // browsers can't send this header to other sites without a CORS preflight,
+    // and `ServerData::api_user` doesn't look at the cookie if it is there
+    if token::bearer(req.headers()).is_some() {
+        return Ok(());
+    }
+    same_origin(req)?;
+    let sent = match req.headers().get(HEADER) {
+        Some(value) => value.to_str().ok().map(str::to_string),
+        None => form_token(req).await?,
+    };
+    match sent {
+        Some(sent) if equal(&sent, token) => Ok(()),
+        Some(_) => Err(Error::Csrf("the token doesn't match".to_string()).into()),
+        None => Err(Error::Csrf("the token is missing".to_string()).into()),
+    }
+}
+
+// hands out the token and rejects state changing requests without it
+pub struct Csrf {
+    secure: bool,
+}
+
+impl Csrf {
+    // `secure` cookies are only sent over TLS
+    pub fn new(secure: bool) -> Self {
+        Self { secure }
+    }
+}
+
+impl<S, B> Transform<S> for Csrf
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = CsrfMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CsrfMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            secure: self.secure,
+        })
+    }
+}
+
+pub struct CsrfMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    secure: bool,
+}
+
+impl<S, B> Service for CsrfMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let secure = self.secure;
+        Box::pin(async move {
+            let existing = req
+                .cookie(COOKIE)
+                .map(|cookie| cookie.value().to_string())
+                .filter(|token| valid(token));
+            let fresh = existing.is_none();
+            let token = existing.unwrap_or_else(generate);
+            if let Err(err) = check(&mut req, &token).await {
+                return Ok(req.error_response(err));
+            }
+            req.extensions_mut().insert(CsrfToken(token.clone()));
+            // the service mustn't stay borrowed while the handler runs
+            let future = service.borrow_mut().call(req);
+            let mut res = future.await?;
+            if fresh {
+                let cookie = Cookie::build(COOKIE, token)
+                    .path("/")
+                    .http_only(true)
+                    .same_site(SameSite::Strict)
+                    .secure(secure)
+                    .finish();
+                res.response_mut().add_cookie(&cookie)?;
+            }
+            Ok(res)
+        })
+    }
+}
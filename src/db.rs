@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Deref;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use native_tls::{Certificate, Identity, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::task::JoinHandle;
+use tokio::time;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{self as psql, Row, Statement};
+
+use crate::config::DbConfig;
+use crate::error::{Error, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SslMode {
+    Disable,
+    // TLS if the server supports it, without verifying the certificate
+    Prefer,
+    // always TLS, the certificate is only verified against `ca_file`
+    Require,
+    // always TLS, verifying the certificate and the host name
+    VerifyFull,
+}
+
+impl FromStr for SslMode {
+    type Err = Error;
+
+    fn from_str(mode: &str) -> Result<Self> {
+        match mode {
+            "disable" => Ok(SslMode::Disable),
+            "prefer" => Ok(SslMode::Prefer),
+            "require" => Ok(SslMode::Require),
+            "verify-full" => Ok(SslMode::VerifyFull),
+            _ => Err(Error::Tls(format!("unknown sslmode {:?}", mode))),
+        }
+    }
+}
+
+fn psql_escape<S: AsRef<str>>(string: S) -> String {
+    string.as_ref().replace("\\", "\\\\")
+}
+
+pub fn psql_config(config: &DbConfig, password: &str) -> Result<String> {
+    // tokio-postgres leaves verification to the connector, see `connector`
+    let sslmode = match config.sslmode.parse::<SslMode>()? {
+        SslMode::Disable => "disable",
+        SslMode::Prefer => "prefer",
+        SslMode::Require | SslMode::VerifyFull => "require",
+    };
+    Ok(format!(
+        "host={} port={} dbname={} user={} password='{}' sslmode={}",
+        config.host,
+        config.port,
+        config.dbname,
+        config.user,
+        psql_escape(password),
+        sslmode
+    ))
+}
+
+pub fn connector(config: &DbConfig) -> Result<MakeTlsConnector> {
+    let mode = config.sslmode.parse::<SslMode>()?;
+    let mut builder = TlsConnector::builder();
+    if let Some(ca_file) = &config.ca_file {
+        builder.add_root_certificate(Certificate::from_pem(&fs::read(ca_file)?)?);
+    }
+    if let Some(identity) = &config.client_identity {
+        builder.identity(Identity::from_pkcs12(
+            &fs::read(identity)?,
+            &config.client_identity_password,
+        )?);
+    }
+    match mode {
+        SslMode::VerifyFull => {}
+        SslMode::Require if config.ca_file.is_some() => {
+            builder.danger_accept_invalid_hostnames(true);
+        }
+        _ => {
+            builder.danger_accept_invalid_certs(true);
+            builder.danger_accept_invalid_hostnames(true);
+        }
+    }
+    Ok(MakeTlsConnector::new(builder.build()?))
+}
+
+struct Connection {
+    client: psql::Client,
+    // prepared statements of the hot queries, by their SQL
+    statements: Mutex<HashMap<String, Statement>>,
+    // when the connection was last known to work
+    checked: Instant,
+    _handle: JoinHandle<()>,
+}
+
+// database connections of a worker
+pub struct Pool {
+    psql_config: String,
+    tls: MakeTlsConnector,
+    config: DbConfig,
+    idle: Mutex<Vec<Connection>>,
+    permits: Semaphore,
+}
+
+impl Pool {
+    pub fn new<S: Into<String>>(
+        psql_config: S,
+        tls: MakeTlsConnector,
+        config: DbConfig,
+    ) -> Self {
+        Self {
+            psql_config: psql_config.into(),
+            tls,
+            permits: Semaphore::new(config.pool_size.max(1)),
+            config,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    // connects to the database, retrying with exponential backoff
+    // so that a restarting database doesn't fail every request
+    async fn connect(&self) -> Result<Connection> {
+        let mut backoff = Duration::from_millis(self.config.backoff_ms);
+        let max_backoff = Duration::from_millis(self.config.max_backoff_ms);
+        let mut retries = 0;
+        loop {
+            match psql::connect(&self.psql_config, self.tls.clone()).await {
+                Ok((client, conn)) => {
+                    let handle = tokio::spawn(async move {
+                        if let Err(e) = conn.await {
+                            eprintln!("connection error: {}", e);
+                        }
+                    });
+                    if self.config.statement_timeout_ms > 0 {
+                        client
+                            .batch_execute(&format!(
+                                "set statement_timeout = {}",
+                                self.config.statement_timeout_ms
+                            ))
+                            .await?;
+                    }
+                    return Ok(Connection {
+                        client,
+                        statements: Mutex::new(HashMap::new()),
+                        checked: Instant::now(),
+                        _handle: handle,
+                    });
+                }
+                Err(err) if retries < self.config.connect_retries => {
+                    eprintln!("couldn't connect to the database, retrying in {:?}: {}", backoff, err);
+                    time::delay_for(backoff).await;
+                    backoff = (backoff * 2).min(max_backoff);
+                    retries += 1;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    // a connection from the pool, which goes back into it when dropped
+    // closed connections are replaced, and those which have been idle for a
+    // while are checked before they are handed out
+    pub async fn get(&self) -> Result<PooledClient<'_>> {
+        let timeout = Duration::from_millis(self.config.checkout_timeout_ms);
+        let permit = time::timeout(timeout, self.permits.acquire())
+            .await
+            .map_err(|_| Error::PoolTimeout)?;
+        let check_after = Duration::from_secs(self.config.check_after_secs);
+        loop {
+            let idle = self.idle.lock().expect("pool lock is poisoned").pop();
+            let conn = match idle {
+                Some(conn) if conn.client.is_closed() => continue,
+                Some(mut conn) if conn.checked.elapsed() >= check_after => {
+                    if conn.client.simple_query("select 1").await.is_err() {
+                        continue;
+                    }
+                    conn.checked = Instant::now();
+                    conn
+                }
+                Some(conn) => conn,
+                None => self.connect().await?,
+            };
+            return Ok(PooledClient {
+                pool: self,
+                conn: Some(conn),
+                _permit: permit,
+            });
+        }
+    }
+}
+
+pub struct PooledClient<'p> {
+    pool: &'p Pool,
+    conn: Option<Connection>,
+    _permit: SemaphorePermit<'p>,
+}
+
+impl<'p> PooledClient<'p> {
+    fn conn(&self) -> &Connection {
+        self.conn.as_ref().expect("connection was already returned")
+    }
+
+    // prepares a statement only once per connection
+    pub async fn prepare_cached(&self, query: &str) -> Result<Statement> {
+        let conn = self.conn();
+        let cached = conn
+            .statements
+            .lock()
+            .expect("statement cache lock is poisoned")
+            .get(query)
+            .cloned();
+        match cached {
+            Some(statement) => Ok(statement),
+            None => {
+                let statement = conn.client.prepare(query).await?;
+                conn.statements
+                    .lock()
+                    .expect("statement cache lock is poisoned")
+                    .insert(query.to_string(), statement.clone());
+                Ok(statement)
+            }
+        }
+    }
+
+    pub async fn query_cached(
+        &self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<Row>> {
+        let statement = self.prepare_cached(query).await?;
+        Ok(self.query(&statement, params).await?)
+    }
+
+    pub async fn query_opt_cached(
+        &self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Option<Row>> {
+        let statement = self.prepare_cached(query).await?;
+        Ok(self.query_opt(&statement, params).await?)
+    }
+
+    pub async fn query_one_cached(
+        &self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Row> {
+        let statement = self.prepare_cached(query).await?;
+        Ok(self.query_one(&statement, params).await?)
+    }
+
+    pub async fn execute_cached(
+        &self,
+        query: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<u64> {
+        let statement = self.prepare_cached(query).await?;
+        Ok(self.execute(&statement, params).await?)
+    }
+}
+
+impl<'p> Deref for PooledClient<'p> {
+    type Target = psql::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.conn().client
+    }
+}
+
+impl<'p> Drop for PooledClient<'p> {
+    fn drop(&mut self) {
+        if let Some(mut conn) = self.conn.take() {
+            if !conn.client.is_closed() {
+                conn.checked = Instant::now();
+                self.pool
+                    .idle
+                    .lock()
+                    .expect("pool lock is poisoned")
+                    .push(conn);
+            }
+        }
+    }
+}
@@ -1,15 +1,24 @@
+use actix_web::http::StatusCode;
 use actix_web::ResponseError;
 use ron::de::Error as RonError;
+use ron::ser::Error as RonSerError;
 use serde_json::Error as JsonError;
+use serde_yaml::Error as YamlError;
 use std::fmt::{self, Display};
 use std::io::Error as IoError;
 use std::num::ParseIntError;
 use tokio_postgres::Error as DbError;
+use toml::de::Error as TomlError;
+use zip::result::ZipError;
 
 #[derive(Debug)]
 pub enum Error {
     Ron(RonError),
+    RonSer(RonSerError),
     Json(JsonError),
+    Yaml(YamlError),
+    Toml(TomlError),
+    Zip(ZipError),
     Db(DbError),
     Io(IoError),
     Template(ParseIntError),
@@ -21,17 +30,30 @@ pub enum Error {
     Argon2(argon2::Error),
     AuthenticationFailed,
     AuthorizationFailed,
+    AccountDisabled,
     PasswordMismatch,
     InvalidCreateUser(String),
+    Conflict(String),
+    InvalidToken(String),
+    Csrf(String),
+    InvalidQuery(String),
     InvalidPattern(String),
     AsyncRecursion,
+    MissingTranslations(usize),
+    DuplicateUsernames(Vec<String>),
+    Tls(String),
+    PoolTimeout,
 }
 
 impl Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Error::Ron(err) => Display::fmt(err, f),
+            Error::RonSer(err) => Display::fmt(err, f),
             Error::Json(err) => Display::fmt(err, f),
+            Error::Yaml(err) => write!(f, "front matter error: {}", err),
+            Error::Toml(err) => write!(f, "front matter error: {}", err),
+            Error::Zip(err) => write!(f, "archive error: {}", err),
             Error::Db(err) => Display::fmt(err, f),
             Error::Io(err) => Display::fmt(err, f),
             Error::Template(err) => write!(f, "template error: {}", err),
@@ -49,12 +71,27 @@ impl Display for Error {
             Error::Argon2(err) => write!(f, "an error occured while trying authenticate: {}", err),
             Error::AuthenticationFailed => write!(f, "authentication failed"),
             Error::AuthorizationFailed => write!(f, "authorization failed"),
+            Error::AccountDisabled => write!(f, "the account is disabled"),
             Error::PasswordMismatch => write!(f, "passwords didn't match"),
             Error::InvalidCreateUser(desc) => {
                 write!(f, "invalid user creation parameter: {}", desc)
             }
+            Error::Conflict(res) => write!(f, "resource already exists: {:?}", res),
+            Error::InvalidToken(desc) => write!(f, "invalid API token parameter: {}", desc),
+            Error::Csrf(desc) => write!(f, "cross-site request rejected: {}", desc),
+            Error::InvalidQuery(desc) => write!(f, "invalid query: {}", desc),
             Error::InvalidPattern(pat) => write!(f, "invalid pattern: {:?}", pat),
             Error::AsyncRecursion => write!(f, "async recursion"),
+            Error::MissingTranslations(count) => {
+                write!(f, "{} translations are missing", count)
+            }
+            Error::DuplicateUsernames(names) => write!(
+                f,
+                "usernames only differing in case, rename all but one of each: {}",
+                names.join(", ")
+            ),
+            Error::Tls(err) => write!(f, "TLS error: {}", err),
+            Error::PoolTimeout => write!(f, "timed out waiting for a database connection"),
         }
     }
 }
@@ -89,12 +126,58 @@ impl From<RonError> for Error {
     }
 }
 
+impl From<RonSerError> for Error {
+    fn from(err: RonSerError) -> Error {
+        Error::RonSer(err)
+    }
+}
+
 impl From<JsonError> for Error {
     fn from(err: JsonError) -> Error {
         Error::Json(err)
     }
 }
 
-impl ResponseError for Error {}
+impl From<YamlError> for Error {
+    fn from(err: YamlError) -> Error {
+        Error::Yaml(err)
+    }
+}
+
+impl From<TomlError> for Error {
+    fn from(err: TomlError) -> Error {
+        Error::Toml(err)
+    }
+}
+
+impl From<ZipError> for Error {
+    fn from(err: ZipError) -> Error {
+        Error::Zip(err)
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(err: native_tls::Error) -> Error {
+        Error::Tls(err.to_string())
+    }
+}
+
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::ResourceNotFound(_) => StatusCode::NOT_FOUND,
+            Error::Io(err) if err.kind() == std::io::ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            Error::IllegalResource(_) => StatusCode::FORBIDDEN,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::AuthenticationFailed => StatusCode::UNAUTHORIZED,
+            Error::AuthorizationFailed => StatusCode::FORBIDDEN,
+            Error::AccountDisabled => StatusCode::FORBIDDEN,
+            Error::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            Error::Csrf(_) => StatusCode::FORBIDDEN,
+            Error::InvalidQuery(_) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
 
 pub type Result<T> = std::result::Result<T, Error>;
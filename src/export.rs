@@ -0,0 +1,271 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use tokio::fs;
+
+use crate::compress::{self, Encoding};
+use crate::assets;
+use crate::config::{AssetConfig, CompressionConfig, ExportConfig};
+use crate::error::Result;
+use crate::i18n::Language;
+use crate::profile;
+use crate::repo::{Article, User};
+use crate::template::{self, Context};
+use crate::web::{self, ServerData};
+
+// renders the whole site as an anonymous visitor into `out`, one directory
+// per language, with pretty URLs, e.g. `/en/impressum/` and `/en/articles/foobar/`
+pub async fn export(data: &ServerData<'_>, out: &Path) -> Result<()> {
+    let config = &data.config.export;
+    let mut urls = Vec::new();
+    let mut articles = data.repo.articles().await?;
+    // newest first, like in the feeds
+    articles.reverse();
+    // the authors who get a page, see `profile::author`
+    let mut authors = Vec::new();
+    for user in data.repo.users().await? {
+        if !user.employee && articles.iter().all(|article| article.author != Some(user.id)) {
+            continue;
+        }
+        // names which need encoding would end up in a directory no web server finds
+        if author_segment(&user) == user.username {
+            authors.push(user.username);
+        }
+    }
+    for code in data.lang.codes() {
+        let lang = match data.lang.get(&code) {
+            Some(lang) => lang,
+            None => continue,
+        };
+        page(data, &lang, out, "public/index.html", &[], "", &mut urls).await?;
+        let mut entries = fs::read_dir("public").await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let file = entry.path();
+            if file.extension() != Some("html".as_ref()) {
+                continue;
+            }
+            let name = file
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if name == "index" || config.dynamic.contains(&name) {
+                continue;
+            }
+            let url = format!("{}/", name);
+            page(data, &lang, out, &file, &[], &url, &mut urls).await?;
+        }
+        for article in &articles {
+            let segment = name(article);
+            let path = web::article_path(&*data.repo, &segment, &code).await?;
+            let url = format!("articles/{}/", segment.trim_end_matches(".md"));
+            let file = "public/articles/template.html";
+            page(data, &lang, out, file, &[path], &url, &mut urls).await?;
+        }
+        for username in &authors {
+            let url = format!("authors/{}/", username);
+            let file = "public/authors/template.html";
+            page(data, &lang, out, file, std::slice::from_ref(username), &url, &mut urls).await?;
+        }
+        let feed = feed(config, &lang, &articles);
+        fs::write(out.join(&code).join("feed.xml"), feed).await?;
+    }
+
+    let mut sitemap = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+                       <urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n"
+        .to_string();
+    for url in &urls {
+        write!(sitemap, "<url><loc>{}{}</loc></url>\n", config.base_url, url).expect("couldn't write to string");
+    }
+    write!(sitemap, "</urlset>\n").expect("couldn't write to string");
+    fs::write(out.join("sitemap.xml"), sitemap).await?;
+
+    // there is no content negotiation without the server
+    let index = format!(
+        "<!doctype html>\n<meta http-equiv=\"refresh\" content=\"0; url=/{0}/\"/>\n<a href=\"/{0}/\">/{0}/</a>\n",
+        data.config.i18n.default_language
+    );
+    fs::write(out.join("index.html"), index).await?;
+
+    copy_assets("public".as_ref(), out, &data.config.assets)?;
+    // the server also serves WebAssembly from the root, see `web::wasm`
+    for entry in std::fs::read_dir("public/frontend")? {
+        let entry = entry?;
+        if entry.path().extension() == Some("wasm".as_ref()) {
+            std::fs::copy(entry.path(), out.join(entry.file_name()))?;
+        }
+    }
+    // for web servers which serve precompressed files, e.g. nginx' `gzip_static`
+    precompress(out, &data.config.compression)
+}
+
+// the name an article is linked by, see `template::href`
+fn name(article: &Article) -> String {
+    match &article.slug {
+        Some(slug) => slug.clone(),
+        None => article.path.trim_start_matches("articles/").to_string(),
+    }
+}
+
+// the name in the link to the page of a user, see `profile::href`
+fn author_segment(user: &User) -> String {
+    let href = profile::href(user);
+    href["/authors/".len()..(href.len() - ".html".len())].to_string()
+}
+
+// renders a template and writes it to `{out}/{code}/{url}index.html`
+async fn page<P: AsRef<Path>>(
+    data: &ServerData<'_>,
+    lang: &Language,
+    out: &Path,
+    file: P,
+    args: &[String],
+    url: &str,
+    urls: &mut Vec<String>,
+) -> Result<()> {
+    let mut body = fs::read_to_string(file).await?;
+    template::search_replace_recursive(&Context::anonymous(), data, lang, &mut body, args)
+        .await?;
+    let body = rewrite_links(&body, lang.code(), &data.config.export);
+    let dir = out.join(lang.code()).join(url);
+    fs::create_dir_all(&dir).await?;
+    fs::write(dir.join("index.html"), body).await?;
+    urls.push(format!("/{}/{}", lang.code(), url));
+    Ok(())
+}
+
+// points the links of a rendered page at the exported pages
+fn rewrite_links(html: &str, code: &str, config: &ExportConfig) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("href=\"") {
+        let start = start + "href=\"".len();
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find('"').unwrap_or(rest.len());
+        output.push_str(&link(&rest[..end], code, config));
+        rest = &rest[end..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn link(href: &str, code: &str, config: &ExportConfig) -> String {
+    if href == "/" || href == "/index.html" {
+        return format!("/{}/", code);
+    }
+    if let Some(rest) = href.strip_prefix("/lang/") {
+        // switching the language leads to the same page in the other mirror
+        let idx = match rest.find(".html") {
+            Some(idx) => idx,
+            None => return href.to_string(),
+        };
+        return match rest[idx..].split_once("?redirect=") {
+            Some((_, redirect)) => link(redirect, &rest[..idx], config),
+            None => format!("/{}/", &rest[..idx]),
+        };
+    }
+    if let Some(name) = href.strip_prefix("/articles/") {
+        return format!("/{}/articles/{}/", code, name.trim_end_matches(".md"));
+    }
+    let author = href
+        .strip_prefix("/authors/")
+        .and_then(|name| name.strip_suffix(".html"));
+    if let Some(name) = author {
+        return format!("/{}/authors/{}/", code, name);
+    }
+    match href
+        .strip_prefix('/')
+        .and_then(|name| name.strip_suffix(".html"))
+    {
+        Some(name) if !name.contains('/') => {
+            if config.dynamic.iter().any(|page| page == name) {
+                href.to_string()
+            } else {
+                format!("/{}/{}/", code, name)
+            }
+        }
+        _ => href.to_string(),
+    }
+}
+
+// an Atom feed of the articles written in a language or in none in particular
+fn feed(config: &ExportConfig, lang: &Language, articles: &[Article]) -> String {
+    let code = lang.code();
+    let base = format!("{}/{}/", config.base_url, code);
+    let articles = articles
+        .iter()
+        .filter(|article| match &article.lang {
+            Some(lang) => lang == code,
+            None => true,
+        })
+        .collect::<Vec<_>>();
+    let updated = articles
+        .first()
+        .map(|article| article.date.as_str())
+        .unwrap_or("1970-01-01");
+    let mut feed = String::new();
+    write!(feed, "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n").expect("couldn't write to string");
+    write!(feed, "<feed xmlns=\"http://www.w3.org/2005/Atom\" xml:lang=\"{}\">\n", code).expect("couldn't write to string");
+    write!(feed, "<title>{}</title>\n", template::escape(&lang.t("title"))).expect("couldn't write to string");
+    write!(feed, "<link href=\"{}\"/>\n", base).expect("couldn't write to string");
+    write!(feed, "<link rel=\"self\" href=\"{}feed.xml\"/>\n", base).expect("couldn't write to string");
+    write!(feed, "<id>{}</id>\n", base).expect("couldn't write to string");
+    write!(feed, "<updated>{}T00:00:00Z</updated>\n", updated).expect("couldn't write to string");
+    for article in articles {
+        let href = format!("{}articles/{}/", base, name(article).trim_end_matches(".md"));
+        write!(feed, "<entry>\n").expect("couldn't write to string");
+        write!(feed, "<title>{}</title>\n", template::escape(&article.title)).expect("couldn't write to string");
+        write!(feed, "<link href=\"{}\"/>\n", href).expect("couldn't write to string");
+        write!(feed, "<id>{}</id>\n", href).expect("couldn't write to string");
+        write!(feed, "<updated>{}T00:00:00Z</updated>\n", article.date).expect("couldn't write to string");
+        if let Some(summary) = &article.summary {
+            write!(feed, "<summary>{}</summary>\n", template::escape(summary)).expect("couldn't write to string");
+        }
+        write!(feed, "</entry>\n").expect("couldn't write to string");
+    }
+    write!(feed, "</feed>\n").expect("couldn't write to string");
+    feed
+}
+
+// copies every file the server would send as is, see `assets::servable`
+fn copy_assets(from: &Path, to: &Path, config: &AssetConfig) -> Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let path = entry.path();
+        let target = to.join(entry.file_name());
+        if path.is_dir() {
+            copy_assets(&path, &target, config)?;
+        } else if assets::servable(&path, config) {
+            std::fs::create_dir_all(to)?;
+            std::fs::copy(&path, &target)?;
+        }
+    }
+    Ok(())
+}
+
+// writes `.gz` and `.br` variants next to every compressible file
+fn precompress(dir: &Path, config: &CompressionConfig) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            precompress(&path, config)?;
+            continue;
+        }
+        if !compress::compressible(&path) {
+            continue;
+        }
+        let body = std::fs::read(&path)?;
+        if body.len() < config.min_size {
+            continue;
+        }
+        for (encoding, level) in &[
+            (Encoding::Gzip, config.gzip_level),
+            (Encoding::Brotli, config.brotli_quality),
+        ] {
+            let mut file = path.clone().into_os_string();
+            file.push(encoding.suffix());
+            std::fs::write(file, encoding.compress(&body, *level)?)?;
+        }
+    }
+    Ok(())
+}
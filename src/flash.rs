@@ -0,0 +1,153 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::{HttpMessage, HttpRequest};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use sha2::{Digest, Sha512};
+
+// a notice for the page after a redirect, e.g. that the password was changed
+// the cookie holds the l10n key of the message, signed so it can't be made up
+const COOKIE: &str = "flash";
+
+// the message sent along with the request, see `message`
+#[derive(Debug, Clone)]
+struct Incoming(String);
+
+// the message for the next page, see `set`
+#[derive(Debug, Clone)]
+struct Outgoing(&'static str);
+
+// the l10n key of the message to show on this page
+pub fn message(req: &HttpRequest) -> Option<String> {
+    req.extensions()
+        .get::<Incoming>()
+        .map(|message| message.0.clone())
+}
+
+// shows the message with the l10n key `key` on the next page that is rendered
+pub fn set(req: &HttpRequest, key: &'static str) {
+    req.extensions_mut().insert(Outgoing(key));
+}
+
+fn cookie(value: &'static str, secure: bool) -> Cookie<'static> {
+    Cookie::build(COOKIE, value)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(secure)
+        .finish()
+}
+
+fn valid(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .bytes()
+            .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit() || byte == b'_')
+}
+
+// signs the cookie with a key derived from `secret`, which can have any length
+pub struct Flash {
+    key: Key,
+    secure: bool,
+}
+
+impl Flash {
+    // `secure` cookies are only sent over TLS
+    pub fn new(secret: &str, secure: bool) -> Self {
+        Self {
+            key: Key::from_master(&Sha512::digest(secret.as_bytes())),
+            secure,
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Flash
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = FlashMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(FlashMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            key: Rc::new(self.key.clone()),
+            secure: self.secure,
+        })
+    }
+}
+
+pub struct FlashMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    key: Rc<Key>,
+    secure: bool,
+}
+
+impl<S, B> Service for FlashMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let key = self.key.clone();
+        let secure = self.secure;
+        Box::pin(async move {
+            let sent = req.cookie(COOKIE).is_some();
+            let incoming = req.cookie(COOKIE).and_then(|cookie| {
+                let mut jar = CookieJar::new();
+                jar.add_original(cookie.into_owned());
+                jar.signed(&key).get(COOKIE)
+            });
+            if let Some(cookie) = incoming.filter(|cookie| valid(cookie.value())) {
+                req.extensions_mut()
+                    .insert(Incoming(cookie.value().to_string()));
+            }
+            // the service mustn't stay borrowed while the handler runs
+            let future = service.borrow_mut().call(req);
+            let mut res = future.await?;
+            let outgoing = res.request().extensions().get::<Outgoing>().cloned();
+            let page = res
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.starts_with("text/html"));
+            let mut jar = CookieJar::new();
+            match outgoing {
+                Some(Outgoing(message)) => jar.signed(&key).add(cookie(message, secure)),
+                // the message is gone once a page was shown, scripts and styles don't count
+                None if sent && page => {
+                    let cookie = cookie("", secure);
+                    jar.add_original(cookie.clone());
+                    jar.remove(cookie);
+                }
+                None => {}
+            }
+            for cookie in jar.delta() {
+                res.response_mut().add_cookie(cookie)?;
+            }
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,110 @@
+use std::fmt::{self, Display, Write};
+
+use actix_identity::Identity;
+use actix_web::http::{header, StatusCode};
+use actix_web::{HttpRequest, HttpResponse};
+use tokio::fs;
+
+use crate::error::Result;
+use crate::i18n::Language;
+use crate::template::{self, Context};
+use crate::web::ServerData;
+
+// a message about a field, the l10n key with its parameters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub key: &'static str,
+    pub params: Vec<(&'static str, String)>,
+}
+
+impl FieldError {
+    pub fn new(key: &'static str) -> Self {
+        Self {
+            key,
+            params: Vec::new(),
+        }
+    }
+
+    pub fn param<T: ToString>(mut self, name: &'static str, value: T) -> Self {
+        self.params.push((name, value.to_string()));
+        self
+    }
+
+    // the pattern which renders the message, e.g. `{{{l10n(key, count=3)}}}`
+    pub(crate) fn pattern(&self) -> String {
+        let mut pattern = format!("{{{{{{l10n({}", self.key);
+        for (name, value) in &self.params {
+            write!(pattern, ", {}={}", name, value).expect("couldn't write to string");
+        }
+        pattern.push_str(")}}}");
+        pattern
+    }
+}
+
+impl Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.key)
+    }
+}
+
+// what was submitted to a form and what was wrong with it, to render the form again
+// see `Pattern::Value` and `Pattern::FieldError`
+#[derive(Debug, Clone, Default)]
+pub struct Form {
+    values: Vec<(String, String)>,
+    errors: Vec<(String, FieldError)>,
+}
+
+impl Form {
+    // passwords are never sent back
+    pub fn value<T: ToString>(mut self, field: &str, value: T) -> Self {
+        self.values.push((field.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn error(&mut self, field: &str, error: FieldError) {
+        self.errors.push((field.to_string(), error));
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+
+    pub fn value_of(&self, field: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn errors_of<'a>(&'a self, field: &'a str) -> impl Iterator<Item = &'a FieldError> + 'a {
+        self.errors
+            .iter()
+            .filter(move |(name, _)| name == field)
+            .map(|(_, error)| error)
+    }
+}
+
+// the page at `path` again, with what was entered and what was wrong with it
+pub async fn redisplay(
+    path: &str,
+    req: &HttpRequest,
+    identity: &Identity,
+    data: &ServerData<'_>,
+    lang: &Language,
+    form: Form,
+    status: StatusCode,
+) -> Result<HttpResponse> {
+    let mut body = fs::read_to_string(path).await?;
+    template::search_replace_recursive(
+        &Context::new(req, identity).with_form(form),
+        data,
+        lang,
+        &mut body,
+        &[],
+    )
+    .await?;
+    Ok(HttpResponse::build(status)
+        .header(header::CONTENT_TYPE, "text/html")
+        .body(body))
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+
+const YAML_FENCE: &str = "---";
+const TOML_FENCE: &str = "+++";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrontMatter {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub cover: Option<String>,
+    // publish date, `yyyy-mm-dd`
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub slug: Option<String>,
+}
+
+impl FrontMatter {
+    fn from_yaml(src: &str) -> Result<Self> {
+        Ok(serde_yaml::from_str(src)?)
+    }
+
+    fn from_toml(src: &str) -> Result<Self> {
+        // TOML has a native date type, which doesn't deserialize into a `String`
+        let mut value: toml::Value = toml::from_str(src)?;
+        if let Some(table) = value.as_table_mut() {
+            let date = match table.get("date") {
+                Some(toml::Value::Datetime(date)) => Some(date.to_string()),
+                _ => None,
+            };
+            if let Some(date) = date {
+                table.insert("date".to_string(), toml::Value::String(date));
+            }
+        }
+        Ok(value.try_into()?)
+    }
+}
+
+// finds the closing fence of a front matter block
+// returns the front matter source and the rest of the document
+fn fenced<'a>(text: &'a str, fence: &str) -> Option<(&'a str, &'a str)> {
+    let mut start = 0;
+    let mut src = None;
+    while start < text.len() {
+        let end = text[start..]
+            .find('\n')
+            .map(|idx| start + idx + 1)
+            .unwrap_or_else(|| text.len());
+        let line = text[start..end].trim_end();
+        match src {
+            None if line != fence => return None,
+            None => src = Some(end),
+            Some(src) if line == fence => return Some((&text[src..start], &text[end..])),
+            Some(_) => {}
+        }
+        start = end;
+    }
+    None
+}
+
+// splits an article into its front matter (YAML between `---` or TOML
+// between `+++`) and the Markdown body
+pub fn split(text: &str) -> Result<(Option<FrontMatter>, &str)> {
+    if let Some((src, body)) = fenced(text, YAML_FENCE) {
+        Ok((Some(FrontMatter::from_yaml(src)?), body))
+    } else if let Some((src, body)) = fenced(text, TOML_FENCE) {
+        Ok((Some(FrontMatter::from_toml(src)?), body))
+    } else {
+        Ok((None, text))
+    }
+}
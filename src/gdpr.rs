@@ -0,0 +1,294 @@
+use std::io::{Cursor, ErrorKind, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use actix_identity::Identity;
+use actix_web::{get, http, post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::fs;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+use crate::audit::{timestamp, Action, Event};
+use crate::auth;
+use crate::error::{Error, Result};
+use crate::i18n::Lang;
+use crate::repo::{self, AuditFilter, User};
+use crate::template::{self, Context};
+use crate::users;
+use crate::web::ServerData;
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteAccountData {
+    password: String,
+}
+
+// the text of a file, `None` if it is gone
+async fn read(path: &str) -> Result<Option<String>> {
+    match fs::read_to_string(path).await {
+        Ok(text) => Ok(Some(text)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// everything stored about `user`, the password hash aside
+// also returns the markdown of their articles and drafts by its name in the ZIP archive
+async fn personal_data(
+    data: &ServerData<'_>,
+    user: &User,
+) -> Result<(Value, Vec<(String, String)>)> {
+    let mut files = Vec::new();
+
+    let mut articles = Vec::new();
+    for article in data.repo.articles().await? {
+        if article.author != Some(user.id) {
+            continue;
+        }
+        let content = read(&format!("public/{}", article.path)).await?;
+        if let Some(content) = &content {
+            files.push((article.path.clone(), content.clone()));
+        }
+        articles.push(json!({
+            "id": article.id,
+            "path": article.path,
+            "title": article.title,
+            "date": article.date,
+            "modified": timestamp(article.modified),
+            "summary": article.summary,
+            "tags": article.tags,
+            "lang": article.lang,
+            "cover": article.cover,
+            "slug": article.slug,
+            "translation_of": article.translation_of,
+            "content": content,
+        }));
+    }
+
+    let mut drafts = Vec::new();
+    for draft in data.repo.drafts(user.id).await? {
+        let content = read(&draft.path).await?;
+        if let Some(content) = &content {
+            let name = Path::new(&draft.path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| format!("{}.md", draft.id));
+            files.push((format!("drafts/{}", name), content.clone()));
+        }
+        drafts.push(json!({
+            "id": draft.id,
+            "path": draft.path,
+            "title": draft.title,
+            "content": content,
+        }));
+    }
+
+    let tokens = data
+        .repo
+        .tokens(user.id)
+        .await?
+        .into_iter()
+        .map(|token| {
+            json!({
+                "id": token.id,
+                "name": token.name,
+                "scopes": token.scopes,
+                "created": timestamp(token.created),
+                "expires": token.expires.map(timestamp),
+                "last_used": token.last_used.map(timestamp),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // what they did and what was done to their account, with the addresses
+    let mut entries = data
+        .repo
+        .audit_log(&AuditFilter {
+            actor: Some(user.id),
+            ..AuditFilter::default()
+        })
+        .await?;
+    let target = format!("user {}", user.id);
+    let about = data
+        .repo
+        .audit_log(&AuditFilter {
+            target: Some(target.clone()),
+            ..AuditFilter::default()
+        })
+        .await?;
+    entries.extend(about.into_iter().filter(|entry| {
+        entry.actor != Some(user.id) && entry.target.as_deref() == Some(target.as_str())
+    }));
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.id));
+    let audit_log = entries
+        .into_iter()
+        .map(|entry| {
+            json!({
+                "time": timestamp(entry.time),
+                "by_me": entry.actor == Some(user.id),
+                "action": entry.action,
+                "target": entry.target,
+                "before": entry.before,
+                "after": entry.after,
+                "ip": entry.ip,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let json = json!({
+        "exported": timestamp(SystemTime::now()),
+        "user": {
+            "id": user.id,
+            "username": user.username,
+            "email": user.email,
+            "firstname": user.firstname,
+            "lastname": user.lastname,
+            "bio": user.bio,
+            "avatar": user.avatar,
+            "website": user.website,
+            "employee": user.employee,
+            "admin": user.admin,
+            "status": user.status.name(),
+            "reset_password": user.reset_password,
+            "delete_after": user.delete_after.map(timestamp),
+        },
+        "articles": articles,
+        "drafts": drafts,
+        "tokens": tokens,
+        "audit_log": audit_log,
+    });
+    Ok((json, files))
+}
+
+// `data.json` with the markdown files next to it
+fn zip(json: &Value, files: &[(String, String)]) -> Result<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file("data.json", FileOptions::default())?;
+    zip.write_all(&serde_json::to_vec_pretty(json)?)?;
+    for (name, content) in files {
+        zip.start_file(name.as_str(), FileOptions::default())?;
+        zip.write_all(content.as_bytes())?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+// the personal data of the logged in user, as JSON or as a ZIP archive
+#[get("/account/export.{format}")]
+pub async fn export<'a>(
+    format: web::Path<String>,
+    req: HttpRequest,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let content_type = match format.as_str() {
+        "json" => "application/json",
+        "zip" => "application/zip",
+        _ => return Err(Error::ResourceNotFound(format!("export.{}", format))),
+    };
+    // tokens don't get this far, the data is only for the user themselves
+    let user = data
+        .user(&identity)
+        .await?
+        .ok_or(Error::AuthenticationFailed)?;
+    let (json, files) = personal_data(&data, &user).await?;
+    let body = if format.as_str() == "zip" {
+        zip(&json, &files)?
+    } else {
+        serde_json::to_vec_pretty(&json)?
+    };
+    Event::new(Some(&user), Action::ExportData)
+        .target(format!("user {}", user.id))
+        .after(format.as_str())
+        .record(&data, &req)
+        .await?;
+    Ok(HttpResponse::Ok()
+        .header(http::header::CONTENT_TYPE, content_type)
+        .header(
+            http::header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"personal-data.{}\"", format),
+        )
+        .header(http::header::CACHE_CONTROL, "no-store")
+        .body(body))
+}
+
+// the account is deleted once the grace period is over, logging in before that keeps it
+#[post("/account/delete.html")]
+pub async fn request_deletion<'a>(
+    delete_data: web::Form<DeleteAccountData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(user) => {
+            // an open session isn't enough
+            let user = auth::query(&user.username, &delete_data.password, &data).await?;
+            let grace = Duration::from_secs(data.config.accounts.deletion_grace_days * DAY);
+            let time = SystemTime::now() + grace;
+            data.repo.set_delete_after(user.id, Some(time)).await?;
+            Event::new(Some(&user), Action::RequestDeletion)
+                .target(format!("user {}", user.id))
+                .after(timestamp(time))
+                .record(&data, &req)
+                .await?;
+            identity.forget();
+            let mut body = fs::read_to_string("private/deletion.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[repo::date(time)],
+            )
+            .await?;
+            Ok(HttpResponse::Ok()
+                .header(http::header::CONTENT_TYPE, "text/html")
+                .body(body))
+        }
+        None => {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            Ok(HttpResponse::Forbidden().body(body))
+        }
+    }
+}
+
+// deletes an account whose grace period is over
+// the articles stay on the site, anonymized, see `Repository::delete_user`
+pub(crate) async fn delete(data: &ServerData<'_>, user: &User) -> Result<()> {
+    users::delete_user(data, user, None).await?;
+    Event::new(None, Action::DeleteUser)
+        .target(format!("user {}", user.id))
+        .before(&user.username)
+        .after("requested by the user")
+        .record_by_server(data)
+        .await
+}
+
+// deletes every account whose grace period is over, returns how many there were
+pub async fn purge(data: &ServerData<'_>) -> Result<usize> {
+    let now = SystemTime::now();
+    let expired = data
+        .repo
+        .users()
+        .await?
+        .into_iter()
+        .filter(|user| user.delete_after.is_some_and(|time| time <= now))
+        .collect::<Vec<_>>();
+    for user in &expired {
+        delete(data, user).await?;
+    }
+    Ok(expired.len())
+}
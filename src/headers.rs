@@ -0,0 +1,218 @@
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use actix_web::{post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
+use futures::future::{ok, LocalBoxFuture, Ready};
+use serde_json::Value;
+
+use crate::config::HeadersConfig;
+use crate::error::Result;
+use crate::token;
+
+// where browsers send the violations of the policy, see `report`
+pub const REPORT_PATH: &str = "/csp-report";
+
+// the nonce of the current request, see `nonce`
+#[derive(Debug, Clone)]
+struct Nonce(String);
+
+// the nonce inline scripts need to run, a new one for every request
+pub fn nonce(req: &HttpRequest) -> Option<String> {
+    req.extensions().get::<Nonce>().map(|nonce| nonce.0.clone())
+}
+
+// the headers of a path, with the overrides of the first matching route
+struct Headers<'a> {
+    csp: &'a str,
+    frame_ancestors: &'a str,
+    referrer_policy: &'a str,
+    permissions_policy: &'a str,
+}
+
+impl<'a> Headers<'a> {
+    fn new(config: &'a HeadersConfig, path: &str) -> Self {
+        let mut headers = Self {
+            csp: &config.csp,
+            frame_ancestors: &config.frame_ancestors,
+            referrer_policy: &config.referrer_policy,
+            permissions_policy: &config.permissions_policy,
+        };
+        if let Some(route) = config
+            .routes
+            .iter()
+            .find(|route| path.starts_with(&route.prefix))
+        {
+            if let Some(csp) = &route.csp {
+                headers.csp = csp;
+            }
+            if let Some(frame_ancestors) = &route.frame_ancestors {
+                headers.frame_ancestors = frame_ancestors;
+            }
+            if let Some(referrer_policy) = &route.referrer_policy {
+                headers.referrer_policy = referrer_policy;
+            }
+            if let Some(permissions_policy) = &route.permissions_policy {
+                headers.permissions_policy = permissions_policy;
+            }
+        }
+        headers
+    }
+
+    fn csp(&self, nonce: &str) -> String {
+        let mut csp = self.csp.replace("{nonce}", nonce);
+        if !self.frame_ancestors.is_empty() {
+            if !csp.is_empty() {
+                csp.push_str("; ");
+            }
+            csp.push_str("frame-ancestors ");
+            csp.push_str(self.frame_ancestors);
+        }
+        csp
+    }
+}
+
+// sets a header unless the handler already did, empty values are left out
+fn set(map: &mut HeaderMap, name: HeaderName, value: &str) {
+    if value.is_empty() || map.contains_key(&name) {
+        return;
+    }
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            map.insert(name, value);
+        }
+        Err(_) => eprintln!("invalid value for {}: {:?}", name, value),
+    }
+}
+
+// adds the security headers of `path` to a response
+fn secure(map: &mut HeaderMap, config: &HeadersConfig, path: &str, nonce: &str) {
+    let headers = Headers::new(config, path);
+    let csp = headers.csp(nonce);
+    set(map, header::CONTENT_SECURITY_POLICY, &csp);
+    set(map, header::X_CONTENT_TYPE_OPTIONS, "nosniff");
+    set(map, header::REFERRER_POLICY, headers.referrer_policy);
+    set(
+        map,
+        HeaderName::from_static("permissions-policy"),
+        headers.permissions_policy,
+    );
+}
+
+// adds the Content-Security-Policy and the other security headers to every response
+pub struct SecurityHeaders {
+    config: Rc<HeadersConfig>,
+}
+
+impl SecurityHeaders {
+    pub fn new(config: HeadersConfig) -> Self {
+        Self {
+            config: Rc::new(config),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for SecurityHeaders
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = Ready<std::result::Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SecurityHeadersMiddleware {
+            service,
+            config: self.config.clone(),
+        })
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: S,
+    config: Rc<HeadersConfig>,
+}
+
+impl<S, B> Service for SecurityHeadersMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error>
+        + 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, std::result::Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<std::result::Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let bytes: [u8; 16] = rand::random();
+        let nonce = token::hex(&bytes);
+        req.extensions_mut().insert(Nonce(nonce.clone()));
+        let config = self.config.clone();
+        let path = req.path().to_string();
+        let future = self.service.call(req);
+        Box::pin(async move {
+            match future.await {
+                Ok(mut res) => {
+                    secure(res.headers_mut(), &config, &path, &nonce);
+                    Ok(res)
+                }
+                // errors get the headers as well
+                Err(err) => {
+                    let mut res = err.as_response_error().error_response();
+                    secure(res.headers_mut(), &config, &path, &nonce);
+                    Err(InternalError::from_response(err, res).into())
+                }
+            }
+        })
+    }
+}
+
+// `{"csp-report": {...}}` from `report-uri`, or a list of reports from the Reporting API
+fn log_violations(body: &Value) {
+    let violations: Vec<&Value> = match body {
+        Value::Array(entries) => entries
+            .iter()
+            .filter(|entry| entry["type"] == "csp-violation")
+            .map(|entry| &entry["body"])
+            .collect(),
+        single => vec![&single["csp-report"]],
+    };
+    for violation in violations {
+        let field = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| violation[*name].as_str())
+                .unwrap_or("?")
+                .to_string()
+        };
+        eprintln!(
+            "CSP violation on {}: {} blocked {}",
+            field(&["document-uri", "documentURL"]),
+            field(&["effective-directive", "violated-directive", "effectiveDirective"]),
+            field(&["blocked-uri", "blockedURL"]),
+        );
+    }
+}
+
+// browsers post here on their own, so it is exempt from the CSRF check
+#[post("/csp-report")]
+pub async fn report(body: web::Bytes) -> Result<impl Responder> {
+    match serde_json::from_slice::<Value>(&body) {
+        Ok(value) => log_violations(&value),
+        Err(err) => eprintln!("invalid CSP report: {}", err),
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
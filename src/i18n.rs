@@ -1,27 +1,156 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt::Debug;
-use std::hash::Hash;
 use std::ops::Index;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
-use std::borrow::Borrow;
+use std::borrow::{Borrow, Cow};
 
+use futures::future::{ok, Ready};
+use ron::ser::PrettyConfig;
 use serde::{Deserialize, Serialize};
+use tokio::fs;
 
+use actix_http::HttpMessage;
 use actix_identity::Identity;
 use actix_web::cookie::Cookie;
-use actix_web::{get, http, web, HttpRequest, HttpResponse, Responder};
+use actix_web::dev::Payload;
+use actix_web::{get, http, web, FromRequest, HttpRequest, HttpResponse, Responder};
 
+use crate::config::I18nConfig;
 use crate::error::Result;
+use crate::repo::Repository;
+use crate::template;
 use crate::web::ServerData;
 
+// CLDR plural categories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Plural {
+    Zero,
+    One,
+    Two,
+    Few,
+    Many,
+    Other,
+}
+
+impl Plural {
+    pub const ALL: [Plural; 6] = [
+        Plural::Zero,
+        Plural::One,
+        Plural::Two,
+        Plural::Few,
+        Plural::Many,
+        Plural::Other,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Plural::Zero => "zero",
+            Plural::One => "one",
+            Plural::Two => "two",
+            Plural::Few => "few",
+            Plural::Many => "many",
+            Plural::Other => "other",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Plural> {
+        Plural::ALL.iter().copied().find(|plural| plural.name() == name)
+    }
+
+    // the plural category of an integer in the given language
+    // see https://unicode-org.github.io/cldr-staging/charts/latest/supplemental/language_plural_rules.html
+    pub fn of(code: &str, n: i64) -> Plural {
+        let n = n.abs();
+        match code {
+            "pl" => {
+                if n == 1 {
+                    Plural::One
+                } else if (2..=4).contains(&(n % 10)) && !(12..=14).contains(&(n % 100)) {
+                    Plural::Few
+                } else {
+                    Plural::Many
+                }
+            }
+            "fr" => {
+                if n == 0 || n == 1 {
+                    Plural::One
+                } else {
+                    Plural::Other
+                }
+            }
+            // de, en and most other germanic languages
+            _ => {
+                if n == 1 {
+                    Plural::One
+                } else {
+                    Plural::Other
+                }
+            }
+        }
+    }
+}
+
+// a single translation, either plain text or one text per plural category
+// both may contain `{name}` placeholders
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Message {
+    Text(String),
+    Plural(BTreeMap<Plural, String>),
+}
+
+impl Message {
+    // the text without any count, `other` for plural messages
+    pub fn as_str(&self) -> &str {
+        match self {
+            Message::Text(text) => text,
+            Message::Plural(forms) => forms
+                .get(&Plural::Other)
+                .or_else(|| forms.values().next_back())
+                .map(String::as_str)
+                .unwrap_or(""),
+        }
+    }
+
+    fn select(&self, code: &str, count: Option<i64>) -> &str {
+        match (self, count) {
+            (Message::Plural(forms), Some(count)) => {
+                // an explicit `zero` form is preferred, even if the language has no such category
+                let explicit = if count == 0 {
+                    forms.get(&Plural::Zero)
+                } else {
+                    None
+                };
+                explicit
+                    .or_else(|| forms.get(&Plural::of(code, count)))
+                    .map(String::as_str)
+                    .unwrap_or_else(|| self.as_str())
+            }
+            _ => self.as_str(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Language {
     code: String,
     language: String,
-    t9n: HashMap<String, String>,
+    t9n: BTreeMap<String, Message>,
 }
 
 impl Language {
+    pub fn new(code: String, language: String) -> Self {
+        Self {
+            code,
+            language,
+            t9n: BTreeMap::new(),
+        }
+    }
+
     pub fn code(&self) -> &str {
         &self.code
     }
@@ -29,38 +158,407 @@ impl Language {
     pub fn language(&self) -> &str {
         &self.language
     }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.t9n.get(key).map(Message::as_str)
+    }
+
+    pub fn message(&self, key: &str) -> Option<&Message> {
+        self.t9n.get(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.t9n.keys().map(String::as_str)
+    }
+
+    pub fn insert(&mut self, key: String, message: Message) {
+        self.t9n.insert(key, message);
+    }
+
+    // sets a plain translation, or a single plural form of one
+    pub fn set(&mut self, key: &str, plural: Option<Plural>, text: String) {
+        match plural {
+            None => self.insert(key.to_string(), Message::Text(text)),
+            Some(plural) => {
+                let message = self
+                    .t9n
+                    .entry(key.to_string())
+                    .or_insert_with(|| Message::Plural(BTreeMap::new()));
+                if let Message::Text(other) = message {
+                    let mut forms = BTreeMap::new();
+                    forms.insert(Plural::Other, other.clone());
+                    *message = Message::Plural(forms);
+                }
+                if let Message::Plural(forms) = message {
+                    forms.insert(plural, text);
+                }
+            }
+        }
+    }
+
+    // the translation of `key`, or the key itself if there is none
+    pub fn t<'b>(&'b self, key: &'b str) -> Cow<'b, str> {
+        match self.get(key) {
+            Some(t9n) => Cow::Borrowed(t9n),
+            None => {
+                eprintln!("no l10n found for key {:?} in {:?}", key, self.code);
+                Cow::Borrowed(key)
+            }
+        }
+    }
+
+    // the translation of `key` with its placeholders filled in from `args`
+    // an integer `count` argument selects the plural form
+    pub fn format(&self, key: &str, args: &[(&str, &str)]) -> String {
+        match self.t9n.get(key) {
+            Some(message) => {
+                let count = args
+                    .iter()
+                    .find(|(name, _)| *name == "count")
+                    .and_then(|(_, count)| count.trim().parse::<i64>().ok());
+                template::fill(message.select(&self.code, count), args)
+            }
+            None => self.t(key).into_owned(),
+        }
+    }
+
+    // copies the keys this language is missing from `other`
+    fn fill_from(&mut self, other: &Language) {
+        for (key, t9n) in &other.t9n {
+            self.t9n
+                .entry(key.clone())
+                .or_insert_with(|| t9n.clone());
+        }
+    }
 }
 
 impl<'a, S> Index<&'a S> for Language
 where
-    S: Eq + Hash + Debug,
+    S: Ord + Debug + ?Sized,
     String: Borrow<S>,
 {
     type Output = str;
 
     fn index(&self, key: &'a S) -> &Self::Output {
-        self.t9n.get(key).unwrap_or_else(|| panic!("no l10n found for key {:?}", key))
+        self.t9n.get(key).map(Message::as_str).unwrap_or_else(|| {
+            eprintln!("no l10n found for key {:?} in {:?}", key, self.code);
+            ""
+        })
+    }
+}
+
+// fills in the keys missing from each language along its fallback chain
+// languages without a configured chain fall back to the default language
+pub fn resolve_fallbacks(langs: &mut HashMap<String, Language>, config: &I18nConfig) {
+    let originals = langs.clone();
+    let default = vec![config.default_language.clone()];
+    for (code, language) in langs.iter_mut() {
+        let chain = config.fallbacks.get(code).unwrap_or(&default);
+        for fallback in chain {
+            if let Some(fallback) = originals.get(fallback) {
+                language.fill_from(fallback);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Catalog {
+    // as written in the RON files, used for editing
+    raw: BTreeMap<String, Language>,
+    // with the fallback chains resolved, used for rendering
+    resolved: HashMap<String, Arc<Language>>,
+    // where each language is stored, from the `l10n` table
+    paths: HashMap<String, String>,
+}
+
+// all loaded languages, shared between the workers so they can be
+// edited and reloaded while the server is running
+#[derive(Debug, Clone, Default)]
+pub struct Languages(Arc<RwLock<Catalog>>);
+
+impl Languages {
+    fn read(&self) -> RwLockReadGuard<'_, Catalog> {
+        self.0.read().expect("language catalog lock is poisoned")
+    }
+
+    fn set(
+        &self,
+        raw: BTreeMap<String, Language>,
+        paths: HashMap<String, String>,
+        config: &I18nConfig,
+    ) {
+        let mut resolved = raw
+            .iter()
+            .map(|(code, language)| (code.clone(), language.clone()))
+            .collect();
+        resolve_fallbacks(&mut resolved, config);
+        let resolved = resolved
+            .into_iter()
+            .map(|(code, language)| (code, Arc::new(language)))
+            .collect();
+        *self.0.write().expect("language catalog lock is poisoned") = Catalog {
+            raw,
+            resolved,
+            paths,
+        };
+    }
+
+    // (re)loads every registered language
+    pub async fn load(&self, repo: &dyn Repository, config: &I18nConfig) -> Result<()> {
+        let mut raw = BTreeMap::new();
+        let mut paths = HashMap::new();
+        for (key, path) in repo.languages().await? {
+            let text = fs::read_to_string(&path).await?;
+            let language: Language = ron::de::from_str(&text)?;
+            raw.insert(key.clone(), language);
+            paths.insert(key, path);
+        }
+        self.set(raw, paths, config);
+        Ok(())
+    }
+
+    pub fn get(&self, code: &str) -> Option<Arc<Language>> {
+        self.read().resolved.get(code).cloned()
+    }
+
+    pub fn contains(&self, code: &str) -> bool {
+        self.read().resolved.contains_key(code)
+    }
+
+    // all language codes, sorted
+    pub fn codes(&self) -> Vec<String> {
+        self.read().raw.keys().cloned().collect()
+    }
+
+    // any language at all, for when nothing else matches
+    pub fn any(&self) -> Option<Arc<Language>> {
+        self.read().resolved.values().next().cloned()
     }
+
+    // the languages without fallbacks, as they are stored
+    pub fn raw(&self) -> BTreeMap<String, Language> {
+        self.read().raw.clone()
+    }
+
+    // writes the languages back to their RON files and reloads them
+    // languages which aren't loaded yet are ignored
+    pub async fn save(
+        &self,
+        langs: BTreeMap<String, Language>,
+        config: &I18nConfig,
+    ) -> Result<()> {
+        let paths = self.read().paths.clone();
+        let mut raw = self.raw();
+        for (code, language) in langs {
+            let path = match paths.get(&code) {
+                Some(path) => path,
+                None => continue,
+            };
+            let text = ron::ser::to_string_pretty(&language, PrettyConfig::default())?;
+            fs::write(path, text).await?;
+            raw.insert(code, language);
+        }
+        self.set(raw, paths, config);
+        Ok(())
+    }
+
+    // creates an empty language in public/l10n/ and registers it
+    pub async fn add(
+        &self,
+        repo: &dyn Repository,
+        language: Language,
+        config: &I18nConfig,
+    ) -> Result<()> {
+        let path = format!("public/l10n/{}.ron", language.code());
+        let text = ron::ser::to_string_pretty(&language, PrettyConfig::default())?;
+        fs::write(&path, text).await?;
+        repo.add_language(language.code(), &path).await?;
+        let (mut raw, mut paths) = {
+            let catalog = self.read();
+            (catalog.raw.clone(), catalog.paths.clone())
+        };
+        paths.insert(language.code().to_string(), path);
+        raw.insert(language.code().to_string(), language);
+        self.set(raw, paths, config);
+        Ok(())
+    }
+}
+
+// every `l10n` pattern referenced in the templates and sources below `root`,
+// with the files it is referenced in
+// comments and keys assembled with `format!`, e.g. `audit_{}`, are skipped
+pub fn referenced_keys<P: AsRef<Path>>(
+    root: P,
+    keys: &mut BTreeMap<String, BTreeSet<PathBuf>>,
+) -> Result<()> {
+    const MARKER: &str = "{{{l10n(";
+    for entry in std::fs::read_dir(root)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            referenced_keys(&path, keys)?;
+            continue;
+        }
+        let source = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("html") | Some("md") => false,
+            Some("rs") => true,
+            _ => continue,
+        };
+        let text = std::fs::read_to_string(&path)?;
+        for line in text.lines() {
+            if source && line.trim_start().starts_with("//") {
+                continue;
+            }
+            let mut rest = line;
+            while let Some(idx) = rest.find(MARKER) {
+                rest = &rest[(idx + MARKER.len())..];
+                let end = rest
+                    .find(|ch: char| !(ch.is_alphanumeric() || ch == '_'))
+                    .unwrap_or(rest.len());
+                if end > 0 && !rest[end..].starts_with('{') {
+                    keys.entry(rest[..end].to_string())
+                        .or_default()
+                        .insert(path.clone());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// the keys referenced below `roots` which a language in `l10n` doesn't translate,
+// as the language code, the key and the files it is referenced in
+pub fn missing_translations<P: AsRef<Path>>(
+    roots: &[P],
+    l10n: P,
+) -> Result<Vec<(String, String, BTreeSet<PathBuf>)>> {
+    let mut keys = BTreeMap::new();
+    for root in roots {
+        referenced_keys(root, &mut keys)?;
+    }
+    let mut missing = Vec::new();
+    for entry in std::fs::read_dir(l10n)? {
+        let path = entry?.path();
+        if path.extension() != Some("ron".as_ref()) {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path)?;
+        let language: Language = ron::de::from_str(&text)?;
+        for (key, files) in &keys {
+            if language.get(key).is_none() {
+                missing.push((language.code().to_string(), key.clone(), files.clone()));
+            }
+        }
+    }
+    Ok(missing)
+}
+
+// the languages a visitor prefers, most preferred first
+// the `lang` cookie comes first, followed by `Accept-Language`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Lang(Vec<String>);
+
+impl Lang {
+    pub fn codes(&self) -> &[String] {
+        &self.0
+    }
+
+    fn push(&mut self, code: &str) {
+        let code = code.trim().to_lowercase();
+        if !code.is_empty() && !self.0.contains(&code) {
+            self.0.push(code);
+        }
+    }
+
+    // parses e.g. `pl, en-GB;q=0.8, en;q=0.7, *;q=0.5`
+    fn push_accept_language(&mut self, header: &str) {
+        let mut ranges = header
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q=")?.parse::<f32>().ok())
+                    .next()
+                    .unwrap_or(1.0);
+                if tag.is_empty() || tag == "*" || quality <= 0.0 {
+                    None
+                } else {
+                    Some((tag, quality))
+                }
+            })
+            .collect::<Vec<_>>();
+        // stable, so ranges with equal quality keep their order
+        ranges.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+        for (tag, _) in ranges {
+            self.push(tag);
+            // `en-GB` is also good enough for `en`
+            if let Some(idx) = tag.find('-') {
+                self.push(&tag[..idx]);
+            }
+        }
+    }
+}
+
+impl FromRequest for Lang {
+    type Error = actix_web::Error;
+    type Future = Ready<std::result::Result<Self, Self::Error>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let mut chosen = Lang::default();
+        if let Some(cookie) = req.cookie("lang") {
+            chosen.push(cookie.value());
+        }
+        let header = req
+            .headers()
+            .get(http::header::ACCEPT_LANGUAGE)
+            .and_then(|header| header.to_str().ok());
+        if let Some(header) = header {
+            chosen.push_accept_language(header);
+        }
+        ok(chosen)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LangData {
+    #[serde(default)]
+    redirect: Option<String>,
+}
+
+// only redirects within the site
+// browsers take `/\host` for `//host`, and `/\t/host` too since they drop tabs and newlines
+fn local(redirect: &str) -> bool {
+    let mut chars = redirect.chars();
+    chars.next() == Some('/')
+        && !matches!(chars.next(), Some('/') | Some('\\'))
+        && !redirect.contains(|ch: char| ch.is_ascii_control())
 }
 
 #[get("/lang/{lang}.html")]
 pub async fn lang<'a>(
+    lang_data: web::Query<LangData>,
     _req: HttpRequest,
     _identity: Identity,
     data: web::Data<ServerData<'a>>,
     info: web::Path<String>,
 ) -> Result<impl Responder> {
-    let lang = info.to_string();
-    if !data.lang.contains_key(&lang) {
+    let code = info.to_string();
+    if !data.lang.contains(&code) {
         return Ok(HttpResponse::BadRequest()
             .finish());
     }
 
-    let mut cookie = Cookie::new("lang", lang);
+    let mut cookie = Cookie::new("lang", code);
     cookie.set_path("/");
     cookie.make_permanent();
+    let location = match &lang_data.redirect {
+        Some(redirect) if local(redirect) => redirect.as_str(),
+        _ => "/",
+    };
     Ok(HttpResponse::SeeOther()
         .cookie(cookie)
-        .header(http::header::LOCATION, "/")
+        .header(http::header::LOCATION, location)
         .finish())
 }
@@ -0,0 +1,138 @@
+use std::future::Future;
+
+use actix_identity::{CookieIdentityPolicy, IdentityService};
+use actix_service::ServiceFactory;
+use actix_web::dev::{Body, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::middleware::{Condition, DefaultHeaders};
+use actix_web::web::FormConfig;
+use actix_web::App;
+use sha2::{Digest, Sha512};
+
+use crate::config::ServerConfig;
+use crate::csrf::Csrf;
+use crate::error::Result;
+use crate::flash::Flash;
+use crate::headers::SecurityHeaders;
+use crate::web::ServerData;
+
+pub mod account;
+pub mod api;
+pub mod assets;
+pub mod audit;
+pub mod auth;
+pub mod cache;
+pub mod compress;
+pub mod config;
+pub mod csrf;
+pub mod db;
+pub mod error;
+pub mod export;
+pub mod flash;
+pub mod form;
+pub mod frontmatter;
+pub mod gdpr;
+pub mod headers;
+pub mod i18n;
+pub mod path;
+pub mod profile;
+pub mod repo;
+pub mod template;
+pub mod term;
+pub mod tls;
+pub mod token;
+pub mod users;
+pub mod validate;
+pub mod web;
+
+// the whole site, with `data` creating the state of each worker
+pub fn app<F, Fut>(
+    data: F,
+    server: &ServerConfig,
+) -> App<
+    impl ServiceFactory<
+        Config = (),
+        Request = ServiceRequest,
+        Response = ServiceResponse<Body>,
+        Error = actix_web::Error,
+        InitError = (),
+    >,
+    Body,
+>
+where
+    F: Fn() -> Fut + 'static,
+    Fut: Future<Output = Result<ServerData<'static>>> + 'static,
+{
+    let hsts = server.tls.as_ref().map(tls::hsts);
+    let secure = hsts.is_some();
+    App::new()
+        .data_factory(data)
+        // translations and articles are larger than the default limit
+        .app_data(FormConfig::default().limit(1 << 20))
+        .wrap(Condition::new(
+            secure,
+            DefaultHeaders::new().header(
+                header::STRICT_TRANSPORT_SECURITY,
+                hsts.unwrap_or_default(),
+            ),
+        ))
+        .wrap(IdentityService::new(
+            // derived from the secret like the key of the flash cookie
+            CookieIdentityPolicy::new(&Sha512::digest(server.secret.as_bytes()))
+                .name("auth-cookie")
+                .secure(secure),
+        ))
+        .wrap(Flash::new(&server.secret, secure))
+        .wrap(Csrf::new(secure))
+        .wrap(SecurityHeaders::new(server.headers.clone()))
+        .service(auth::create)
+        .service(auth::login)
+        .service(auth::reset_password)
+        .service(auth::logout)
+        .service(auth::change_email)
+        .service(auth::change_password)
+        .service(account::me)
+        .service(profile::update)
+        .service(gdpr::export)
+        .service(gdpr::request_deletion)
+        .service(token::create)
+        .service(token::revoke)
+        .service(account::admin_panel)
+        .service(account::api_setadmin)
+        .service(account::api_setemployee)
+        .service(users::show)
+        .service(users::update)
+        .service(users::status)
+        .service(users::force_reset)
+        .service(users::delete)
+        .service(account::editor)
+        .service(account::draft)
+        .service(account::translate)
+        .service(account::new)
+        .service(account::save)
+        .service(account::api_draft)
+        .service(account::wasm)
+        .service(i18n::lang)
+        .service(account::translations)
+        .service(account::save_translations)
+        .service(account::new_language)
+        .service(audit::audit_page)
+        .service(audit::export)
+        .service(web::api_whoami)
+        .service(web::api_l10n)
+        .service(web::api_t9n)
+        .service(api::list)
+        .service(api::show)
+        .service(api::create)
+        .service(api::update)
+        .service(api::remove)
+        .service(api::openapi)
+        .service(headers::report)
+        .service(web::root)
+        .service(web::index)
+        .service(web::articles)
+        .service(profile::show)
+        .service(web::wasm)
+        // matches everything, so it has to come last
+        .service(web::asset)
+}
@@ -1,39 +1,35 @@
-#![feature(async_closure)]
-
+use std::path::Path;
 use std::process;
+use std::time::Duration;
 
 use clap::{App as Clapp, Arg, ArgMatches, SubCommand};
-use tokio_postgres::NoTls;
+use futures::future;
+use tokio::{fs, time};
 
-use actix_identity::{CookieIdentityPolicy, IdentityService};
 use actix_web::{App, HttpServer};
 use arrayvec::ArrayString;
 
-use crate::error::{Error, Result};
-
-pub mod account;
-pub mod auth;
-pub mod error;
-pub mod i18n;
-pub mod path;
-pub mod template;
-pub mod term;
-pub mod web;
+use circus_backend::cache::Assets;
+use circus_backend::config::{Config, CONFIG_PATH};
+use circus_backend::db::{self, Pool};
+use circus_backend::error::{Error, Result};
+use circus_backend::i18n::{self, Languages};
+use circus_backend::repo::{NewArticle, Postgres, Repository};
+use circus_backend::{export, frontmatter, gdpr, term, tls, token, web};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
 const ABOUT: &str = "circus-backend is an open source webservice framework";
 const AFTER_HELP: &str = "This program was made possible by https://Zirkus-Internationale.de.";
 
-fn psql_escape<S: AsRef<str>>(string: S) -> String {
-    string.as_ref().replace("\\", "\\\\")
-}
-
-fn psql_config(password: &str) -> String {
-    format!(
-        "host=localhost port=5432 dbname=circus user=circus password='{}'",
-        psql_escape(password)
-    )
+// the database described by the configuration
+fn postgres(config: &Config, password: &str) -> Result<Postgres> {
+    let pool = Pool::new(
+        db::psql_config(&config.db, password)?,
+        db::connector(&config.db)?,
+        config.db.clone(),
+    );
+    Ok(Postgres::new(pool))
 }
 
 fn init_user<'a, 'b>(_matches: &'a ArgMatches<'b>) -> Result<()> {
@@ -121,58 +117,110 @@ async fn init_tables<'a, 'b>(_matches: &'a ArgMatches<'b>) -> Result<()> {
     let password = term::prompt(&window, Some("Password: "), true);
     pancurses::endwin();
     let password = password.unwrap_or_else(String::new);
-    let data = web::ServerData::new(psql_config(&password), NoTls).await?;
-    data.client
-        .execute(
-            "create table if not exists articles
-                         (
-                             id serial primary key not null,
-                             path text not null,
-                             title text not null,
-                             cdate date not null,
-                             udate date,
-                             author text
-                         )",
-            &[],
-        )
-        .await?;
-    data.client
-        .execute(
-            "create table if not exists users
-                         (
-                             id serial primary key not null,
-                             username text not null,
-                             pwhash text not null,
-                             email text not null,
-                             firstname text,
-                             lastname text
-                         )",
-            &[],
-        )
-        .await?;
-    data.client
-        .execute(
-            "create table if not exists employees
-                         (
-                             id serial primary key not null,
-                             uid integer references users (id) not null
-                         )",
-            &[],
-        )
-        .await?;
-    data.client
-        .execute(
-            "create table if not exists admins
-                         (
-                             id serial primary key not null,
-                             uid integer references users (id) not null
-                         )",
-            &[],
-        )
+    let config = Config::load(CONFIG_PATH).await?;
+    let repo = postgres(&config, &password)?;
+    repo.init_tables().await?;
+    let mut entries = fs::read_dir("public/l10n").await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension() != Some("ron".as_ref()) {
+            continue;
+        }
+        let code = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        repo.add_language(&code, &path.to_string_lossy()).await?;
+    }
+    Ok(())
+}
+
+// registers every article in public/articles/ that has no `articles` row yet
+async fn import<'a, 'b>(matches: &'a ArgMatches<'b>) -> Result<()> {
+    let window = pancurses::initscr();
+    let password = term::prompt(&window, Some("Password: "), true);
+    pancurses::endwin();
+    let password = password.unwrap_or_else(String::new);
+    let config = Config::load(CONFIG_PATH).await?;
+    let repo = postgres(&config, &password)?;
+    let username = matches.value_of("author").unwrap_or_default();
+    let uid = repo
+        .user_by_name(username)
+        .await?
+        .ok_or_else(|| Error::ResourceNotFound(format!("user {}", username)))?
+        .id;
+
+    let mut entries = fs::read_dir("public/articles").await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let file = entry.path();
+        if file.extension() != Some("md".as_ref()) {
+            continue;
+        }
+        let name = file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let path = format!("articles/{}", name);
+        if repo.article_by_path(&path).await?.is_some() {
+            continue;
+        }
+        let text = fs::read_to_string(&file).await?;
+        let (fm, _) = frontmatter::split(&text)?;
+        let fm = fm.unwrap_or_default();
+        let title = fm.title.clone().unwrap_or_else(|| {
+            file.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        repo.create_article(NewArticle {
+            path: path.clone(),
+            title: title.clone(),
+            author: uid,
+            date: fm.date.clone(),
+            lang: None,
+            translation_of: None,
+        })
         .await?;
+        repo.sync_front_matter(&path, &fm).await?;
+        println!("imported {} ({})", path, title);
+    }
     Ok(())
 }
 
+// renders every page and article into a static mirror of the site
+async fn export<'a, 'b>(matches: &'a ArgMatches<'b>) -> Result<()> {
+    let window = pancurses::initscr();
+    let password = term::prompt(&window, Some("Password: "), true);
+    pancurses::endwin();
+    let password = password.unwrap_or_else(String::new);
+    let config = Config::load(CONFIG_PATH).await?;
+    let out = matches
+        .value_of("out")
+        .unwrap_or(&config.export.directory)
+        .to_string();
+    let assets = Assets::new(config.compression.clone());
+    let repo = postgres(&config, &password)?;
+    let data = web::ServerData::new(Box::new(repo), config, Languages::default(), assets).await?;
+    export::export(&data, Path::new(&out)).await
+}
+
+// checks that every language has every key referenced by the templates
+fn l10n_check<'a, 'b>(_matches: &'a ArgMatches<'b>) -> Result<()> {
+    let missing = i18n::missing_translations(&["public", "private", "src"], "public/l10n")?;
+    for (code, key, files) in &missing {
+        let files = files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect::<Vec<_>>();
+        eprintln!("{}: missing {:?} (used in {})", code, key, files.join(", "));
+    }
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::MissingTranslations(missing.len()))
+    }
+}
+
 fn git_add<'a, 'b>(matches: &'a ArgMatches<'b>) -> Result<()> {
     let mut child = process::Command::new("git")
         .arg("add")
@@ -222,6 +270,39 @@ async fn main() -> Result<()> {
             SubCommand::with_name("init-user")
                 .about("initializes the circus user (must be ran as `root`)"),
         )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about(
+                    "registers the articles in public/articles/ which aren't in the \
+                    database yet, using their front matter",
+                )
+                .arg(
+                    Arg::with_name("author")
+                        .short("a")
+                        .long("author")
+                        .takes_value(true)
+                        .required(true)
+                        .value_name("USERNAME"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about(
+                    "renders every page and article in every language into a static \
+                    directory, with feeds and a sitemap",
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .short("o")
+                        .long("out")
+                        .takes_value(true)
+                        .value_name("DIRECTORY"),
+                ),
+        )
+        .subcommand(SubCommand::with_name("l10n-check").about(
+            "checks that every language in public/l10n/ translates every key \
+                    used by the templates",
+        ))
         .subcommand(
             SubCommand::with_name("add")
                 .about(
@@ -262,6 +343,9 @@ async fn main() -> Result<()> {
         ("init-db", Some(matches)) => init_db(matches),
         ("init-tables", Some(matches)) => init_tables(matches).await,
         ("init-user", Some(matches)) => init_user(matches),
+        ("import", Some(matches)) => import(matches).await,
+        ("export", Some(matches)) => export(matches).await,
+        ("l10n-check", Some(matches)) => l10n_check(matches),
         ("add", Some(matches)) => git_add(matches),
         ("commit", Some(matches)) => git_commit(matches),
         ("start", Some(_matches)) => {
@@ -275,45 +359,74 @@ async fn main() -> Result<()> {
                     err.element().len()
                 );
             });
-            let data = move || web::ServerData::new(psql_config(&password), NoTls);
-            HttpServer::new(move || {
-                App::new()
-                    .data_factory(data)
-                    .wrap(IdentityService::new(
-                        CookieIdentityPolicy::new(&[0; 64])
-                            .name("auth-cookie")
-                            .secure(false),
-                    ))
-                    .service(auth::create)
-                    .service(auth::login)
-                    .service(auth::logout)
-                    .service(auth::change_email)
-                    .service(auth::change_password)
-                    .service(account::me)
-                    .service(account::admin_panel)
-                    .service(account::api_setadmin)
-                    .service(account::api_setemployee)
-                    .service(account::editor)
-                    .service(account::draft)
-                    .service(account::new)
-                    .service(account::save)
-                    .service(account::api_draft)
-                    .service(account::wasm)
-                    .service(i18n::lang)
-                    .service(web::api_whoami)
-                    .service(web::api_l10n)
-                    .service(web::api_t9n)
-                    .service(web::root)
-                    .service(web::index)
-                    .service(web::articles)
-                    .service(web::stylesheet)
-                    .service(web::javascript)
-                    .service(web::wasm)
-            })
-            .bind("127.0.0.1:8080")?
-            .run()
-            .await
-            .map_err(From::from)
+            let config = Config::load(CONFIG_PATH).await?;
+            // shared, so that edits to the translations reach every worker
+            let langs = Languages::default();
+            let assets = Assets::new(config.compression.clone());
+            assets.preload("public/style").await?;
+            assets.preload("public/frontend").await?;
+            let mut server = config.server.clone();
+            // every worker has to read the cookies the others wrote
+            if server.secret.is_empty() {
+                server.secret = token::hex(&rand::random::<[u8; 32]>());
+            }
+            let purge_interval = config.accounts.purge_interval_secs.max(1);
+            let psql_config = db::psql_config(&config.db, &password)?;
+            let connector = db::connector(&config.db)?;
+            let data = move || {
+                // every worker has its own connections
+                let pool = Pool::new(psql_config.clone(), connector.clone(), config.db.clone());
+                web::ServerData::new(
+                    Box::new(Postgres::new(pool)),
+                    config.clone(),
+                    langs.clone(),
+                    assets.clone(),
+                )
+            };
+            // accounts whose grace period is over are deleted without anyone asking
+            let purge = data.clone();
+            actix_rt::spawn(async move {
+                let data = match purge().await {
+                    Ok(data) => data,
+                    Err(err) => return eprintln!("couldn't start purging accounts: {}", err),
+                };
+                let mut interval = time::interval(Duration::from_secs(purge_interval));
+                loop {
+                    interval.tick().await;
+                    match gdpr::purge(&data).await {
+                        Ok(0) => {}
+                        Ok(count) => eprintln!("deleted {} accounts", count),
+                        Err(err) => eprintln!("couldn't purge accounts: {}", err),
+                    }
+                }
+            });
+            let app = {
+                let server = server.clone();
+                HttpServer::new(move || circus_backend::app(data.clone(), &server))
+            };
+            match &server.tls {
+                Some(config) => {
+                    let resolver = tls::CertResolver::load(config)?;
+                    tls::reload_on_hangup(resolver.clone(), config.clone())?;
+                    let https = app
+                        .bind_rustls(&config.bind, tls::server_config(resolver))?
+                        .run();
+                    // plain HTTP only redirects to HTTPS
+                    let port = tls::port(config);
+                    let http = HttpServer::new(move || {
+                        App::new().default_service(
+                            actix_web::web::route().to(move |req| tls::redirect(req, port)),
+                        )
+                    })
+                    .bind(&server.bind)?
+                    .run();
+                    future::try_join(https, http)
+                        .await
+                        .map(|_| ())
+                        .map_err(From::from)
+                }
+                None => app.bind(&server.bind)?.run().await.map_err(From::from),
+            }
         }
         ("", _) => Err(Error::Cmdline("no command passed".to_string())),
         (x, _) => Err(Error::Cmdline(format!("unrecognized command: {:?}", x))),
@@ -0,0 +1,192 @@
+use std::fmt::Write;
+
+use actix_identity::Identity;
+use actix_web::http::StatusCode;
+use actix_web::{get, post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::audit::{Action, Event};
+use crate::cache;
+use crate::error::{Error, Result};
+use crate::form::{self, FieldError, Form};
+use crate::i18n::Lang;
+use crate::repo::{Article, Profile, User};
+use crate::template::{self, escape, Context};
+use crate::users::optional;
+use crate::web::ServerData;
+
+// in characters
+const MAX_BIO: usize = 2000;
+
+// the page with the form, rendered again when something is wrong
+const ACCOUNT: &str = "public/account/me.html";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProfileData {
+    firstname: String,
+    lastname: String,
+    bio: String,
+    avatar: String,
+    website: String,
+}
+
+// links have to be absolute, images may also be on the site itself
+// avatars are `https` only, the CSP doesn't load other images
+fn link(url: &str, image: bool) -> bool {
+    let valid = if image {
+        url.starts_with("https://") || (url.starts_with('/') && !url.starts_with("//"))
+    } else {
+        url.starts_with("https://") || url.starts_with("http://")
+    };
+    valid && !url.contains(char::is_whitespace)
+}
+
+// the public page of a user, e.g. `/authors/jd.html`
+// dots are encoded as well, so no name becomes a `..` segment
+pub(crate) fn href(user: &User) -> String {
+    let mut href = "/authors/".to_string();
+    for byte in user.username.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'~' => href.push(byte as char),
+            _ => write!(href, "%{:02X}", byte).expect("couldn't write to string"),
+        }
+    }
+    href.push_str(".html");
+    href
+}
+
+// a user with their articles, newest first
+// only users who write or have written articles have a public page
+pub(crate) async fn author(data: &ServerData<'_>, username: &str) -> Result<(User, Vec<Article>)> {
+    let not_found = || Error::ResourceNotFound(format!("author {}", username));
+    let user = data
+        .repo
+        .user_by_name(username)
+        .await?
+        .ok_or_else(not_found)?;
+    let mut articles = data
+        .repo
+        .articles()
+        .await?
+        .into_iter()
+        .filter(|article| article.author == Some(user.id))
+        .collect::<Vec<_>>();
+    if !user.employee && articles.is_empty() {
+        return Err(not_found());
+    }
+    articles.reverse();
+    Ok((user, articles))
+}
+
+// the profile of an author with the previews of their articles
+pub(crate) async fn page(data: &ServerData<'_>, username: &str) -> Result<String> {
+    let (user, articles) = author(data, username).await?;
+    let mut html = "<div class=\"author\">\n".to_string();
+    if let Some(avatar) = &user.avatar {
+        write!(html, "<img class=\"avatar\" src=\"{}\" alt=\"\"/>\n", escape(avatar)).expect("couldn't write to string");
+    }
+    write!(html, "<h1>{}</h1>\n", escape(&user.display_name())).expect("couldn't write to string");
+    if let Some(bio) = &user.bio {
+        write!(html, "<p class=\"bio\">{}</p>\n", escape(bio).replace('\n', "<br/>\n")).expect("couldn't write to string");
+    }
+    if let Some(website) = &user.website {
+        write!(html, "<a class=\"website\" href=\"{0}\" rel=\"nofollow ugc noopener\">{0}</a>\n", escape(website)).expect("couldn't write to string");
+    }
+    write!(html, "</div>\n").expect("couldn't write to string");
+    write!(html, "<h2>{{{{{{l10n(author_articles)}}}}}}</h2>\n").expect("couldn't write to string");
+    if articles.is_empty() {
+        write!(html, "<p>{{{{{{l10n(author_no_articles)}}}}}}</p>\n").expect("couldn't write to string");
+    }
+    for article in &articles {
+        html.push_str(&template::preview(data, article).await?);
+    }
+    Ok(html)
+}
+
+#[post("/account/profile.html")]
+pub async fn update<'a>(
+    profile_data: web::Form<ProfileData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(user) => {
+            let profile_data = profile_data.into_inner();
+            let mut form = Form::default()
+                .value("firstname", &profile_data.firstname)
+                .value("lastname", &profile_data.lastname)
+                .value("bio", &profile_data.bio)
+                .value("avatar", &profile_data.avatar)
+                .value("website", &profile_data.website);
+            let profile = Profile {
+                firstname: optional(profile_data.firstname),
+                lastname: optional(profile_data.lastname),
+                bio: optional(profile_data.bio.replace("\r\n", "\n")),
+                avatar: optional(profile_data.avatar),
+                website: optional(profile_data.website),
+            };
+            if profile.bio.as_ref().map_or(0, |bio| bio.chars().count()) > MAX_BIO {
+                form.error("bio", FieldError::new("validation_bio_long").param("count", MAX_BIO));
+            }
+            if profile.avatar.as_ref().is_some_and(|url| !link(url, true)) {
+                form.error("avatar", FieldError::new("validation_avatar"));
+            }
+            if profile.website.as_ref().is_some_and(|url| !link(url, false)) {
+                form.error("website", FieldError::new("validation_link"));
+            }
+            if !form.is_valid() {
+                let status = StatusCode::BAD_REQUEST;
+                return form::redisplay(ACCOUNT, &req, &identity, &data, &lang, form, status).await;
+            }
+            data.repo.update_profile(user.id, profile).await?;
+            Event::new(Some(&user), Action::UpdateProfile)
+                .target(format!("user {}", user.id))
+                .record(&data, &req)
+                .await?;
+            Ok(HttpResponse::SeeOther()
+                .header("Location", "/account/me.html")
+                .finish())
+        }
+        None => {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            Ok(HttpResponse::Forbidden().body(body))
+        }
+    }
+}
+
+#[get("/authors/{username}.html")]
+pub async fn show<'a>(
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<String>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    // unknown authors are a 404 before the template is read
+    author(&data, &info).await?;
+    let path = "public/authors/template.html";
+    let mut body = fs::read_to_string(path).await?;
+    template::search_replace_recursive(
+        &Context::new(&req, &identity),
+        &data,
+        &lang,
+        &mut body,
+        &[info.to_string()],
+    )
+    .await?;
+    let modified = data.repo.articles_modified(None).await?;
+    cache::page(&req, &data.config.compression, body, &[path], modified).await
+}
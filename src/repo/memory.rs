@@ -0,0 +1,554 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use super::{
+    ApiToken, Article, ArticleUpdate, AuditEntry, AuditFilter, Draft, NewApiToken, NewArticle,
+    NewAuditEntry, NewUser, Profile, Repository, User, UserFilter, UserStatus, UserUpdate,
+};
+use crate::error::{Error, Result};
+use crate::frontmatter::FrontMatter;
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Default)]
+struct Store {
+    users: Vec<User>,
+    articles: Vec<Article>,
+    drafts: Vec<Draft>,
+    // with the hashes of their secrets
+    tokens: Vec<(ApiToken, String)>,
+    audit_log: Vec<AuditEntry>,
+    languages: BTreeMap<String, String>,
+    // the last id handed out, shared by all records like a sequence
+    id: i32,
+}
+
+impl Store {
+    fn next_id(&mut self) -> i32 {
+        self.id += 1;
+        self.id
+    }
+
+    fn user_mut(&mut self, uid: i32) -> Result<&mut User> {
+        self.users
+            .iter_mut()
+            .find(|user| user.id == uid)
+            .ok_or_else(|| Error::ResourceNotFound(format!("user {}", uid)))
+    }
+}
+
+// keeps everything in memory, for running the server without a database
+// clones share the same records, so every worker sees the same data
+#[derive(Debug, Clone, Default)]
+pub struct Memory(Arc<RwLock<Store>>);
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Store> {
+        self.0.read().expect("memory store lock is poisoned")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Store> {
+        self.0.write().expect("memory store lock is poisoned")
+    }
+}
+
+// `yyyy-mm-dd` of a point in time, in UTC
+// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub(crate) fn date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / DAY)
+        .unwrap_or(0) as i64;
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// the start of a `yyyy-mm-dd` day, in UTC
+// see http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+pub(crate) fn parse_date(date: &str) -> Option<SystemTime> {
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(Some(year)), Some(Some(month)), Some(Some(day))) => (year, month, day),
+        _ => return None,
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    Some(UNIX_EPOCH + Duration::from_secs(days.max(0) as u64 * DAY))
+}
+
+#[async_trait(?Send)]
+impl Repository for Memory {
+    async fn user(&self, id: i32) -> Result<Option<User>> {
+        Ok(self.read().users.iter().find(|user| user.id == id).cloned())
+    }
+
+    async fn user_by_name(&self, username: &str) -> Result<Option<User>> {
+        Ok(self
+            .read()
+            .users
+            .iter()
+            .find(|user| user.username == username)
+            .cloned())
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        Ok(self.read().users.clone())
+    }
+
+    async fn username_taken(&self, username: &str) -> Result<bool> {
+        let lower = username.to_lowercase();
+        Ok(self
+            .read()
+            .users
+            .iter()
+            .any(|user| user.username.to_lowercase() == lower))
+    }
+
+    async fn email_banned(&self, email: &str) -> Result<bool> {
+        Ok(self.read().users.iter().any(|user| {
+            user.status == UserStatus::Banned && user.email.eq_ignore_ascii_case(email)
+        }))
+    }
+
+    async fn search_users(&self, filter: &UserFilter) -> Result<Vec<User>> {
+        Ok(self
+            .read()
+            .users
+            .iter()
+            .filter(|user| filter.matches(user))
+            .skip(filter.offset)
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<i32> {
+        let mut store = self.write();
+        // like the unique index of the database
+        let name = user.username.to_lowercase();
+        if store.users.iter().any(|other| other.username.to_lowercase() == name) {
+            return Err(Error::Conflict(format!("user {}", user.username)));
+        }
+        let id = store.next_id();
+        store.users.push(User {
+            id,
+            username: user.username,
+            pwhash: user.pwhash,
+            email: user.email,
+            firstname: user.firstname,
+            lastname: user.lastname,
+            bio: None,
+            avatar: None,
+            website: None,
+            employee: false,
+            admin: false,
+            status: UserStatus::Active,
+            reset_password: false,
+            delete_after: None,
+        });
+        Ok(id)
+    }
+
+    async fn set_email(&self, uid: i32, email: &str) -> Result<()> {
+        self.write().user_mut(uid)?.email = email.to_string();
+        Ok(())
+    }
+
+    async fn set_pwhash(&self, uid: i32, pwhash: &str) -> Result<()> {
+        self.write().user_mut(uid)?.pwhash = pwhash.to_string();
+        Ok(())
+    }
+
+    async fn set_employee(&self, uid: i32, employee: bool) -> Result<bool> {
+        let mut store = self.write();
+        let user = store.user_mut(uid)?;
+        let changed = user.employee != employee;
+        user.employee = employee;
+        Ok(changed)
+    }
+
+    async fn set_admin(&self, uid: i32, admin: bool) -> Result<bool> {
+        let mut store = self.write();
+        let user = store.user_mut(uid)?;
+        let changed = user.admin != admin;
+        user.admin = admin;
+        Ok(changed)
+    }
+
+    async fn update_user(&self, uid: i32, update: UserUpdate) -> Result<()> {
+        let mut store = self.write();
+        let user = store.user_mut(uid)?;
+        user.email = update.email;
+        user.firstname = update.firstname;
+        user.lastname = update.lastname;
+        Ok(())
+    }
+
+    async fn update_profile(&self, uid: i32, profile: Profile) -> Result<()> {
+        let mut store = self.write();
+        let user = store.user_mut(uid)?;
+        user.firstname = profile.firstname;
+        user.lastname = profile.lastname;
+        user.bio = profile.bio;
+        user.avatar = profile.avatar;
+        user.website = profile.website;
+        Ok(())
+    }
+
+    async fn set_status(&self, uid: i32, status: UserStatus) -> Result<bool> {
+        let mut store = self.write();
+        let user = store.user_mut(uid)?;
+        let changed = user.status != status;
+        user.status = status;
+        Ok(changed)
+    }
+
+    async fn set_reset_password(&self, uid: i32, reset: bool) -> Result<()> {
+        self.write().user_mut(uid)?.reset_password = reset;
+        Ok(())
+    }
+
+    async fn set_delete_after(&self, uid: i32, time: Option<SystemTime>) -> Result<()> {
+        self.write().user_mut(uid)?.delete_after = time;
+        Ok(())
+    }
+
+    async fn delete_user(&self, uid: i32, heir: Option<i32>) -> Result<()> {
+        let mut store = self.write();
+        // fails like the other methods if there is no such user
+        store.user_mut(uid)?;
+        for article in store.articles.iter_mut().filter(|article| article.author == Some(uid)) {
+            article.author = heir;
+        }
+        store.drafts.retain(|draft| draft.author != uid);
+        store.tokens.retain(|(token, _)| token.user != uid);
+        store.users.retain(|user| user.id != uid);
+        Ok(())
+    }
+
+    async fn article(&self, id: i32) -> Result<Option<Article>> {
+        Ok(self
+            .read()
+            .articles
+            .iter()
+            .find(|article| article.id == id)
+            .cloned())
+    }
+
+    async fn article_by_path(&self, path: &str) -> Result<Option<Article>> {
+        Ok(self
+            .read()
+            .articles
+            .iter()
+            .find(|article| article.path == path)
+            .cloned())
+    }
+
+    async fn article_by_title(&self, title: &str) -> Result<Option<Article>> {
+        Ok(self
+            .read()
+            .articles
+            .iter()
+            .find(|article| article.title == title)
+            .cloned())
+    }
+
+    async fn article_by_slug(&self, slug: &str) -> Result<Option<Article>> {
+        Ok(self
+            .read()
+            .articles
+            .iter()
+            .find(|article| article.slug.as_deref() == Some(slug))
+            .cloned())
+    }
+
+    async fn articles(&self) -> Result<Vec<Article>> {
+        let mut articles = self.read().articles.clone();
+        articles.sort_by(|a, b| (&a.date, a.id).cmp(&(&b.date, b.id)));
+        Ok(articles)
+    }
+
+    async fn translations(&self, path: &str) -> Result<Vec<Article>> {
+        let store = self.read();
+        let original = match store.articles.iter().find(|article| article.path == path) {
+            Some(article) => article.translation_of.unwrap_or(article.id),
+            None => return Ok(Vec::new()),
+        };
+        let mut translations = store
+            .articles
+            .iter()
+            .filter(|article| article.translation_of.unwrap_or(article.id) == original)
+            .cloned()
+            .collect::<Vec<_>>();
+        // like PostgreSQL, articles without a language come last
+        translations.sort_by_key(|article| (article.lang.is_none(), article.lang.clone()));
+        Ok(translations)
+    }
+
+    async fn create_article(&self, article: NewArticle) -> Result<i32> {
+        let mut store = self.write();
+        let translation_of = article.translation_of.and_then(|id| {
+            store
+                .articles
+                .iter()
+                .find(|original| original.id == id)
+                .map(|original| original.translation_of.unwrap_or(original.id))
+        });
+        let now = SystemTime::now();
+        let modified = article.date.as_deref().and_then(parse_date).unwrap_or(now);
+        let id = store.next_id();
+        store.articles.push(Article {
+            id,
+            path: article.path,
+            title: article.title,
+            author: Some(article.author),
+            date: date(modified),
+            modified,
+            summary: None,
+            tags: Vec::new(),
+            lang: article.lang,
+            cover: None,
+            slug: None,
+            translation_of,
+        });
+        Ok(id)
+    }
+
+    async fn update_article(&self, id: i32, update: ArticleUpdate) -> Result<()> {
+        let mut store = self.write();
+        if let Some(article) = store.articles.iter_mut().find(|article| article.id == id) {
+            if let Some(title) = update.title {
+                article.title = title;
+            }
+            if let Some(lang) = update.lang {
+                article.lang = Some(lang);
+            }
+            article.modified = SystemTime::now();
+        }
+        Ok(())
+    }
+
+    async fn delete_article(&self, id: i32) -> Result<()> {
+        let mut store = self.write();
+        // the oldest translation becomes the new original
+        let heir = store
+            .articles
+            .iter()
+            .filter(|article| article.translation_of == Some(id))
+            .map(|article| article.id)
+            .min();
+        for article in &mut store.articles {
+            if article.translation_of == Some(id) {
+                article.translation_of = heir.filter(|&heir| heir != article.id);
+            }
+        }
+        store.articles.retain(|article| article.id != id);
+        Ok(())
+    }
+
+    async fn sync_front_matter(&self, path: &str, fm: &FrontMatter) -> Result<()> {
+        let mut store = self.write();
+        if let Some(article) = store.articles.iter_mut().find(|article| article.path == path) {
+            if let Some(title) = &fm.title {
+                article.title = title.clone();
+            }
+            article.summary = fm.summary.clone();
+            article.tags = fm.tags.clone();
+            if let Some(lang) = &fm.language {
+                article.lang = Some(lang.clone());
+            }
+            article.cover = fm.cover.clone();
+            if let Some(modified) = fm.date.as_deref().and_then(parse_date) {
+                article.date = date(modified);
+                article.modified = modified;
+            }
+            article.slug = fm.slug.clone();
+        }
+        Ok(())
+    }
+
+    async fn articles_modified(&self, path: Option<&str>) -> Result<Option<SystemTime>> {
+        let store = self.read();
+        let articles = store.articles.iter();
+        Ok(match path {
+            Some(path) => articles
+                .filter(|article| article.path == path)
+                .map(|article| article.modified)
+                .next(),
+            None => articles.map(|article| article.modified).max(),
+        })
+    }
+
+    async fn drafts(&self, author: i32) -> Result<Vec<Draft>> {
+        Ok(self
+            .read()
+            .drafts
+            .iter()
+            .filter(|draft| draft.author == author)
+            .cloned()
+            .collect())
+    }
+
+    async fn draft(&self, id: i32, author: i32) -> Result<Option<Draft>> {
+        Ok(self
+            .read()
+            .drafts
+            .iter()
+            .find(|draft| draft.id == id && draft.author == author)
+            .cloned())
+    }
+
+    async fn draft_by_path(&self, author: i32, path: &str) -> Result<Option<Draft>> {
+        Ok(self
+            .read()
+            .drafts
+            .iter()
+            .find(|draft| draft.author == author && draft.path == path)
+            .cloned())
+    }
+
+    async fn save_draft(&self, author: i32, path: &str, title: &str) -> Result<()> {
+        let mut store = self.write();
+        match store.drafts.iter_mut().find(|draft| draft.path == path) {
+            Some(draft) => draft.title = Some(title.to_string()),
+            None => {
+                let id = store.next_id();
+                store.drafts.push(Draft {
+                    id,
+                    path: path.to_string(),
+                    title: Some(title.to_string()),
+                    author,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_draft(&self, author: i32, path: &str, title: &str) -> Result<()> {
+        self.write().drafts.retain(|draft| {
+            draft.author != author || draft.path != path || draft.title.as_deref() != Some(title)
+        });
+        Ok(())
+    }
+
+    async fn tokens(&self, user: i32) -> Result<Vec<ApiToken>> {
+        Ok(self
+            .read()
+            .tokens
+            .iter()
+            .filter(|(token, _)| token.user == user)
+            .map(|(token, _)| token.clone())
+            .collect())
+    }
+
+    async fn token_by_hash(&self, hash: &str) -> Result<Option<ApiToken>> {
+        Ok(self
+            .read()
+            .tokens
+            .iter()
+            .find(|(_, other)| other == hash)
+            .map(|(token, _)| token.clone()))
+    }
+
+    async fn create_token(&self, token: NewApiToken) -> Result<i32> {
+        let mut store = self.write();
+        let id = store.next_id();
+        store.tokens.push((
+            ApiToken {
+                id,
+                user: token.user,
+                name: token.name,
+                scopes: token.scopes,
+                created: SystemTime::now(),
+                expires: token.expires,
+                last_used: None,
+            },
+            token.hash,
+        ));
+        Ok(id)
+    }
+
+    async fn touch_token(&self, id: i32) -> Result<()> {
+        let mut store = self.write();
+        if let Some((token, _)) = store.tokens.iter_mut().find(|(token, _)| token.id == id) {
+            token.last_used = Some(SystemTime::now());
+        }
+        Ok(())
+    }
+
+    async fn revoke_token(&self, id: i32, user: i32) -> Result<bool> {
+        let mut store = self.write();
+        let before = store.tokens.len();
+        store
+            .tokens
+            .retain(|(token, _)| token.id != id || token.user != user);
+        Ok(store.tokens.len() < before)
+    }
+
+    async fn audit(&self, entry: NewAuditEntry) -> Result<()> {
+        let mut store = self.write();
+        let id = store.next_id();
+        store.audit_log.push(AuditEntry {
+            id,
+            actor: entry.actor,
+            action: entry.action,
+            target: entry.target,
+            before: entry.before,
+            after: entry.after,
+            ip: entry.ip,
+            time: SystemTime::now(),
+        });
+        Ok(())
+    }
+
+    async fn audit_log(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        Ok(self
+            .read()
+            .audit_log
+            .iter()
+            .rev()
+            .filter(|entry| filter.matches(entry))
+            .skip(filter.offset)
+            .take(filter.limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    async fn languages(&self) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .read()
+            .languages
+            .iter()
+            .map(|(code, path)| (code.clone(), path.clone()))
+            .collect())
+    }
+
+    async fn add_language(&self, code: &str, path: &str) -> Result<()> {
+        self.write()
+            .languages
+            .insert(code.to_string(), path.to_string());
+        Ok(())
+    }
+}
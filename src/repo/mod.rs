@@ -0,0 +1,337 @@
+use std::iter;
+use std::time::SystemTime;
+
+use async_trait::async_trait;
+
+use crate::error::Result;
+use crate::frontmatter::FrontMatter;
+
+mod memory;
+mod postgres;
+
+pub(crate) use self::memory::{date, parse_date};
+pub use self::memory::Memory;
+pub use self::postgres::Postgres;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    pub pwhash: String,
+    pub email: String,
+    pub firstname: Option<String>,
+    pub lastname: Option<String>,
+    // shown on their author page, see `profile`
+    pub bio: Option<String>,
+    // URL of the image
+    pub avatar: Option<String>,
+    pub website: Option<String>,
+    // employees may write articles, admins manage users and translations
+    pub employee: bool,
+    pub admin: bool,
+    pub status: UserStatus,
+    // set by an admin, the user has to choose a new password before logging in again
+    pub reset_password: bool,
+    // the user asked for their account to be deleted, it is once this has passed
+    pub delete_after: Option<SystemTime>,
+}
+
+impl User {
+    // whether the user may log in and their sessions and tokens count
+    pub fn active(&self) -> bool {
+        self.status == UserStatus::Active && !self.reset_password && self.delete_after.is_none()
+    }
+
+    // how the user is credited as an author, e.g. `Jane "jd" Doe`
+    pub fn display_name(&self) -> String {
+        match (&self.firstname, &self.lastname) {
+            (Some(first), Some(last)) => format!("{} \"{}\" {}", first, self.username, last),
+            (Some(first), None) => format!("{} \"{}\"", first, self.username),
+            (None, Some(last)) => format!("\"{}\" {}", self.username, last),
+            (None, None) => self.username.clone(),
+        }
+    }
+}
+
+// disabled and banned users can't log in, banned ones can't register again either
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserStatus {
+    Active,
+    Disabled,
+    Banned,
+}
+
+impl UserStatus {
+    pub const ALL: [UserStatus; 3] = [UserStatus::Active, UserStatus::Disabled, UserStatus::Banned];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            UserStatus::Active => "active",
+            UserStatus::Disabled => "disabled",
+            UserStatus::Banned => "banned",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|status| status.name() == name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct NewUser {
+    pub username: String,
+    pub pwhash: String,
+    pub email: String,
+    pub firstname: Option<String>,
+    pub lastname: Option<String>,
+}
+
+// what an admin can change about a user, all fields are replaced
+#[derive(Debug, Clone)]
+pub struct UserUpdate {
+    pub email: String,
+    pub firstname: Option<String>,
+    pub lastname: Option<String>,
+}
+
+// what users can change about themselves, all fields are replaced
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub firstname: Option<String>,
+    pub lastname: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,
+    pub website: Option<String>,
+}
+
+// which users to return, ordered by their id
+#[derive(Debug, Clone, Default)]
+pub struct UserFilter {
+    // a part of the username, the e-mail address or the names, ignoring case
+    pub query: Option<String>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl UserFilter {
+    pub fn matches(&self, user: &User) -> bool {
+        let query = match &self.query {
+            Some(query) => query.to_lowercase(),
+            None => return true,
+        };
+        iter::once(&user.username)
+            .chain(iter::once(&user.email))
+            .chain(user.firstname.iter())
+            .chain(user.lastname.iter())
+            .any(|field| field.to_lowercase().contains(&query))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Article {
+    pub id: i32,
+    // relative to public/, e.g. `articles/foobar.md`
+    pub path: String,
+    pub title: String,
+    // uid of the author, `None` once they deleted their account
+    pub author: Option<i32>,
+    // creation date, `yyyy-mm-dd`
+    pub date: String,
+    // when the article was last updated, or created if it never was
+    pub modified: SystemTime,
+    pub summary: Option<String>,
+    pub tags: Vec<String>,
+    pub lang: Option<String>,
+    pub cover: Option<String>,
+    pub slug: Option<String>,
+    // id of the article this one is a translation of
+    pub translation_of: Option<i32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewArticle {
+    pub path: String,
+    pub title: String,
+    pub author: i32,
+    // `yyyy-mm-dd`, today if `None`
+    pub date: Option<String>,
+    pub lang: Option<String>,
+    // translations of translations belong to the same original
+    pub translation_of: Option<i32>,
+}
+
+// the fields of an article which can be changed, `None` keeps the current value
+#[derive(Debug, Clone, Default)]
+pub struct ArticleUpdate {
+    pub title: Option<String>,
+    pub lang: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Draft {
+    pub id: i32,
+    // e.g. `private/{username}/drafts/foobar.md`
+    pub path: String,
+    pub title: Option<String>,
+    pub author: i32,
+}
+
+// a personal API token, only the hash of the secret is stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiToken {
+    pub id: i32,
+    pub user: i32,
+    pub name: String,
+    // see `token::Scope`
+    pub scopes: Vec<String>,
+    pub created: SystemTime,
+    // never expires if `None`
+    pub expires: Option<SystemTime>,
+    pub last_used: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewApiToken {
+    pub user: i32,
+    pub name: String,
+    // hex SHA-256 of the secret
+    pub hash: String,
+    pub scopes: Vec<String>,
+    pub expires: Option<SystemTime>,
+}
+
+// a privileged action or a login event, see `audit`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEntry {
+    pub id: i32,
+    // uid of who did it, `None` for visitors who aren't logged in
+    pub actor: Option<i32>,
+    // see `audit::Action`
+    pub action: String,
+    // what it was done to, e.g. `user 3` or the path of an article
+    pub target: Option<String>,
+    // the value that was changed, before and after
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub ip: Option<String>,
+    pub time: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry {
+    pub actor: Option<i32>,
+    pub action: String,
+    pub target: Option<String>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    pub ip: Option<String>,
+}
+
+// which entries of the audit log to return, `None` matches everything
+#[derive(Debug, Clone, Default)]
+pub struct AuditFilter {
+    pub actor: Option<i32>,
+    pub action: Option<String>,
+    // a part of the target
+    pub target: Option<String>,
+    // `since` is inclusive, `until` isn't
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+impl AuditFilter {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        self.actor.is_none_or(|actor| entry.actor == Some(actor))
+            && self
+                .action
+                .as_ref()
+                .is_none_or(|action| &entry.action == action)
+            && self.target.as_ref().is_none_or(|target| {
+                entry
+                    .target
+                    .as_ref()
+                    .is_some_and(|other| other.contains(target.as_str()))
+            })
+            && self.since.is_none_or(|since| entry.time >= since)
+            && self.until.is_none_or(|until| entry.time < until)
+    }
+}
+
+// everything the server keeps in the database
+// implemented for PostgreSQL, and in memory for running without one
+#[async_trait(?Send)]
+pub trait Repository {
+    async fn user(&self, id: i32) -> Result<Option<User>>;
+    async fn user_by_name(&self, username: &str) -> Result<Option<User>>;
+    // all users, ordered by their id
+    async fn users(&self) -> Result<Vec<User>>;
+    async fn search_users(&self, filter: &UserFilter) -> Result<Vec<User>>;
+    // whether a user has the name, ignoring case
+    async fn username_taken(&self, username: &str) -> Result<bool>;
+    // whether a banned user has the e-mail address, ignoring case
+    async fn email_banned(&self, email: &str) -> Result<bool>;
+    // returns the id of the new user, fails with `Error::Conflict` if the name is taken ignoring case
+    async fn create_user(&self, user: NewUser) -> Result<i32>;
+    async fn set_email(&self, uid: i32, email: &str) -> Result<()>;
+    async fn set_pwhash(&self, uid: i32, pwhash: &str) -> Result<()>;
+    // both return `false` if the user already had or didn't have the role
+    async fn set_employee(&self, uid: i32, employee: bool) -> Result<bool>;
+    async fn set_admin(&self, uid: i32, admin: bool) -> Result<bool>;
+    async fn update_user(&self, uid: i32, update: UserUpdate) -> Result<()>;
+    async fn update_profile(&self, uid: i32, profile: Profile) -> Result<()>;
+    // returns `false` if the user already had the status
+    async fn set_status(&self, uid: i32, status: UserStatus) -> Result<bool>;
+    async fn set_reset_password(&self, uid: i32, reset: bool) -> Result<()>;
+    // `None` cancels the deletion, see `gdpr`
+    async fn set_delete_after(&self, uid: i32, time: Option<SystemTime>) -> Result<()>;
+    // removes the user with their roles, drafts and tokens
+    // their articles go to `heir`, or are kept without an author
+    async fn delete_user(&self, uid: i32, heir: Option<i32>) -> Result<()>;
+
+    async fn article(&self, id: i32) -> Result<Option<Article>>;
+    async fn article_by_path(&self, path: &str) -> Result<Option<Article>>;
+    async fn article_by_title(&self, title: &str) -> Result<Option<Article>>;
+    async fn article_by_slug(&self, slug: &str) -> Result<Option<Article>>;
+    // all articles, oldest first
+    async fn articles(&self) -> Result<Vec<Article>>;
+    // the article at `path` and all its translations, ordered by language
+    async fn translations(&self, path: &str) -> Result<Vec<Article>>;
+    // returns the id of the new article
+    async fn create_article(&self, article: NewArticle) -> Result<i32>;
+    // also marks the article as modified today
+    async fn update_article(&self, id: i32, update: ArticleUpdate) -> Result<()>;
+    // the remaining translations of the article stay linked to each other
+    async fn delete_article(&self, id: i32) -> Result<()>;
+    // writes the front matter of the article at `path` to its record
+    async fn sync_front_matter(&self, path: &str, fm: &FrontMatter) -> Result<()>;
+    // when the article at `path`, or any article at all, last changed
+    async fn articles_modified(&self, path: Option<&str>) -> Result<Option<SystemTime>>;
+
+    async fn drafts(&self, author: i32) -> Result<Vec<Draft>>;
+    async fn draft(&self, id: i32, author: i32) -> Result<Option<Draft>>;
+    async fn draft_by_path(&self, author: i32, path: &str) -> Result<Option<Draft>>;
+    // creates the draft at `path` or renames it
+    async fn save_draft(&self, author: i32, path: &str, title: &str) -> Result<()>;
+    async fn delete_draft(&self, author: i32, path: &str, title: &str) -> Result<()>;
+
+    // the tokens of a user, oldest first
+    async fn tokens(&self, user: i32) -> Result<Vec<ApiToken>>;
+    async fn token_by_hash(&self, hash: &str) -> Result<Option<ApiToken>>;
+    // returns the id of the new token
+    async fn create_token(&self, token: NewApiToken) -> Result<i32>;
+    // records that the token was just used
+    async fn touch_token(&self, id: i32) -> Result<()>;
+    // returns `false` if the user has no such token
+    async fn revoke_token(&self, id: i32, user: i32) -> Result<bool>;
+
+    // entries are never changed or removed
+    async fn audit(&self, entry: NewAuditEntry) -> Result<()>;
+    // the matching entries, newest first
+    async fn audit_log(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>>;
+
+    // the codes of the registered languages with the paths of their RON files
+    async fn languages(&self) -> Result<Vec<(String, String)>>;
+    async fn add_language(&self, code: &str, path: &str) -> Result<()>;
+}
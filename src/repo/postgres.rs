@@ -0,0 +1,932 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::Row;
+
+use super::{
+    ApiToken, Article, ArticleUpdate, AuditEntry, AuditFilter, Draft, NewApiToken, NewArticle,
+    NewAuditEntry, NewUser, Profile, Repository, User, UserFilter, UserStatus, UserUpdate,
+};
+use crate::db::{Pool, PooledClient};
+use crate::error::{Error, Result};
+use crate::frontmatter::FrontMatter;
+
+// the columns of `User`, with the roles of the user
+macro_rules! select_user {
+    ($rest:expr) => {
+        concat!(
+            "select id, username, pwhash, email, firstname, lastname, bio, avatar, website, \
+             status, reset_password, delete_after, \
+             exists (select 1 from employees where employees.uid = users.id) as employee, \
+             exists (select 1 from admins where admins.uid = users.id) as admin \
+             from users ",
+            $rest
+        )
+    };
+}
+
+// the columns of `Article`
+macro_rules! select_article {
+    ($rest:expr) => {
+        concat!(
+            "select id, path, title, author, summary, tags, lang, cover, slug, translation_of, \
+             to_char(cdate, 'yyyy-mm-dd') as date, \
+             extract(epoch from coalesce(mtime, udate, cdate))::bigint as modified \
+             from articles ",
+            $rest
+        )
+    };
+}
+
+fn user(row: &Row) -> User {
+    User {
+        id: row.get("id"),
+        username: row.get("username"),
+        pwhash: row.get("pwhash"),
+        email: row.get("email"),
+        firstname: row.get("firstname"),
+        lastname: row.get("lastname"),
+        bio: row.get("bio"),
+        avatar: row.get("avatar"),
+        website: row.get("website"),
+        employee: row.get("employee"),
+        admin: row.get("admin"),
+        // a status this version doesn't know doesn't let anyone in
+        status: UserStatus::from_name(row.get("status")).unwrap_or(UserStatus::Disabled),
+        reset_password: row.get("reset_password"),
+        delete_after: row.get("delete_after"),
+    }
+}
+
+fn epoch(secs: i64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_secs(secs.max(0) as u64)
+}
+
+fn article(row: &Row) -> Article {
+    Article {
+        id: row.get("id"),
+        path: row.get("path"),
+        title: row.get("title"),
+        author: row.get("author"),
+        date: row.get("date"),
+        modified: epoch(row.get("modified")),
+        summary: row.get("summary"),
+        tags: row.get("tags"),
+        lang: row.get("lang"),
+        cover: row.get("cover"),
+        slug: row.get("slug"),
+        translation_of: row.get("translation_of"),
+    }
+}
+
+fn token(row: &Row) -> ApiToken {
+    ApiToken {
+        id: row.get("id"),
+        user: row.get("uid"),
+        name: row.get("name"),
+        scopes: row.get("scopes"),
+        created: row.get("created"),
+        expires: row.get("expires"),
+        last_used: row.get("last_used"),
+    }
+}
+
+fn audit_entry(row: &Row) -> AuditEntry {
+    AuditEntry {
+        id: row.get("id"),
+        actor: row.get("actor"),
+        action: row.get("action"),
+        target: row.get("target"),
+        before: row.get("before_value"),
+        after: row.get("after_value"),
+        ip: row.get("ip"),
+        time: row.get("time"),
+    }
+}
+
+fn draft(row: &Row) -> Draft {
+    Draft {
+        id: row.get("id"),
+        path: row.get("path"),
+        title: row.get("title"),
+        author: row.get("author"),
+    }
+}
+
+pub struct Postgres {
+    pool: Pool,
+}
+
+impl Postgres {
+    pub fn new(pool: Pool) -> Self {
+        Self { pool }
+    }
+
+    async fn client(&self) -> Result<PooledClient<'_>> {
+        self.pool.get().await
+    }
+
+    // creates the tables, and adds the columns which are newer than them
+    pub async fn init_tables(&self) -> Result<()> {
+        let client = self.client().await?;
+        client
+            .execute(
+                "create table if not exists articles
+                             (
+                                 id serial primary key not null,
+                                 path text not null,
+                                 title text not null,
+                                 cdate date not null,
+                                 udate date,
+                                 author integer
+                             )",
+                &[],
+            )
+            .await?;
+        client
+            .execute(
+                "create table if not exists l10n
+                             (
+                                 code text primary key not null,
+                                 path text not null
+                             )",
+                &[],
+            )
+            .await?;
+        for column in &[
+            "summary text",
+            "tags text[] not null default '{}'",
+            "lang text",
+            "cover text",
+            "slug text unique",
+            "translation_of integer references articles (id)",
+            // `udate` only has the day, which is too coarse for `Last-Modified`
+            "mtime timestamptz",
+        ] {
+            client
+                .execute(
+                    &*format!("alter table articles add column if not exists {}", column),
+                    &[],
+                )
+                .await?;
+        }
+        client
+            .execute(
+                "create table if not exists users
+                             (
+                                 id serial primary key not null,
+                                 username text not null,
+                                 pwhash text not null,
+                                 email text not null,
+                                 firstname text,
+                                 lastname text
+                             )",
+                &[],
+            )
+            .await?;
+        for column in &[
+            "status text not null default 'active'",
+            "reset_password boolean not null default false",
+            "delete_after timestamptz",
+            "bio text",
+            "avatar text",
+            "website text",
+        ] {
+            client
+                .execute(
+                    &*format!("alter table users add column if not exists {}", column),
+                    &[],
+                )
+                .await?;
+        }
+        // usernames only differing in case would be mistaken for each other,
+        // accounts from before the index have to be renamed by hand first
+        let duplicates = client
+            .query(
+                "select username from users where lower(username) in
+                             (
+                                 select lower(username) from users
+                                 group by lower(username) having count(*) > 1
+                             )
+                             order by lower(username), id",
+                &[],
+            )
+            .await?;
+        if !duplicates.is_empty() {
+            return Err(Error::DuplicateUsernames(
+                duplicates.iter().map(|row| row.get(0)).collect(),
+            ));
+        }
+        client
+            .execute(
+                "create unique index if not exists users_username_lower on users (lower(username))",
+                &[],
+            )
+            .await?;
+        client
+            .execute(
+                "create table if not exists employees
+                             (
+                                 id serial primary key not null,
+                                 uid integer references users (id) not null
+                             )",
+                &[],
+            )
+            .await?;
+        client
+            .execute(
+                "create table if not exists admins
+                             (
+                                 id serial primary key not null,
+                                 uid integer references users (id) not null
+                             )",
+                &[],
+            )
+            .await?;
+        client
+            .execute(
+                "create table if not exists drafts
+                             (
+                                 id serial primary key not null,
+                                 path text not null,
+                                 title text,
+                                 author integer references users (id) not null
+                             )",
+                &[],
+            )
+            .await?;
+        client
+            .execute(
+                "create table if not exists api_tokens
+                             (
+                                 id serial primary key not null,
+                                 uid integer references users (id) not null,
+                                 name text not null,
+                                 hash text unique not null,
+                                 scopes text[] not null default '{}',
+                                 created timestamptz not null default now(),
+                                 expires timestamptz,
+                                 last_used timestamptz
+                             )",
+                &[],
+            )
+            .await?;
+        // the actor isn't a reference, the log outlives deleted users
+        client
+            .execute(
+                "create table if not exists audit_log
+                             (
+                                 id serial primary key not null,
+                                 actor integer,
+                                 action text not null,
+                                 target text,
+                                 before_value text,
+                                 after_value text,
+                                 ip text,
+                                 time timestamptz not null default now()
+                             )",
+                &[],
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl Repository for Postgres {
+    async fn user(&self, id: i32) -> Result<Option<User>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt_cached(select_user!("where id = $1"), &[&id])
+            .await?;
+        Ok(row.as_ref().map(user))
+    }
+
+    async fn user_by_name(&self, username: &str) -> Result<Option<User>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt_cached(select_user!("where username = $1"), &[&username])
+            .await?;
+        Ok(row.as_ref().map(user))
+    }
+
+    async fn users(&self) -> Result<Vec<User>> {
+        let rows = self
+            .client()
+            .await?
+            .query(select_user!("order by id"), &[])
+            .await?;
+        Ok(rows.iter().map(user).collect())
+    }
+
+    async fn username_taken(&self, username: &str) -> Result<bool> {
+        let row = self
+            .client()
+            .await?
+            .query_one_cached(
+                "select exists (select 1 from users where lower(username) = lower($1))",
+                &[&username],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    async fn email_banned(&self, email: &str) -> Result<bool> {
+        let row = self
+            .client()
+            .await?
+            .query_one_cached(
+                "select exists (select 1 from users \
+                 where lower(email) = lower($1) and status = $2)",
+                &[&email, &UserStatus::Banned.name()],
+            )
+            .await?;
+        Ok(row.get(0))
+    }
+
+    async fn search_users(&self, filter: &UserFilter) -> Result<Vec<User>> {
+        let query = filter.query.as_ref().map(|query| query.to_lowercase());
+        let limit = filter.limit.map(|limit| limit as i64);
+        let offset = filter.offset as i64;
+        let rows = self
+            .client()
+            .await?
+            .query(
+                select_user!(
+                    "where $1::text is null \
+                     or strpos(lower(username), $1) > 0 \
+                     or strpos(lower(email), $1) > 0 \
+                     or strpos(lower(firstname), $1) > 0 \
+                     or strpos(lower(lastname), $1) > 0 \
+                     order by id limit $2 offset $3"
+                ),
+                &[&query, &limit, &offset],
+            )
+            .await?;
+        Ok(rows.iter().map(user).collect())
+    }
+
+    async fn create_user(&self, user: NewUser) -> Result<i32> {
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "insert into users (firstname, lastname, username, email, pwhash) \
+                 values ($1, $2, $3, $4, $5) returning id",
+                &[
+                    &user.firstname,
+                    &user.lastname,
+                    &user.username,
+                    &user.email,
+                    &user.pwhash,
+                ],
+            )
+            .await
+            .map_err(|err| {
+                if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                    Error::Conflict(format!("user {}", user.username))
+                } else {
+                    err.into()
+                }
+            })?;
+        Ok(row.get("id"))
+    }
+
+    async fn set_email(&self, uid: i32, email: &str) -> Result<()> {
+        self.client()
+            .await?
+            .execute("update users set email = $1 where id = $2", &[&email, &uid])
+            .await?;
+        Ok(())
+    }
+
+    async fn set_pwhash(&self, uid: i32, pwhash: &str) -> Result<()> {
+        self.client()
+            .await?
+            .execute("update users set pwhash = $1 where id = $2", &[&pwhash, &uid])
+            .await?;
+        Ok(())
+    }
+
+    async fn set_employee(&self, uid: i32, employee: bool) -> Result<bool> {
+        let client = self.client().await?;
+        let changed = if employee {
+            let existing = client
+                .query_opt("select id from employees where uid = $1", &[&uid])
+                .await?;
+            existing.is_none()
+                && client
+                    .execute("insert into employees (uid) values ($1)", &[&uid])
+                    .await?
+                    > 0
+        } else {
+            client
+                .execute("delete from employees where uid = $1", &[&uid])
+                .await?
+                > 0
+        };
+        Ok(changed)
+    }
+
+    async fn set_admin(&self, uid: i32, admin: bool) -> Result<bool> {
+        let client = self.client().await?;
+        let changed = if admin {
+            let existing = client
+                .query_opt("select id from admins where uid = $1", &[&uid])
+                .await?;
+            existing.is_none()
+                && client
+                    .execute("insert into admins (uid) values ($1)", &[&uid])
+                    .await?
+                    > 0
+        } else {
+            client
+                .execute("delete from admins where uid = $1", &[&uid])
+                .await?
+                > 0
+        };
+        Ok(changed)
+    }
+
+    async fn update_user(&self, uid: i32, update: UserUpdate) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update users set email = $2, firstname = $3, lastname = $4 where id = $1",
+                &[&uid, &update.email, &update.firstname, &update.lastname],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn update_profile(&self, uid: i32, profile: Profile) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update users set firstname = $2, lastname = $3, bio = $4, avatar = $5, website = $6 \
+                 where id = $1",
+                &[
+                    &uid,
+                    &profile.firstname,
+                    &profile.lastname,
+                    &profile.bio,
+                    &profile.avatar,
+                    &profile.website,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_status(&self, uid: i32, status: UserStatus) -> Result<bool> {
+        let changed = self
+            .client()
+            .await?
+            .execute(
+                "update users set status = $2 where id = $1 and status <> $2",
+                &[&uid, &status.name()],
+            )
+            .await?;
+        Ok(changed > 0)
+    }
+
+    async fn set_reset_password(&self, uid: i32, reset: bool) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update users set reset_password = $2 where id = $1",
+                &[&uid, &reset],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_delete_after(&self, uid: i32, time: Option<SystemTime>) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update users set delete_after = $2 where id = $1",
+                &[&uid, &time],
+            )
+            .await?;
+        Ok(())
+    }
+
+    // one statement, so the user is either gone with everything referencing them or not at all
+    async fn delete_user(&self, uid: i32, heir: Option<i32>) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "with reassigned as ( \
+                     update articles set author = $2::integer where author = $1 \
+                 ), deleted_drafts as ( \
+                     delete from drafts where author = $1 \
+                 ), deleted_tokens as ( \
+                     delete from api_tokens where uid = $1 \
+                 ), deleted_employees as ( \
+                     delete from employees where uid = $1 \
+                 ), deleted_admins as ( \
+                     delete from admins where uid = $1 \
+                 ) \
+                 delete from users where id = $1",
+                &[&uid, &heir],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn article(&self, id: i32) -> Result<Option<Article>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt_cached(select_article!("where id = $1"), &[&id])
+            .await?;
+        Ok(row.as_ref().map(article))
+    }
+
+    async fn article_by_path(&self, path: &str) -> Result<Option<Article>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt_cached(select_article!("where path = $1"), &[&path])
+            .await?;
+        Ok(row.as_ref().map(article))
+    }
+
+    async fn article_by_title(&self, title: &str) -> Result<Option<Article>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt_cached(select_article!("where title = $1"), &[&title])
+            .await?;
+        Ok(row.as_ref().map(article))
+    }
+
+    async fn article_by_slug(&self, slug: &str) -> Result<Option<Article>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt_cached(select_article!("where slug = $1"), &[&slug])
+            .await?;
+        Ok(row.as_ref().map(article))
+    }
+
+    async fn articles(&self) -> Result<Vec<Article>> {
+        let rows = self
+            .client()
+            .await?
+            .query_cached(select_article!("order by cdate, id"), &[])
+            .await?;
+        Ok(rows.iter().map(article).collect())
+    }
+
+    async fn translations(&self, path: &str) -> Result<Vec<Article>> {
+        let rows = self
+            .client()
+            .await?
+            .query_cached(
+                select_article!(
+                    "where coalesce(translation_of, id) = \
+                     (select coalesce(translation_of, id) from articles where path = $1) \
+                     order by lang"
+                ),
+                &[&path],
+            )
+            .await?;
+        Ok(rows.iter().map(article).collect())
+    }
+
+    async fn create_article(&self, article: NewArticle) -> Result<i32> {
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "insert into articles (path, title, cdate, author, lang, translation_of) \
+                 values ($1, $2, coalesce(to_date($3, 'yyyy-mm-dd'), current_date), $4, $5, \
+                 (select coalesce(translation_of, id) from articles where id = $6)) \
+                 returning id",
+                &[
+                    &article.path,
+                    &article.title,
+                    &article.date,
+                    &article.author,
+                    &article.lang,
+                    &article.translation_of,
+                ],
+            )
+            .await?;
+        Ok(row.get("id"))
+    }
+
+    async fn update_article(&self, id: i32, update: ArticleUpdate) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update articles set title = coalesce($2, title), lang = coalesce($3, lang), \
+                 udate = current_date, mtime = now() where id = $1",
+                &[&id, &update.title, &update.lang],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_article(&self, id: i32) -> Result<()> {
+        let client = self.client().await?;
+        // the oldest translation becomes the new original
+        client
+            .execute(
+                "update articles set translation_of = nullif( \
+                     (select min(id) from articles where translation_of = $1), id) \
+                 where translation_of = $1",
+                &[&id],
+            )
+            .await?;
+        client
+            .execute("delete from articles where id = $1", &[&id])
+            .await?;
+        Ok(())
+    }
+
+    // only touches the row if something actually changed
+    async fn sync_front_matter(&self, path: &str, fm: &FrontMatter) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "update articles set
+                     title = coalesce($2, title),
+                     summary = $3,
+                     tags = $4,
+                     lang = coalesce($5, lang),
+                     cover = $6,
+                     cdate = coalesce(to_date($7, 'yyyy-mm-dd'), cdate),
+                     slug = $8
+                 where path = $1 and (
+                     title is distinct from coalesce($2, title) or
+                     summary is distinct from $3 or
+                     tags is distinct from $4 or
+                     lang is distinct from coalesce($5, lang) or
+                     cover is distinct from $6 or
+                     cdate is distinct from coalesce(to_date($7, 'yyyy-mm-dd'), cdate) or
+                     slug is distinct from $8
+                 )",
+                &[
+                    &path,
+                    &fm.title,
+                    &fm.summary,
+                    &fm.tags,
+                    &fm.language,
+                    &fm.cover,
+                    &fm.date,
+                    &fm.slug,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn articles_modified(&self, path: Option<&str>) -> Result<Option<SystemTime>> {
+        let client = self.client().await?;
+        let row = match path {
+            Some(path) => {
+                client
+                    .query_opt_cached(
+                        "select extract(epoch from coalesce(mtime, udate, cdate))::bigint as modified \
+                         from articles where path = $1",
+                        &[&path],
+                    )
+                    .await?
+            }
+            None => {
+                client
+                    .query_opt_cached(
+                        "select extract(epoch from max(coalesce(mtime, udate, cdate)))::bigint as modified \
+                         from articles",
+                        &[],
+                    )
+                    .await?
+            }
+        };
+        Ok(row
+            .and_then(|row| row.get::<_, Option<i64>>("modified"))
+            .map(epoch))
+    }
+
+    async fn drafts(&self, author: i32) -> Result<Vec<Draft>> {
+        let rows = self
+            .client()
+            .await?
+            .query_cached(
+                "select id, path, title, author from drafts where author = $1 order by id",
+                &[&author],
+            )
+            .await?;
+        Ok(rows.iter().map(draft).collect())
+    }
+
+    async fn draft(&self, id: i32, author: i32) -> Result<Option<Draft>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "select id, path, title, author from drafts where id = $1 and author = $2",
+                &[&id, &author],
+            )
+            .await?;
+        Ok(row.as_ref().map(draft))
+    }
+
+    async fn draft_by_path(&self, author: i32, path: &str) -> Result<Option<Draft>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt(
+                "select id, path, title, author from drafts where author = $1 and path = $2",
+                &[&author, &path],
+            )
+            .await?;
+        Ok(row.as_ref().map(draft))
+    }
+
+    async fn save_draft(&self, author: i32, path: &str, title: &str) -> Result<()> {
+        let client = self.client().await?;
+        let existing = client
+            .query_opt("select id from drafts where path = $1", &[&path])
+            .await?;
+        match existing {
+            Some(row) => {
+                let id = row.get::<_, i32>("id");
+                client
+                    .execute("update drafts set title = $1 where id = $2", &[&title, &id])
+                    .await?;
+            }
+            None => {
+                client
+                    .execute(
+                        "insert into drafts (path, title, author) values ($1, $2, $3)",
+                        &[&path, &title, &author],
+                    )
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn delete_draft(&self, author: i32, path: &str, title: &str) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "delete from drafts where path = $1 and title = $2 and author = $3",
+                &[&path, &title, &author],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn tokens(&self, user: i32) -> Result<Vec<ApiToken>> {
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "select id, uid, name, scopes, created, expires, last_used from api_tokens \
+                 where uid = $1 order by id",
+                &[&user],
+            )
+            .await?;
+        Ok(rows.iter().map(token).collect())
+    }
+
+    async fn token_by_hash(&self, hash: &str) -> Result<Option<ApiToken>> {
+        let row = self
+            .client()
+            .await?
+            .query_opt_cached(
+                "select id, uid, name, scopes, created, expires, last_used from api_tokens \
+                 where hash = $1",
+                &[&hash],
+            )
+            .await?;
+        Ok(row.as_ref().map(token))
+    }
+
+    async fn create_token(&self, token: NewApiToken) -> Result<i32> {
+        let row = self
+            .client()
+            .await?
+            .query_one(
+                "insert into api_tokens (uid, name, hash, scopes, expires) \
+                 values ($1, $2, $3, $4, $5) returning id",
+                &[
+                    &token.user,
+                    &token.name,
+                    &token.hash,
+                    &token.scopes,
+                    &token.expires,
+                ],
+            )
+            .await?;
+        Ok(row.get("id"))
+    }
+
+    async fn touch_token(&self, id: i32) -> Result<()> {
+        self.client()
+            .await?
+            .execute_cached("update api_tokens set last_used = now() where id = $1", &[&id])
+            .await?;
+        Ok(())
+    }
+
+    async fn revoke_token(&self, id: i32, user: i32) -> Result<bool> {
+        let revoked = self
+            .client()
+            .await?
+            .execute(
+                "delete from api_tokens where id = $1 and uid = $2",
+                &[&id, &user],
+            )
+            .await?;
+        Ok(revoked > 0)
+    }
+
+    async fn audit(&self, entry: NewAuditEntry) -> Result<()> {
+        self.client()
+            .await?
+            .execute_cached(
+                "insert into audit_log (actor, action, target, before_value, after_value, ip) \
+                 values ($1, $2, $3, $4, $5, $6)",
+                &[
+                    &entry.actor,
+                    &entry.action,
+                    &entry.target,
+                    &entry.before,
+                    &entry.after,
+                    &entry.ip,
+                ],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn audit_log(&self, filter: &AuditFilter) -> Result<Vec<AuditEntry>> {
+        // a null limit doesn't limit anything
+        let limit = filter.limit.map(|limit| limit as i64);
+        let offset = filter.offset as i64;
+        let rows = self
+            .client()
+            .await?
+            .query(
+                "select id, actor, action, target, before_value, after_value, ip, time \
+                 from audit_log \
+                 where ($1::integer is null or actor = $1) \
+                 and ($2::text is null or action = $2) \
+                 and ($3::text is null or strpos(target, $3) > 0) \
+                 and ($4::timestamptz is null or time >= $4) \
+                 and ($5::timestamptz is null or time < $5) \
+                 order by id desc limit $6 offset $7",
+                &[
+                    &filter.actor,
+                    &filter.action,
+                    &filter.target,
+                    &filter.since,
+                    &filter.until,
+                    &limit,
+                    &offset,
+                ],
+            )
+            .await?;
+        Ok(rows.iter().map(audit_entry).collect())
+    }
+
+    async fn languages(&self) -> Result<Vec<(String, String)>> {
+        let client = self.client().await?;
+        // the table doesn't exist before `init-tables` ran
+        let exists = client
+            .query_one("select to_regclass('l10n') is not null as exists", &[])
+            .await?
+            .get::<_, bool>("exists");
+        if !exists {
+            return Ok(Vec::new());
+        }
+        let rows = client
+            .query("select code, path from l10n order by code", &[])
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| (row.get("code"), row.get("path")))
+            .collect())
+    }
+
+    async fn add_language(&self, code: &str, path: &str) -> Result<()> {
+        self.client()
+            .await?
+            .execute(
+                "insert into l10n (code, path) values ($1, $2) \
+                 on conflict (code) do update set path = excluded.path",
+                &[&code, &path],
+            )
+            .await?;
+        Ok(())
+    }
+}
@@ -1,17 +1,27 @@
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::fmt::Write;
 use std::str::FromStr;
+use std::time::SystemTime;
 
 use actix_identity::Identity;
-use futures::future;
-use futures::TryFutureExt;
+use actix_web::HttpRequest;
 use pulldown_cmark as md;
 use tokio::fs;
-use tokio_postgres as psql;
 
+use crate::audit::{self, AuditQuery};
+use crate::csrf;
 use crate::error::{Error, Result};
-use crate::i18n::Language;
+use crate::flash;
+use crate::form::Form;
+use crate::frontmatter::{self, FrontMatter};
+use crate::headers;
+use crate::i18n::{Language, Message};
 use crate::path::PublicPath;
+use crate::profile;
+use crate::repo::{self, Article, User};
+use crate::users::{self, UsersQuery};
+use crate::web::ServerData;
 
 #[derive(Debug, Clone)]
 enum Pattern {
@@ -20,42 +30,272 @@ enum Pattern {
     Editor,
     Admin,
     Drafts,
-    AdminPanel,
+    AdminPanel(Option<usize>),
+    UserDetails(usize),
+    Tokens,
+    AuditLog(usize),
+    Author(usize),
+    Csrf,
+    CsrfToken,
+    Nonce,
+    Flash,
     Me(String),
     Path(String),
     Positional(usize),
-    L10n(String),
+    L10n(String, Vec<(String, String)>),
     ArticlePositional(usize),
     PreviewLatest(usize),
     ArticleLatest(usize),
     PreviewTitle(String),
     ArticleTitle(String),
+    Argument(usize),
+    Translations(usize),
+    Translate(usize),
+    LanguageSelect,
+    TranslationEditor,
+    Asset(String),
+    Value(String),
+    FieldError(String),
     Maybe(Box<Pattern>),
 }
 
-async fn author(client: &psql::Client, uid: i32) -> Result<Option<String>> {
-    let user = client
-        .query_opt(
-            "select firstname, lastname, username from users where id = $1",
-            &[&uid],
-        )
-        .await?;
-    match user {
-        Some(user) => {
-            let firstname = user.get::<_, Option<&str>>("firstname");
-            let lastname = user.get::<_, Option<&str>>("lastname");
-            let username = user.get::<_, &str>("username");
-            match (firstname, lastname) {
-                (Some(first), Some(last)) => {
-                    Ok(Some(format!("{} \"{}\" {}", first, username, last)))
-                }
-                (Some(first), None) => Ok(Some(format!("{} \"{}\"", first, username))),
-                (None, Some(last)) => Ok(Some(format!("\"{}\" {}", username, last))),
-                _ => Ok(Some(username.to_string())),
+// who a page is rendered for
+#[derive(Debug, Clone, Default)]
+pub struct Context {
+    // the logged in user, `None` for anonymous visitors
+    pub user: Option<String>,
+    // the CSRF token forms have to send back, `None` outside of requests
+    pub csrf: Option<String>,
+    // lets inline scripts run, see `headers::SecurityHeaders`
+    pub nonce: Option<String>,
+    // a form rendered again after it was submitted
+    pub form: Option<Form>,
+    // the l10n key of a notice from the previous request, see `flash`
+    pub flash: Option<String>,
+}
+
+impl Context {
+    pub fn new(req: &HttpRequest, identity: &Identity) -> Self {
+        Self {
+            user: identity.identity(),
+            csrf: csrf::token(req),
+            nonce: headers::nonce(req),
+            form: None,
+            flash: flash::message(req),
+        }
+    }
+
+    pub fn with_form(self, form: Form) -> Self {
+        Self {
+            form: Some(form),
+            ..self
+        }
+    }
+
+    // renders pages the way a visitor who isn't logged in sees them
+    pub fn anonymous() -> Self {
+        Self::default()
+    }
+
+    // the logged in user, see `ServerData::user`
+    async fn logged_in(&self, data: &ServerData<'_>) -> Result<Option<User>> {
+        match &self.user {
+            Some(username) => Ok(data
+                .repo
+                .user_by_name(username)
+                .await?
+                .filter(User::active)),
+            None => Ok(None),
+        }
+    }
+}
+
+// ` by {author}` linking to their page, or nothing if the author doesn't exist anymore
+async fn by_author(data: &ServerData<'_>, uid: Option<i32>) -> Result<String> {
+    let user = match uid {
+        Some(uid) => data.repo.user(uid).await?,
+        None => None,
+    };
+    Ok(user
+        .map(|user| {
+            format!(
+                " {{{{{{l10n(by_author)}}}}}} <a href=\"{}\" rel=\"author\">{}</a>",
+                profile::href(&user),
+                escape(&user.display_name())
+            )
+        })
+        .unwrap_or_else(String::new))
+}
+
+// renders Markdown to HTML, returning its front matter separately
+pub(crate) fn markdown(text: &str) -> Result<(Option<FrontMatter>, String)> {
+    let (fm, text) = frontmatter::split(text)?;
+    let parser = md::Parser::new_ext(text, md::Options::all());
+    let mut html = String::new();
+    md::html::push_html(&mut html, parser);
+    Ok((fm, html))
+}
+
+// reads a public file, rendering it to HTML if it is Markdown
+// front matter is stripped from Markdown files and returned separately
+async fn render(path: &PublicPath<'_>) -> Result<(Option<FrontMatter>, String)> {
+    let text = fs::read_to_string(path).await?;
+    if path.extension() == Some("md".as_ref()) {
+        markdown(&text)
+    } else {
+        Ok((None, text))
+    }
+}
+
+// keeps the article in sync with the front matter of its file
+// returns the title that should be displayed
+// a front matter the database rejects, e.g. with a taken slug or an invalid date,
+// is only logged, the page is shown anyway
+async fn sync(
+    data: &ServerData<'_>,
+    path: &str,
+    title: &str,
+    fm: Option<FrontMatter>,
+) -> Result<String> {
+    match fm {
+        Some(fm) => {
+            if let Err(err) = data.repo.sync_front_matter(path, &fm).await {
+                eprintln!("couldn't sync the front matter of {}: {}", path, err);
+            }
+            Ok(fm.title.unwrap_or_else(|| title.to_string()))
+        }
+        None => Ok(title.to_string()),
+    }
+}
+
+// escapes text for use in HTML content and attribute values, braces
+// included so the text can't smuggle patterns into the rendered page
+pub(crate) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('{', "&#123;")
+}
+
+// links to an article by its slug if it has one
+pub(crate) fn href(article: &Article) -> String {
+    match &article.slug {
+        Some(slug) => format!("/articles/{}", slug),
+        None => format!("/{}", article.path),
+    }
+}
+
+// removes all tags from rendered HTML, leaving only the text
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for ch in html.chars() {
+        match ch {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            }
+            ch if !in_tag => text.push(ch),
+            _ => {}
+        }
+    }
+    text
+}
+
+// the first `words` words of a text, with an ellipsis if it was cut short
+fn excerpt(text: &str, words: usize) -> String {
+    let mut iter = text.split_whitespace();
+    let mut excerpt = iter.by_ref().take(words).collect::<Vec<_>>().join(" ");
+    if iter.next().is_some() {
+        excerpt.push_str(" &hellip;");
+    }
+    excerpt
+}
+
+// replaces `{name}` placeholders in a template in a single pass
+// unknown placeholders and `{{{patterns}}}` are left alone
+pub(crate) fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest[1..].find('}').and_then(|end| {
+            let name = &rest[1..(end + 1)];
+            values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (end + 2, value))
+        });
+        match value {
+            Some((len, value)) => {
+                output.push_str(value);
+                rest = &rest[len..];
+            }
+            None => {
+                output.push('{');
+                rest = &rest[1..];
             }
         }
-        None => Ok(None),
     }
+    output.push_str(rest);
+    output
+}
+
+// renders the preview of an article using the configured preview template
+pub(crate) async fn preview(data: &ServerData<'_>, article: &Article) -> Result<String> {
+    let config = &data.config.preview;
+    let template = fs::read_to_string(&config.template).await?;
+    let (fm, html) = render(&PublicPath::try_from(article.path.as_str())?).await?;
+    let title = sync(data, &article.path, &article.title, fm.clone()).await?;
+    let text = strip_html(&html);
+    let summary = fm
+        .as_ref()
+        .and_then(|fm| fm.summary.clone())
+        .or_else(|| article.summary.clone());
+    let excerpt = match summary {
+        Some(summary) => escape(&summary),
+        // the text is HTML already, only its braces could be taken for patterns
+        None => excerpt(&text, config.excerpt_words).replace('{', "&#123;"),
+    };
+    let cover = fm
+        .and_then(|fm| fm.cover)
+        .or_else(|| article.cover.clone())
+        .map(|cover| format!("<img class=\"cover\" src=\"{}\" alt=\"\"/>", escape(&cover)))
+        .unwrap_or_else(String::new);
+    let words = text.split_whitespace().count();
+    let minutes = (words / config.words_per_minute.max(1)).max(1).to_string();
+    let by_author = by_author(data, article.author).await?;
+    let href = href(article);
+    Ok(fill(
+        &template,
+        &[
+            ("href", href.as_str()),
+            ("title", title.as_str()),
+            ("date", article.date.as_str()),
+            ("by_author", by_author.as_str()),
+            ("excerpt", excerpt.as_str()),
+            ("cover", cover.as_str()),
+            ("reading_time", minutes.as_str()),
+        ],
+    ))
+}
+
+// renders a whole article with its title, date and author
+async fn full(data: &ServerData<'_>, article: &Article) -> Result<String> {
+    let path = PublicPath::try_from(article.path.as_str())?;
+    if !path.exists() {
+        return Err(Error::ResourceNotFound(path.to_string_lossy().to_string()));
+    }
+    let (fm, contents) = render(&path).await?;
+    let title = sync(data, &article.path, &article.title, fm).await?;
+    let by_author = by_author(data, article.author).await?;
+    Ok(format!(
+        "<article><h1>{}</h1>{}{}<br/>{}</article>",
+        title, article.date, by_author, contents,
+    ))
 }
 
 impl FromStr for Pattern {
@@ -73,7 +313,25 @@ impl FromStr for Pattern {
         } else if pattern == "drafts" {
             Ok(Pattern::Drafts)
         } else if pattern == "admin-panel" {
-            Ok(Pattern::AdminPanel)
+            Ok(Pattern::AdminPanel(None))
+        } else if let Some(pos) = pattern.strip_prefix("admin-panel%") {
+            Ok(Pattern::AdminPanel(Some(pos.parse()?)))
+        } else if let Some(pos) = pattern.strip_prefix("user-details%") {
+            Ok(Pattern::UserDetails(pos.parse()?))
+        } else if pattern == "tokens" {
+            Ok(Pattern::Tokens)
+        } else if let Some(pos) = pattern.strip_prefix("audit-log%") {
+            Ok(Pattern::AuditLog(pos.parse()?))
+        } else if let Some(pos) = pattern.strip_prefix("author%") {
+            Ok(Pattern::Author(pos.parse()?))
+        } else if pattern == "csrf" {
+            Ok(Pattern::Csrf)
+        } else if pattern == "csrf-token" {
+            Ok(Pattern::CsrfToken)
+        } else if pattern == "nonce" {
+            Ok(Pattern::Nonce)
+        } else if pattern == "flash" {
+            Ok(Pattern::Flash)
         } else if pattern.starts_with("me.") {
             Ok(Pattern::Me(pattern[3..].to_string()))
         } else if pattern.starts_with('/') {
@@ -86,8 +344,20 @@ impl FromStr for Pattern {
             if &pattern[end..] != ")" {
                 return Err(Error::InvalidPattern(pattern.to_string()));
             }
-            let sub = &pattern[start..end];
-            Ok(Pattern::L10n(sub.to_string()))
+            // `l10n(key, name=value, count=%1)`
+            let mut parts = pattern[start..end].split(',').map(str::trim);
+            let key = parts.next().unwrap_or("").to_string();
+            let mut params = Vec::new();
+            for part in parts {
+                let mut param = part.splitn(2, '=');
+                match (param.next(), param.next()) {
+                    (Some(name), Some(value)) => {
+                        params.push((name.trim().to_string(), value.trim().to_string()))
+                    }
+                    _ => return Err(Error::InvalidPattern(pattern.to_string())),
+                }
+            }
+            Ok(Pattern::L10n(key, params))
         } else if pattern.starts_with("article%") {
             Ok(Pattern::ArticlePositional(
                 pattern["article%".len()..].parse()?,
@@ -104,6 +374,37 @@ impl FromStr for Pattern {
             Ok(Pattern::ArticleTitle(
                 pattern["article ".len()..].to_string(),
             ))
+        } else if let Some(pos) = pattern.strip_prefix("arg%") {
+            Ok(Pattern::Argument(pos.parse()?))
+        } else if let Some(pos) = pattern.strip_prefix("translations%") {
+            Ok(Pattern::Translations(pos.parse()?))
+        } else if let Some(pos) = pattern.strip_prefix("translate%") {
+            Ok(Pattern::Translate(pos.parse()?))
+        } else if pattern == "language-select" {
+            Ok(Pattern::LanguageSelect)
+        } else if pattern == "translation-editor" {
+            Ok(Pattern::TranslationEditor)
+        } else if pattern.starts_with("asset(") {
+            let start = "asset(".len();
+            let end = pattern.len() - 1;
+            if &pattern[end..] != ")" {
+                return Err(Error::InvalidPattern(pattern.to_string()));
+            }
+            Ok(Pattern::Asset(pattern[start..end].trim().to_string()))
+        } else if pattern.starts_with("value(") {
+            let start = "value(".len();
+            let end = pattern.len() - 1;
+            if &pattern[end..] != ")" {
+                return Err(Error::InvalidPattern(pattern.to_string()));
+            }
+            Ok(Pattern::Value(pattern[start..end].trim().to_string()))
+        } else if pattern.starts_with("error(") {
+            let start = "error(".len();
+            let end = pattern.len() - 1;
+            if &pattern[end..] != ")" {
+                return Err(Error::InvalidPattern(pattern.to_string()));
+            }
+            Ok(Pattern::FieldError(pattern[start..end].trim().to_string()))
         } else if pattern.starts_with("maybe(") {
             let start = "maybe(".len();
             let end = pattern.len() - 1;
@@ -121,18 +422,19 @@ impl FromStr for Pattern {
 impl Pattern {
     pub async fn to_string_nonrecursive(
         self,
-        identity: &Identity,
-        client: &psql::Client,
+        ctx: &Context,
+        data: &ServerData<'_>,
         lang: &Language,
         args: &[String],
     ) -> Result<String> {
         match self {
             Pattern::Empty => Ok(String::new()),
             Pattern::Login => {
-                match identity.identity() {
+                match &ctx.user {
+                    // names from before the validation may contain anything
                     Some(identity) => {
-                        Ok(format!("<span class=\"float-right\"><a href=\"/auth/logout.html\">{{{{{{l10n(logout)}}}}}}</a></span> \
-                                    <span class=\"float-right\"><a href=\"/account/me.html\">{{{{{{l10n(logged_in_as)}}}}}}: {}</a></span>", identity))
+                        Ok(format!("<span class=\"float-right\"><form class=\"logout\" action=\"/auth/logout.html\" method=\"post\">{{{{{{csrf}}}}}}<input type=\"submit\" value=\"{{{{{{l10n(logout)}}}}}}\"/></form></span> \
+                                    <span class=\"float-right\"><a href=\"/account/me.html\">{}</a></span>", lang.format("logged_in_as", &[("user", &escape(identity))])))
                     }
                     None => {
                         Ok("<span class=\"float-right\"><a href=\"/login.html\">{{{l10n(login)}}}</a></span> \
@@ -141,58 +443,35 @@ impl Pattern {
                 }
             }
             Pattern::Editor => {
-                match identity.identity() {
-                    Some(identity) => {
-                        // only employees are allowed to make new articles
-                        let user = client.query_opt(
-                            "select employees.id from employees where employees.uid = \
-                             (select users.id as uid from users where username = $1)",
-                            &[&identity]
-                        ).await?;
-                        if user.is_some() {
-                            Ok("<span class=\"float-right\"><a href=\"/account/editor.html\">{{{l10n(new_article)}}}</a></span>".to_string())
-                        } else {
-                            Err(Error::AuthorizationFailed)
-                        }
+                match ctx.logged_in(data).await? {
+                    // only employees are allowed to make new articles
+                    Some(user) if user.employee => {
+                        Ok("<span class=\"float-right\"><a href=\"/account/editor.html\">{{{l10n(new_article)}}}</a></span>".to_string())
                     }
-                    None => {
+                    _ => {
                         Err(Error::AuthorizationFailed)
                     }
                 }
             }
             Pattern::Admin => {
-                match identity.identity() {
-                    Some(identity) => {
-                        // only employees are allowed to make new articles
-                        let user = client.query_opt(
-                            "select admins.id from admins where admins.uid = \
-                             (select users.id as uid from users where username = $1)",
-                            &[&identity]
-                        ).await?;
-                        if user.is_some() {
-                            Ok("<span class=\"float-right\"><a href=\"/account/admin.html\">{{{l10n(admin_panel)}}}</a></span>".to_string())
-                        } else {
-                            Err(Error::AuthorizationFailed)
-                        }
+                match ctx.logged_in(data).await? {
+                    Some(user) if user.admin => {
+                        Ok("<span class=\"float-right\"><a href=\"/account/admin.html\">{{{l10n(admin_panel)}}}</a></span>".to_string())
                     }
-                    None => {
+                    _ => {
                         Err(Error::AuthorizationFailed)
                     }
                 }
             }
             Pattern::Drafts => {
-                match identity.identity() {
-                    Some(identity) => {
-                        let drafts = client.query(
-                            "select id, path, title from drafts where drafts.author = \
-                             (select users.id as author from users where username = $1)",
-                            &[&identity]
-                        ).await?;
+                match ctx.logged_in(data).await? {
+                    Some(user) => {
+                        let drafts = data.repo.drafts(user.id).await?;
                         if drafts.len() > 0 {
-                            let mut select = format!("<select oninput=\"load_draft()\" id=\"draft-select\" name=\"draft-select\" size=\"{}\">\n", drafts.len().min(5).max(2));
+                            let mut select = format!("<select id=\"draft-select\" name=\"draft-select\" size=\"{}\">\n", drafts.len().min(5).max(2));
                             for draft in drafts {
-                                let value = draft.get::<_, i32>("id");
-                                let mut title = draft.get::<_, Option<&str>>("title").unwrap_or("&lt;untitled&gt;");
+                                let value = draft.id;
+                                let mut title = draft.title.as_deref().unwrap_or("&lt;untitled&gt;");
                                 if title.is_empty() {
                                     title = "&lt;untitled&gt;";
                                 }
@@ -209,23 +488,27 @@ impl Pattern {
                     }
                 }
             }
-            Pattern::AdminPanel => {
-                match identity.identity() {
-                    Some(identity) => {
-                        let admin = client.query_opt(
-                            "select id from admins where uid = \
-                             (select id as uid from users where username = $1)",
-                            &[&identity]
-                        ).await?;
-                        if admin.is_none() {
-                            return Err(Error::AuthorizationFailed);
-                        }
-
-                        let users = client.query(
-                            "select id, username, firstname, lastname, email from users",
-                            &[]
-                        ).await?;
-                        let mut select = format!("<table>\n");
+            Pattern::AdminPanel(pos) => {
+                // the search is the query string of the admin panel, if it has one
+                let query = match pos {
+                    Some(pos) => {
+                        let query = args
+                            .get(pos - 1)
+                            .ok_or_else(|| Error::ResourceNotFound(format!("admin-panel%{}", pos)))?;
+                        UsersQuery::parse(query)?
+                    }
+                    None => UsersQuery::default(),
+                };
+                match ctx.logged_in(data).await? {
+                    Some(user) if user.admin => {
+                        let mut users = data.repo.search_users(&query.filter()).await?;
+                        let more = users.len() > users::PER_PAGE;
+                        users.truncate(users::PER_PAGE);
+                        let mut select = format!("<form class=\"user-search\" action=\"/account/admin.html\" method=\"get\">\n");
+                        write!(select, "<input type=\"search\" name=\"q\" value=\"{}\"/>\n", escape(query.query())).expect("couldn't write to string");
+                        write!(select, "<input type=\"submit\" value=\"{{{{{{l10n(users_search)}}}}}}\"/>\n").expect("couldn't write to string");
+                        write!(select, "</form>\n").expect("couldn't write to string");
+                        write!(select, "<table>\n").expect("couldn't write to string");
                         write!(select, "<tr>\n").expect("couldn't write to string");
                         write!(select, "<th>UID</th>\n").expect("couldn't write to string");
                         write!(select, "<th>{{{{{{l10n(account_username)}}}}}}</th>\n").expect("couldn't write to string");
@@ -234,229 +517,387 @@ impl Pattern {
                         write!(select, "<th>{{{{{{l10n(account_email)}}}}}}</th>\n").expect("couldn't write to string");
                         write!(select, "<th>{{{{{{l10n(account_isemployee)}}}}}}</th>\n").expect("couldn't write to string");
                         write!(select, "<th>{{{{{{l10n(account_isadmin)}}}}}}</th>\n").expect("couldn't write to string");
+                        write!(select, "<th>{{{{{{l10n(users_status)}}}}}}</th>\n").expect("couldn't write to string");
+                        write!(select, "<th></th>\n").expect("couldn't write to string");
                         write!(select, "</tr>\n").expect("couldn't write to string");
                         for user in users {
-                            let id = user.get::<_, i32>("id");
-                            let isadmin = client
-                                .query_opt(
-                                    "select id from admins where uid = \
-                                    (select id as uid from users where id = $1)",
-                                    &[&id]
-                                )
-                                .await?
-                                .is_some();
-                            let isemployee = client
-                                .query_opt(
-                                    "select id from employees where uid = \
-                                    (select id as uid from users where id = $1)",
-                                    &[&id]
-                                )
-                                .await?
-                                .is_some();
-                            let isadmin = if isadmin { "checked=\"checked\"" } else { "" };
-                            let isemployee = if isemployee { "checked=\"checked\"" } else { "" };
+                            let id = user.id;
+                            let isadmin = if user.admin { "checked=\"checked\"" } else { "" };
+                            let isemployee = if user.employee { "checked=\"checked\"" } else { "" };
                             write!(select, "<tr>\n").expect("couldn't write to string");
                             write!(select, "<td>{}</td>\n", id).expect("couldn't write to string");
-                            write!(select, "<td>{}</td>\n", user.get::<_, &str>("username")).expect("couldn't write to string");
-                            write!(select, "<td>{}</td>\n", user.get::<_, Option<&str>>("firstname").unwrap_or("")).expect("couldn't write to string");
-                            write!(select, "<td>{}</td>\n", user.get::<_, Option<&str>>("lastname").unwrap_or("")).expect("couldn't write to string");
-                            write!(select, "<td><a href=\"mailto:{0}\">{0}</a></td>\n", user.get::<_, &str>("email")).expect("couldn't write to string");
-                            write!(select, "<td><form><input type=\"checkbox\" {} oninput=\"make_employee(this, {})\"/></form></td>\n", isemployee, id).expect("couldn't write to string");
-                            write!(select, "<td><form><input type=\"checkbox\" {} oninput=\"make_admin(this, {})\"/></form></td>\n", isadmin, id).expect("couldn't write to string");
+                            write!(select, "<td>{}</td>\n", escape(&user.username)).expect("couldn't write to string");
+                            write!(select, "<td>{}</td>\n", escape(user.firstname.as_deref().unwrap_or(""))).expect("couldn't write to string");
+                            write!(select, "<td>{}</td>\n", escape(user.lastname.as_deref().unwrap_or(""))).expect("couldn't write to string");
+                            write!(select, "<td><a href=\"mailto:{0}\">{0}</a></td>\n", escape(&user.email)).expect("couldn't write to string");
+                            write!(select, "<td><form><input type=\"checkbox\" class=\"make-employee\" {} data-uid=\"{}\"/></form></td>\n", isemployee, id).expect("couldn't write to string");
+                            write!(select, "<td><form><input type=\"checkbox\" class=\"make-admin\" {} data-uid=\"{}\"/></form></td>\n", isadmin, id).expect("couldn't write to string");
+                            write!(select, "<td>{}</td>\n", users::status_label(user.status)).expect("couldn't write to string");
+                            write!(select, "<td><a href=\"/account/users/{}.html\">{{{{{{l10n(users_manage)}}}}}}</a></td>\n", id).expect("couldn't write to string");
                             write!(select, "</tr>\n").expect("couldn't write to string");
                         }
                         write!(select, "</table>\n").expect("couldn't write to string");
+                        let page = query.page();
+                        write!(select, "<nav class=\"pages\">\n").expect("couldn't write to string");
+                        if page > 1 {
+                            write!(select, "<a href=\"{}\">{{{{{{l10n(users_previous)}}}}}}</a>\n", query.link(page - 1)).expect("couldn't write to string");
+                        }
+                        if more {
+                            write!(select, "<a href=\"{}\">{{{{{{l10n(users_next)}}}}}}</a>\n", query.link(page + 1)).expect("couldn't write to string");
+                        }
+                        write!(select, "</nav>\n").expect("couldn't write to string");
                         Ok(select)
                     }
+                    _ => {
+                        Err(Error::AuthorizationFailed)
+                    }
+                }
+            }
+            Pattern::UserDetails(pos) => {
+                let uid = args
+                    .get(pos - 1)
+                    .ok_or_else(|| Error::ResourceNotFound(format!("user-details%{}", pos)))?;
+                match ctx.logged_in(data).await? {
+                    Some(user) if user.admin => {
+                        let uid = uid
+                            .parse::<i32>()
+                            .map_err(|_| Error::ResourceNotFound(format!("user {}", uid)))?;
+                        users::details(data, uid).await
+                    }
+                    _ => {
+                        Err(Error::AuthorizationFailed)
+                    }
+                }
+            }
+            Pattern::Tokens => {
+                match ctx.logged_in(data).await? {
+                    Some(user) => {
+                        let tokens = data.repo.tokens(user.id).await?;
+                        if tokens.is_empty() {
+                            return Ok(String::new());
+                        }
+                        let now = SystemTime::now();
+                        let mut table = "<table class=\"tokens\">\n".to_string();
+                        write!(table, "<tr>\n").expect("couldn't write to string");
+                        write!(table, "<th>{{{{{{l10n(tokens_name)}}}}}}</th>\n").expect("couldn't write to string");
+                        write!(table, "<th>{{{{{{l10n(tokens_scopes)}}}}}}</th>\n").expect("couldn't write to string");
+                        write!(table, "<th>{{{{{{l10n(tokens_created)}}}}}}</th>\n").expect("couldn't write to string");
+                        write!(table, "<th>{{{{{{l10n(tokens_expires)}}}}}}</th>\n").expect("couldn't write to string");
+                        write!(table, "<th>{{{{{{l10n(tokens_last_used)}}}}}}</th>\n").expect("couldn't write to string");
+                        write!(table, "<th></th>\n").expect("couldn't write to string");
+                        write!(table, "</tr>\n").expect("couldn't write to string");
+                        for token in tokens {
+                            let expired = token.expires.is_some_and(|expires| expires <= now);
+                            let class = if expired { "expired" } else { "" };
+                            let expires = token.expires.map(repo::date).unwrap_or_else(|| "{{{l10n(tokens_never)}}}".to_string());
+                            let last_used = token.last_used.map(repo::date).unwrap_or_else(|| "{{{l10n(tokens_never)}}}".to_string());
+                            write!(table, "<tr class=\"{}\">\n", class).expect("couldn't write to string");
+                            write!(table, "<td>{}</td>\n", escape(&token.name)).expect("couldn't write to string");
+                            write!(table, "<td>{}</td>\n", token.scopes.join(", ")).expect("couldn't write to string");
+                            write!(table, "<td>{}</td>\n", repo::date(token.created)).expect("couldn't write to string");
+                            write!(table, "<td>{}</td>\n", expires).expect("couldn't write to string");
+                            write!(table, "<td>{}</td>\n", last_used).expect("couldn't write to string");
+                            write!(table, "<td><form action=\"/account/tokens/revoke.html\" method=\"post\">{{{{{{csrf}}}}}}<input type=\"hidden\" name=\"id\" value=\"{}\"/><input type=\"submit\" value=\"{{{{{{l10n(tokens_revoke)}}}}}}\"/></form></td>\n", token.id).expect("couldn't write to string");
+                            write!(table, "</tr>\n").expect("couldn't write to string");
+                        }
+                        write!(table, "</table>\n").expect("couldn't write to string");
+                        Ok(table)
+                    }
                     None => {
                         Err(Error::AuthorizationFailed)
                     }
                 }
             }
+            Pattern::AuditLog(pos) => {
+                // the query string of the audit page
+                let query = args
+                    .get(pos - 1)
+                    .ok_or_else(|| Error::ResourceNotFound(format!("audit-log%{}", pos)))?;
+                match ctx.logged_in(data).await? {
+                    Some(user) if user.admin => {
+                        let query = AuditQuery::parse(query)?;
+                        audit::table(data, &query).await
+                    }
+                    _ => {
+                        Err(Error::AuthorizationFailed)
+                    }
+                }
+            }
+            Pattern::Author(pos) => {
+                let username = args
+                    .get(pos - 1)
+                    .ok_or_else(|| Error::ResourceNotFound(format!("author%{}", pos)))?;
+                profile::page(data, username).await
+            }
+            Pattern::Csrf => {
+                match &ctx.csrf {
+                    Some(token) => Ok(format!("<input type=\"hidden\" name=\"csrf_token\" value=\"{}\"/>", token)),
+                    None => Ok(String::new()),
+                }
+            }
+            Pattern::CsrfToken => {
+                Ok(ctx.csrf.clone().unwrap_or_default())
+            }
+            Pattern::Nonce => {
+                Ok(ctx.nonce.clone().unwrap_or_default())
+            }
+            Pattern::Flash => {
+                match &ctx.flash {
+                    Some(key) => Ok(format!("<p class=\"flash\">{{{{{{l10n({})}}}}}}</p>", key)),
+                    None => Ok(String::new()),
+                }
+            }
             Pattern::Me(field) => {
                 if field == "pwhash" {
                     Ok("No passwords for you!".to_string())
                 } else {
-                    match identity.identity() {
-                        Some(me) => {
-                            match client.query_opt("select * from users where username = $1", &[&me]).await? {
-                                Some(row) => Ok(row.get::<&str, &str>(&field).to_string()),
-                                None => Ok("".to_string()),
-                            }
-                        }
+                    // what users typed in is rendered as text
+                    // a profile sent back shows what was entered, see `profile::update`
+                    let entered = |field| ctx.form.as_ref().and_then(|form| form.value_of(field));
+                    match ctx.logged_in(data).await? {
+                        Some(me) => match field.as_str() {
+                            "id" => Ok(me.id.to_string()),
+                            "username" => Ok(escape(&me.username)),
+                            "email" => Ok(escape(&me.email)),
+                            "firstname" => Ok(escape(entered("firstname").or(me.firstname.as_deref()).unwrap_or(""))),
+                            "lastname" => Ok(escape(entered("lastname").or(me.lastname.as_deref()).unwrap_or(""))),
+                            "bio" => Ok(escape(entered("bio").or(me.bio.as_deref()).unwrap_or(""))),
+                            "avatar" => Ok(escape(entered("avatar").or(me.avatar.as_deref()).unwrap_or(""))),
+                            "website" => Ok(escape(entered("website").or(me.website.as_deref()).unwrap_or(""))),
+                            "page" => Ok(profile::href(&me)),
+                            _ => Err(Error::InvalidPattern(format!("me.{}", field))),
+                        },
                         None => Ok("".to_string()),
                     }
                 }
             }
             Pattern::Path(path) => {
                 let path = PublicPath::try_from(path)?;
-                let text = fs::read_to_string(&path).await?;
-                if path.extension() == Some("md".as_ref()) {
-                    let parser = md::Parser::new_ext(&text, md::Options::all());
-                    let mut html = String::new();
-                    md::html::push_html(&mut html, parser);
-                    Ok(html)
-                } else {
-                    Ok(text)
-                }
+                let (_, text) = render(&path).await?;
+                Ok(text)
             }
             Pattern::Positional(pos) => {
                 let path = args
                     .get(pos - 1)
                     .ok_or_else(|| Error::ResourceNotFound(format!("%{}", pos)))?;
                 let path = PublicPath::try_from(&**path)?;
-                let text = fs::read_to_string(&path).await?;
-                if path.extension() == Some("md".as_ref()) {
-                    let parser = md::Parser::new_ext(&text, md::Options::all());
-                    let mut html = String::new();
-                    md::html::push_html(&mut html, parser);
-                    Ok(html)
-                } else {
-                    Ok(text)
-                }
+                let (_, text) = render(&path).await?;
+                Ok(text)
             }
-            Pattern::L10n(key) => {
-                Ok(lang[&key].to_string())
+            Pattern::L10n(key, params) => {
+                // `%n` values refer to the positional arguments
+                let mut values = Vec::with_capacity(params.len());
+                for (name, value) in &params {
+                    let value = if let Some(pos) = value.strip_prefix('%') {
+                        let pos = pos.parse::<usize>()?;
+                        args.get(pos - 1)
+                            .ok_or_else(|| Error::ResourceNotFound(value.to_string()))?
+                    } else {
+                        value
+                    };
+                    values.push((name.as_str(), value.as_str()));
+                }
+                Ok(lang.format(&key, &values))
             }
             Pattern::ArticlePositional(pos) => {
                 let path = args
                     .get(pos - 1)
                     .ok_or_else(|| Error::ResourceNotFound(format!("%{}", pos)))?;
-                let args: &[&(dyn psql::types::ToSql + Sync)] = &[path];
-                let article = client
-                    .query_one("select title, to_char(cdate, 'yyyy-mm-dd') as date, author from articles where path = $1", args);
-                let contents = article
-                    .map_err(From::from)
-                    .and_then(async move |article| {
-                        let path = PublicPath::try_from(&**path)?;
-                        if path.exists() {
-                            let text = fs::read_to_string(&path)
-                                .await?;
-                            if path.extension() == Some("md".as_ref()) {
-                                let parser = md::Parser::new_ext(&text, md::Options::all());
-                                let mut html = String::new();
-                                md::html::push_html(&mut html, parser);
-                                Ok((article, html))
-                            } else {
-                                Ok((article, text))
-                            }
-                        } else {
-                            Err(Error::ResourceNotFound(path.to_string_lossy().to_string()))
-                        }
-                    });
-                contents.and_then(async move |(article, contents)| {
-                    let by_author = author(client, article.get::<_, i32>("author")).await?.map(|author| format!(" {{{{{{l10n(by_author)}}}}}} {}", author)).unwrap_or_else(String::new);
-                    Ok(format!(
-                        "<article><h1>{}</h1>{}{}<br/>{}</article>",
-                        article.get::<_, &str>("title"),
-                        article.get::<_, &str>("date"),
-                        by_author,
-                        contents,
-                    ))
-                }).await
+                let article = data
+                    .repo
+                    .article_by_path(path)
+                    .await?
+                    .ok_or_else(|| Error::ResourceNotFound(path.to_string()))?;
+                full(data, &article).await
             }
             Pattern::PreviewLatest(no) => {
-                let rows = client
-                    .query("select title, path, to_char(cdate, 'yyyy-mm-dd') as date, author from articles order by cdate", &[])
-                    .await?;
-                let article = rows.len().checked_sub(no).and_then(|no| rows.get(no)).ok_or_else(|| Error::ResourceNotFound(format!("preview~{}", no)))?;
-                let by_author = author(client, article.get::<_, i32>("author")).await?.map(|author| format!(" {{{{{{l10n(by_author)}}}}}} {}", author)).unwrap_or_else(String::new);
-                Ok(format!(
-                    "<article><h2><a href=\"{}\">{}</a></h2>{}{}</article>",
-                    article.get::<_, &str>("path"),
-                    article.get::<_, &str>("title"),
-                    article.get::<_, &str>("date"),
-                    by_author,
-                ))
+                let articles = data.repo.articles().await?;
+                let article = articles.len().checked_sub(no).and_then(|no| articles.get(no)).ok_or_else(|| Error::ResourceNotFound(format!("preview~{}", no)))?;
+                preview(data, article).await
             }
             Pattern::ArticleLatest(no) => {
-                let rows = client
-                    .query("select path, title, to_char(cdate, 'yyyy-mm-dd') as date, author from articles order by cdate", &[])
-                    .await?;
-                let article = rows.len().checked_sub(no).and_then(|no| rows.get(no));
-                let contents = article.map(|article| {
-                    future::ok(article)
-                        .and_then(async move |article| {
-                            let path = article.get::<_, &str>("path");
-                            let path = PublicPath::try_from(path)?;
-                            if path.exists() {
-                                let text = fs::read_to_string(&path)
-                                    .await?;
-                                if path.extension() == Some("md".as_ref()) {
-                                    let parser = md::Parser::new_ext(&text, md::Options::all());
-                                    let mut html = String::new();
-                                    md::html::push_html(&mut html, parser);
-                                    Ok((article, html))
-                                } else {
-                                    Ok((article, text))
-                                }
-                            } else {
-                                Err(Error::ResourceNotFound(path.to_string_lossy().to_string()))
-                            }
-                        })
-                });
-                if let Some(contents) = contents {
-                    contents.and_then(async move |(article, contents)| {
-                        let by_author = author(client, article.get::<_, i32>("author")).await?.map(|author| format!(" {{{{{{l10n(by_author)}}}}}} {}", author)).unwrap_or_else(String::new);
-                        Ok(format!(
-                            "<article><h1>{}</h1>{}{}<br/>{}</article>",
-                            article.get::<_, &str>("title"),
-                            article.get::<_, &str>("date"),
-                            by_author,
-                            contents,
-                        ))
-                    }).await
-                } else {
-                    Ok(String::new())
+                let articles = data.repo.articles().await?;
+                match articles.len().checked_sub(no).and_then(|no| articles.get(no)) {
+                    Some(article) => full(data, article).await,
+                    None => Ok(String::new()),
                 }
             }
             Pattern::PreviewTitle(title) => {
-                let article = client
-                    .query_one("select title, path, to_char(cdate, 'yyyy-mm-dd') as date, author from articles where title = $1", &[&title])
-                    .await?;
-                let by_author = author(client, article.get::<_, i32>("author")).await?.map(|author| format!(" {{{{{{l10n(by_author)}}}}}} {}", author)).unwrap_or_else(String::new);
-                Ok(format!(
-                    "<article><h2><a href=\"{}\">{}</a></h2>{}{}</article>",
-                    article.get::<_, &str>("path"),
-                    article.get::<_, &str>("title"),
-                    article.get::<_, &str>("date"),
-                    by_author,
-                ))
+                let article = data
+                    .repo
+                    .article_by_title(&title)
+                    .await?
+                    .ok_or_else(|| Error::ResourceNotFound(title.clone()))?;
+                preview(data, &article).await
             }
             Pattern::ArticleTitle(title) => {
-                let args: &[&(dyn psql::types::ToSql + Sync)] = &[&title];
-                let article = client
-                    .query_one("select title, to_char(cdate, 'yyyy-mm-dd') as date, author from articles where title = $1", args);
-                let contents = article
-                    .map_err(From::from)
-                    .and_then(async move |article| {
-                        let path = article.get::<_, &str>("path");
-                        let path = PublicPath::try_from(path)?;
-                        if path.exists() {
-                            let text = fs::read_to_string(&path)
-                                .await?;
-                            if path.extension() == Some("md".as_ref()) {
-                                let parser = md::Parser::new_ext(&text, md::Options::all());
-                                let mut html = String::new();
-                                md::html::push_html(&mut html, parser);
-                                Ok((article, html))
-                            } else {
-                                Ok((article, text))
+                let article = data
+                    .repo
+                    .article_by_title(&title)
+                    .await?
+                    .ok_or_else(|| Error::ResourceNotFound(title.clone()))?;
+                full(data, &article).await
+            }
+            Pattern::Argument(pos) => {
+                let arg = args
+                    .get(pos - 1)
+                    .ok_or_else(|| Error::ResourceNotFound(format!("arg%{}", pos)))?;
+                Ok(escape(arg))
+            }
+            Pattern::Translations(pos) => {
+                let path = args
+                    .get(pos - 1)
+                    .ok_or_else(|| Error::ResourceNotFound(format!("%{}", pos)))?;
+                let translations = data.repo.translations(path).await?;
+                if translations.len() < 2 {
+                    return Ok(String::new());
+                }
+                let mut nav = "<nav class=\"translations\">\n".to_string();
+                for translation in &translations {
+                    let code = match &translation.lang {
+                        Some(code) => code,
+                        None => continue,
+                    };
+                    let name = data.lang.get(code).map(|lang| lang.language().to_string()).unwrap_or_else(|| code.to_string());
+                    // switching the language picks the matching translation
+                    write!(nav, "<a href=\"/lang/{}.html?redirect={}\">{}</a>\n", code, href(translation), name).expect("couldn't write to string");
+                }
+                write!(nav, "</nav>\n").expect("couldn't write to string");
+                Ok(nav)
+            }
+            Pattern::Translate(pos) => {
+                let path = args
+                    .get(pos - 1)
+                    .ok_or_else(|| Error::ResourceNotFound(format!("%{}", pos)))?;
+                match ctx.logged_in(data).await? {
+                    // only employees are allowed to make new articles
+                    Some(user) if user.employee => {
+                        let article = data
+                            .repo
+                            .article_by_path(path)
+                            .await?
+                            .ok_or_else(|| Error::ResourceNotFound(path.to_string()))?;
+                        Ok(format!("<a href=\"/account/translate/{}.html\">{{{{{{l10n(translate)}}}}}}</a>", article.id))
+                    }
+                    _ => {
+                        Err(Error::AuthorizationFailed)
+                    }
+                }
+            }
+            Pattern::LanguageSelect => {
+                let mut select = "<select id=\"lang\" name=\"lang\">\n".to_string();
+                for code in data.lang.codes() {
+                    let name = data.lang.get(&code).map(|lang| lang.language().to_string()).unwrap_or_else(|| code.clone());
+                    let selected = if code == lang.code() { " selected=\"selected\"" } else { "" };
+                    write!(select, "<option value=\"{}\"{}>{}</option>\n", code, selected, name).expect("couldn't write to string");
+                }
+                write!(select, "</select>\n").expect("couldn't write to string");
+                Ok(select)
+            }
+            Pattern::TranslationEditor => {
+                match ctx.logged_in(data).await? {
+                    Some(user) if user.admin => {
+                        // keys missing from a language are highlighted as missing,
+                        // keys the default language doesn't have as stale
+                        let langs = data.lang.raw();
+                        let reference = langs.get(&data.config.i18n.default_language);
+                        let keys = langs.values().flat_map(|lang| lang.keys().map(str::to_string)).collect::<BTreeSet<_>>();
+                        let mut table = "<form action=\"/account/translations.html\" method=\"post\">\n{{{csrf}}}\n".to_string();
+                        write!(table, "<table class=\"translations\">\n").expect("couldn't write to string");
+                        write!(table, "<tr>\n").expect("couldn't write to string");
+                        write!(table, "<th>{{{{{{l10n(translations_key)}}}}}}</th>\n").expect("couldn't write to string");
+                        for lang in langs.values() {
+                            write!(table, "<th>{} ({})</th>\n", lang.language(), lang.code()).expect("couldn't write to string");
+                        }
+                        write!(table, "</tr>\n").expect("couldn't write to string");
+                        for key in &keys {
+                            // plural messages get a row for each form
+                            let forms = langs
+                                .values()
+                                .filter_map(|lang| match lang.message(key) {
+                                    Some(Message::Plural(forms)) => Some(forms.keys().copied()),
+                                    _ => None,
+                                })
+                                .flatten()
+                                .collect::<BTreeSet<_>>();
+                            // plain messages keep a row of their own, so they aren't lost
+                            // when only the plural forms of other languages are filled in
+                            let plain = langs.values().any(|lang| matches!(lang.message(key), Some(Message::Text(_))));
+                            let mut rows = Vec::new();
+                            if plain || forms.is_empty() {
+                                rows.push(None);
                             }
-                        } else {
-                            Err(Error::ResourceNotFound(path.to_string_lossy().to_string()))
+                            rows.extend(forms.into_iter().map(Some));
+                            for plural in rows {
+                                let label = match plural {
+                                    Some(plural) => format!("{} ({})", key, plural.name()),
+                                    None => key.clone(),
+                                };
+                                write!(table, "<tr>\n").expect("couldn't write to string");
+                                write!(table, "<td>{}</td>\n", escape(&label)).expect("couldn't write to string");
+                                for lang in langs.values() {
+                                    let message = lang.message(key);
+                                    let text = match (message, plural) {
+                                        (Some(Message::Plural(forms)), Some(plural)) => forms.get(&plural).map(String::as_str),
+                                        (Some(Message::Text(text)), None) => Some(text.as_str()),
+                                        _ => None,
+                                    };
+                                    let class = if message.is_none() {
+                                        "missing"
+                                    } else if reference.map(|reference| reference.message(key).is_none()).unwrap_or(false) {
+                                        "stale"
+                                    } else {
+                                        ""
+                                    };
+                                    // plural messages have no plain text to edit
+                                    if plural.is_none() && matches!(message, Some(Message::Plural(_))) {
+                                        write!(table, "<td class=\"{}\"></td>\n", class).expect("couldn't write to string");
+                                        continue;
+                                    }
+                                    let name = match plural {
+                                        Some(plural) => format!("{}:{}:{}", lang.code(), key, plural.name()),
+                                        None => format!("{}:{}", lang.code(), key),
+                                    };
+                                    write!(table, "<td class=\"{}\"><textarea name=\"{}\">{}</textarea></td>\n", class, escape(&name), text.map(escape).unwrap_or_else(String::new)).expect("couldn't write to string");
+                                }
+                                write!(table, "</tr>\n").expect("couldn't write to string");
+                            }
+                        }
+                        write!(table, "<tr>\n").expect("couldn't write to string");
+                        write!(table, "<td><input type=\"text\" name=\"new_key\"/></td>\n").expect("couldn't write to string");
+                        for lang in langs.values() {
+                            write!(table, "<td><textarea name=\"new:{}\"></textarea></td>\n", lang.code()).expect("couldn't write to string");
                         }
-                    });
-                contents.and_then(async move |(article, contents)| {
-                    let by_author = author(client, article.get::<_, i32>("author")).await?.map(|author| format!(" {{{{{{l10n(by_author)}}}}}} {}", author)).unwrap_or_else(String::new);
-                    Ok(format!(
-                        "<article><h1>{}</h1>{}{}<br/>{}</article>",
-                        article.get::<_, &str>("title"),
-                        article.get::<_, &str>("date"),
-                        by_author,
-                        contents,
-                    ))
-                }).await
+                        write!(table, "</tr>\n").expect("couldn't write to string");
+                        write!(table, "</table>\n").expect("couldn't write to string");
+                        write!(table, "<input type=\"submit\" value=\"{{{{{{l10n(translations_save)}}}}}}\"/>\n").expect("couldn't write to string");
+                        write!(table, "</form>\n").expect("couldn't write to string");
+                        Ok(table)
+                    }
+                    _ => {
+                        Err(Error::AuthorizationFailed)
+                    }
+                }
+            }
+            Pattern::Asset(url) => {
+                // links to the current version, which can be cached forever
+                let file = PublicPath::try_from(url.trim_start_matches('/'))?;
+                let asset = data.assets.get(file).await?;
+                Ok(format!("{}?v={}", url, asset.hash))
+            }
+            Pattern::Value(field) => {
+                // empty on forms which weren't submitted yet
+                let value = ctx.form.as_ref().and_then(|form| form.value_of(&field)).unwrap_or("");
+                Ok(escape(value))
+            }
+            Pattern::FieldError(field) => {
+                let mut errors = String::new();
+                if let Some(form) = &ctx.form {
+                    for error in form.errors_of(&field) {
+                        write!(errors, "<span class=\"field-error\">{}</span>\n", error.pattern()).expect("couldn't write to string");
+                    }
+                }
+                Ok(errors)
             }
             Pattern::Maybe(_) => {
                 Err(Error::AsyncRecursion)
@@ -466,19 +907,19 @@ impl Pattern {
 
     pub async fn to_string(
         self,
-        identity: &Identity,
-        client: &psql::Client,
+        ctx: &Context,
+        data: &ServerData<'_>,
         lang: &Language,
         args: &[String],
     ) -> Result<String> {
         match self {
             Pattern::Maybe(opt) => Ok(opt
-                .to_string_nonrecursive(identity, client, lang, args)
+                .to_string_nonrecursive(ctx, data, lang, args)
                 .await
                 .unwrap_or_else(|_| String::new())),
             other => {
                 other
-                    .to_string_nonrecursive(identity, client, lang, args)
+                    .to_string_nonrecursive(ctx, data, lang, args)
                     .await
             }
         }
@@ -486,23 +927,23 @@ impl Pattern {
 
     pub async fn replace_at(
         self,
-        identity: &Identity,
-        client: &psql::Client,
+        ctx: &Context,
+        data: &ServerData<'_>,
         lang: &Language,
         input: &mut String,
         start: usize,
         end: usize,
         args: &[String],
     ) -> Result<usize> {
-        let text = self.to_string(identity, client, lang, args).await?;
+        let text = self.to_string(ctx, data, lang, args).await?;
         input.replace_range(start..(end + 3), &text);
         Ok(text.len())
     }
 }
 
 async fn replace_at(
-    identity: &Identity,
-    client: &psql::Client,
+    ctx: &Context,
+    data: &ServerData<'_>,
     lang: &Language,
     input: &mut String,
     start: usize,
@@ -513,7 +954,7 @@ async fn replace_at(
         let pattern = &input[(start + 3)..end];
         let pattern = pattern.parse().unwrap_or(Pattern::Empty);
         pattern
-            .replace_at(identity, client, lang, input, start, end, args)
+            .replace_at(ctx, data, lang, input, start, end, args)
             .await
     } else {
         Ok(0)
@@ -521,8 +962,8 @@ async fn replace_at(
 }
 
 pub async fn search_replace(
-    identity: &Identity,
-    client: &psql::Client,
+    ctx: &Context,
+    data: &ServerData<'_>,
     lang: &Language,
     input: &mut String,
     args: &[String],
@@ -531,7 +972,7 @@ pub async fn search_replace(
     loop {
         match input[i..].find("{{{") {
             Some(idx) => {
-                let len = replace_at(identity, client, lang, input, idx, args).await?;
+                let len = replace_at(ctx, data, lang, input, idx, args).await?;
                 i = idx + len;
             }
             None => break Ok(()),
@@ -540,8 +981,8 @@ pub async fn search_replace(
 }
 
 pub async fn search_replace_recursive(
-    identity: &Identity,
-    client: &psql::Client,
+    ctx: &Context,
+    data: &ServerData<'_>,
     lang: &Language,
     input: &mut String,
     args: &[String],
@@ -549,7 +990,7 @@ pub async fn search_replace_recursive(
     loop {
         match input.find("{{{") {
             Some(idx) => {
-                replace_at(identity, client, lang, input, idx, args).await?;
+                replace_at(ctx, data, lang, input, idx, args).await?;
             }
             None => break Ok(()),
         }
@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+
+use actix_web::{http, HttpRequest, HttpResponse};
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{NoClientAuth, ResolvesServerCert, ServerConfig, SignatureScheme};
+use tokio::signal::unix::{signal, SignalKind};
+
+use crate::config::TlsConfig;
+use crate::error::{Error, Result};
+
+// hands out the current certificate, which can be swapped while the server
+// is running without dropping any connections
+#[derive(Clone)]
+pub struct CertResolver(Arc<RwLock<CertifiedKey>>);
+
+impl CertResolver {
+    pub fn load(config: &TlsConfig) -> Result<Self> {
+        Ok(Self(Arc::new(RwLock::new(certified_key(config)?))))
+    }
+
+    pub fn reload(&self, config: &TlsConfig) -> Result<()> {
+        let key = certified_key(config)?;
+        *self.0.write().expect("certificate lock is poisoned") = key;
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(
+        &self,
+        _server_name: Option<webpki::DNSNameRef>,
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<CertifiedKey> {
+        Some(self.0.read().expect("certificate lock is poisoned").clone())
+    }
+}
+
+// reads the certificate chain and the PKCS#8 or RSA private key
+fn certified_key(config: &TlsConfig) -> Result<CertifiedKey> {
+    let mut file = BufReader::new(File::open(&config.cert)?);
+    let certs = pemfile::certs(&mut file)
+        .map_err(|_| Error::Tls(format!("invalid certificate {:?}", config.cert)))?;
+    if certs.is_empty() {
+        return Err(Error::Tls(format!("no certificate in {:?}", config.cert)));
+    }
+    let mut file = BufReader::new(File::open(&config.key)?);
+    let mut keys = pemfile::pkcs8_private_keys(&mut file)
+        .map_err(|_| Error::Tls(format!("invalid private key {:?}", config.key)))?;
+    if keys.is_empty() {
+        let mut file = BufReader::new(File::open(&config.key)?);
+        keys = pemfile::rsa_private_keys(&mut file)
+            .map_err(|_| Error::Tls(format!("invalid private key {:?}", config.key)))?;
+    }
+    let key = keys
+        .first()
+        .ok_or_else(|| Error::Tls(format!("no private key in {:?}", config.key)))?;
+    let key = sign::any_supported_type(key)
+        .map_err(|_| Error::Tls(format!("unsupported private key {:?}", config.key)))?;
+    Ok(CertifiedKey::new(certs, Arc::new(key)))
+}
+
+pub fn server_config(resolver: CertResolver) -> ServerConfig {
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = Arc::new(resolver);
+    config
+}
+
+// reloads the certificate on SIGHUP, e.g. after it was renewed
+// a broken certificate is reported and the old one is kept
+pub fn reload_on_hangup(resolver: CertResolver, config: TlsConfig) -> Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+    actix_rt::spawn(async move {
+        while hangup.recv().await.is_some() {
+            match resolver.reload(&config) {
+                Ok(()) => eprintln!("reloaded certificate {:?}", config.cert),
+                Err(err) => eprintln!("couldn't reload certificate: {}", err),
+            }
+        }
+    });
+    Ok(())
+}
+
+// the value of the `Strict-Transport-Security` header
+pub fn hsts(config: &TlsConfig) -> String {
+    if config.hsts_subdomains {
+        format!("max-age={}; includeSubDomains", config.hsts_max_age)
+    } else {
+        format!("max-age={}", config.hsts_max_age)
+    }
+}
+
+// the port the HTTPS listener is bound to
+pub fn port(config: &TlsConfig) -> u16 {
+    config
+        .bind
+        .rsplit(':')
+        .next()
+        .and_then(|port| port.parse().ok())
+        .unwrap_or(443)
+}
+
+// answers every plain HTTP request with a redirect to the same URL over HTTPS
+pub async fn redirect(req: HttpRequest, port: u16) -> HttpResponse {
+    let info = req.connection_info();
+    let host = info.host();
+    // the host may come with the port of the plain HTTP listener
+    let host = match host.rfind(':') {
+        Some(idx) if !host[idx..].contains(']') => &host[..idx],
+        _ => host,
+    };
+    let port = if port == 443 {
+        String::new()
+    } else {
+        format!(":{}", port)
+    };
+    let path = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    HttpResponse::PermanentRedirect()
+        .header(http::header::LOCATION, format!("https://{}{}{}", host, port, path))
+        .finish()
+}
@@ -0,0 +1,232 @@
+use std::fmt::Write;
+use std::time::{Duration, SystemTime};
+
+use actix_identity::Identity;
+use actix_web::{http, post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::audit::{Action, Event};
+use crate::error::{Error, Result};
+use crate::i18n::Lang;
+use crate::repo::{NewApiToken, User};
+use crate::template::{self, Context};
+use crate::web::ServerData;
+
+// makes leaked tokens easy to recognize
+const PREFIX: &str = "circus_";
+
+const DAY: u64 = 24 * 60 * 60;
+
+// what a token may be used for, reading public resources is always allowed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    // creating, changing and deleting articles
+    Articles,
+    // reading drafts
+    Drafts,
+    // managing users and their roles
+    Users,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Articles, Scope::Drafts, Scope::Users];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scope::Articles => "articles",
+            Scope::Drafts => "drafts",
+            Scope::Users => "users",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|scope| scope.name() == name)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RevokeData {
+    id: i32,
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02x}", byte).expect("couldn't write to string");
+    }
+    hex
+}
+
+// a new secret, it is only shown to the user once
+pub fn generate() -> String {
+    let bytes: [u8; 32] = rand::random();
+    format!("{}{}", PREFIX, hex(&bytes))
+}
+
+// secrets are random enough that a fast hash is as good as a password hash
+pub fn hash(secret: &str) -> String {
+    hex(&Sha256::digest(secret.as_bytes()))
+}
+
+// the secret of an `Authorization: Bearer` header
+pub(crate) fn bearer(headers: &http::HeaderMap) -> Option<&str> {
+    let value = headers
+        .get(http::header::AUTHORIZATION)?
+        .to_str()
+        .ok()?;
+    let scheme = "bearer ";
+    if value.len() > scheme.len() && value[..scheme.len()].eq_ignore_ascii_case(scheme) {
+        Some(value[scheme.len()..].trim())
+    } else {
+        None
+    }
+}
+
+// the owner of a valid token, which has to allow `scope` if one is given
+pub(crate) async fn authenticate(
+    data: &ServerData<'_>,
+    secret: &str,
+    scope: Option<Scope>,
+) -> Result<User> {
+    let token = data
+        .repo
+        .token_by_hash(&hash(secret))
+        .await?
+        .ok_or(Error::AuthenticationFailed)?;
+    if token.expires.is_some_and(|expires| expires <= SystemTime::now()) {
+        return Err(Error::AuthenticationFailed);
+    }
+    if let Some(scope) = scope {
+        if !token.scopes.iter().any(|name| name == scope.name()) {
+            return Err(Error::AuthorizationFailed);
+        }
+    }
+    let user = data
+        .repo
+        .user(token.user)
+        .await?
+        .filter(User::active)
+        .ok_or(Error::AuthenticationFailed)?;
+    data.repo.touch_token(token.id).await?;
+    Ok(user)
+}
+
+// `name`, `expires` in days, empty for never, and any number of `scope`s
+#[post("/account/tokens.html")]
+pub async fn create<'a>(
+    token_data: web::Form<Vec<(String, String)>>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        let mut name = String::new();
+        let mut expires = None;
+        let mut scopes = Vec::new();
+        for (key, value) in token_data.into_inner() {
+            match key.as_str() {
+                "name" => name = value.trim().to_string(),
+                "expires" if !value.is_empty() => {
+                    let expiry = value
+                        .parse::<u64>()
+                        .ok()
+                        .and_then(|days| days.checked_mul(DAY))
+                        .and_then(|secs| SystemTime::now().checked_add(Duration::from_secs(secs)))
+                        .ok_or_else(|| {
+                            Error::InvalidToken(format!("{:?} isn't a number of days", value))
+                        })?;
+                    expires = Some(expiry);
+                }
+                "scope" => {
+                    let scope = Scope::from_name(&value).ok_or_else(|| {
+                        Error::InvalidToken(format!("unknown scope {:?}", value))
+                    })?;
+                    if !scopes.iter().any(|name| name == scope.name()) {
+                        scopes.push(scope.name().to_string());
+                    }
+                }
+                _ => {}
+            }
+        }
+        if name.is_empty() {
+            return Err(Error::InvalidToken("name is empty".to_string()));
+        }
+        let secret = generate();
+        let event = Event::new(Some(&user), Action::CreateToken)
+            .target(&name)
+            .after(scopes.join(" "));
+        data.repo
+            .create_token(NewApiToken {
+                user: user.id,
+                name: name.clone(),
+                hash: hash(&secret),
+                scopes,
+                expires,
+            })
+            .await?;
+        event.record(&data, &req).await?;
+        let mut body = fs::read_to_string("private/token.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[secret, name],
+        )
+        .await?;
+        // the secret mustn't end up in any cache
+        Ok(HttpResponse::Ok()
+            .header(http::header::CONTENT_TYPE, "text/html")
+            .header(http::header::CACHE_CONTROL, "no-store")
+            .body(body))
+    } else {
+        let mut body = fs::read_to_string("private/forbidden.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[],
+        )
+        .await?;
+        Ok(HttpResponse::Forbidden().body(body))
+    }
+}
+
+#[post("/account/tokens/revoke.html")]
+pub async fn revoke<'a>(
+    revoke_data: web::Form<RevokeData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    if let Some(user) = data.user(&identity).await? {
+        if !data.repo.revoke_token(revoke_data.id, user.id).await? {
+            return Err(Error::ResourceNotFound(format!("token {}", revoke_data.id)));
+        }
+        Event::new(Some(&user), Action::RevokeToken)
+            .target(format!("token {}", revoke_data.id))
+            .record(&data, &req)
+            .await?;
+        Ok(HttpResponse::SeeOther()
+            .header("Location", "/account/me.html")
+            .finish())
+    } else {
+        let mut body = fs::read_to_string("private/forbidden.html").await?;
+        template::search_replace_recursive(
+            &Context::new(&req, &identity),
+            &data,
+            &lang,
+            &mut body,
+            &[],
+        )
+        .await?;
+        Ok(HttpResponse::Forbidden().body(body))
+    }
+}
@@ -0,0 +1,475 @@
+use std::fmt::Write;
+use std::io::ErrorKind;
+
+use actix_identity::Identity;
+use actix_web::{get, http, post, web, HttpRequest, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+use crate::audit::{Action, Event};
+use crate::error::{Error, Result};
+use crate::i18n::Lang;
+use crate::repo::{self, User, UserFilter, UserStatus, UserUpdate};
+use crate::template::{self, escape, href, Context};
+use crate::validate;
+use crate::web::ServerData;
+
+pub(crate) const PER_PAGE: usize = 50;
+
+// the search of the admin panel, see `Pattern::AdminPanel`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UsersQuery {
+    // a part of the username, the e-mail address or the names
+    q: String,
+    // starts at 1
+    page: usize,
+}
+
+impl UsersQuery {
+    pub(crate) fn parse(query: &str) -> Result<Self> {
+        serde_urlencoded::from_str(query).map_err(|err| Error::InvalidQuery(err.to_string()))
+    }
+
+    pub(crate) fn query(&self) -> &str {
+        &self.q
+    }
+
+    pub(crate) fn page(&self) -> usize {
+        self.page.max(1)
+    }
+
+    // one more than fits on the page, to know if there is a next one
+    pub(crate) fn filter(&self) -> UserFilter {
+        let query = self.q.trim();
+        UserFilter {
+            query: if query.is_empty() {
+                None
+            } else {
+                Some(query.to_string())
+            },
+            limit: Some(PER_PAGE + 1),
+            offset: (self.page() - 1) * PER_PAGE,
+        }
+    }
+
+    pub(crate) fn link(&self, page: usize) -> String {
+        let query = UsersQuery {
+            page,
+            ..self.clone()
+        };
+        let query = serde_urlencoded::to_string(&query).unwrap_or_else(|_| String::new());
+        format!("/account/admin.html?{}", escape(&query))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateUserData {
+    firstname: String,
+    lastname: String,
+    email: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusData {
+    status: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeleteUserData {
+    // uid of who gets the articles, empty to keep them without an author
+    #[serde(default)]
+    heir: String,
+}
+
+pub(crate) fn optional(text: String) -> Option<String> {
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+// the name of a status in the visitor's language
+// spelled out instead of assembled so `l10n-check` sees the keys
+pub(crate) fn status_label(value: UserStatus) -> &'static str {
+    match value {
+        UserStatus::Active => "{{{l10n(users_status_active)}}}",
+        UserStatus::Disabled => "{{{l10n(users_status_disabled)}}}",
+        UserStatus::Banned => "{{{l10n(users_status_banned)}}}",
+    }
+}
+
+// the page of a user, the forms on it post to `{base}/{form}.html`
+fn base(uid: i32) -> String {
+    format!("/account/users/{}", uid)
+}
+
+fn location(uid: i32) -> String {
+    format!("{}.html", base(uid))
+}
+
+async fn find(data: &ServerData<'_>, uid: i32) -> Result<User> {
+    data.repo
+        .user(uid)
+        .await?
+        .ok_or_else(|| Error::ResourceNotFound(format!("user {}", uid)))
+}
+
+// admins can't lock themselves out
+fn not_me(admin: &User, uid: i32) -> Result<()> {
+    if admin.id == uid {
+        Err(Error::IllegalResource(format!("user {}", uid)))
+    } else {
+        Ok(())
+    }
+}
+
+// the forms to manage a user, with their articles and drafts
+pub(crate) async fn details(data: &ServerData<'_>, uid: i32) -> Result<String> {
+    let user = find(data, uid).await?;
+    let base = base(uid);
+
+    let mut html = format!("<h1>{}</h1>\n", escape(&user.display_name()));
+    if user.reset_password {
+        write!(html, "<p>{{{{{{l10n(users_reset_pending)}}}}}}</p>\n").expect("couldn't write to string");
+    }
+    if let Some(time) = user.delete_after {
+        write!(html, "<p>{{{{{{l10n(users_deletion_pending)}}}}}} {}</p>\n", repo::date(time)).expect("couldn't write to string");
+    }
+
+    write!(html, "<div class=\"update-account\">\n").expect("couldn't write to string");
+    write!(html, "<form action=\"{}.html\" method=\"post\">{{{{{{csrf}}}}}}\n", base).expect("couldn't write to string");
+    for (name, value, label) in &[
+        ("firstname", user.firstname.as_deref().unwrap_or(""), "{{{l10n(account_firstname)}}}"),
+        ("lastname", user.lastname.as_deref().unwrap_or(""), "{{{l10n(account_lastname)}}}"),
+        ("email", user.email.as_str(), "{{{l10n(account_email)}}}"),
+    ] {
+        write!(html, "<label class=\"login-label\" for=\"{}\">{}:</label>\n", name, label).expect("couldn't write to string");
+        write!(html, "<input type=\"text\" id=\"{0}\" name=\"{0}\" value=\"{1}\"/></br>\n", name, escape(value)).expect("couldn't write to string");
+    }
+    write!(html, "<input type=\"submit\" value=\"{{{{{{l10n(account_submit)}}}}}}\"/>\n").expect("couldn't write to string");
+    write!(html, "</form>\n").expect("couldn't write to string");
+    write!(html, "</div>\n").expect("couldn't write to string");
+
+    write!(html, "<div class=\"update-account\">\n").expect("couldn't write to string");
+    write!(html, "<form action=\"{}/status.html\" method=\"post\">{{{{{{csrf}}}}}}\n", base).expect("couldn't write to string");
+    write!(html, "<label class=\"login-label\" for=\"status\">{{{{{{l10n(users_status)}}}}}}:</label>\n").expect("couldn't write to string");
+    write!(html, "<select id=\"status\" name=\"status\">\n").expect("couldn't write to string");
+    for option in UserStatus::ALL.iter() {
+        let selected = if *option == user.status { " selected=\"selected\"" } else { "" };
+        write!(html, "<option value=\"{}\"{}>{}</option>\n", option.name(), selected, status_label(*option)).expect("couldn't write to string");
+    }
+    write!(html, "</select>\n").expect("couldn't write to string");
+    write!(html, "<input type=\"submit\" value=\"{{{{{{l10n(account_submit)}}}}}}\"/>\n").expect("couldn't write to string");
+    write!(html, "</form>\n").expect("couldn't write to string");
+    write!(html, "</div>\n").expect("couldn't write to string");
+
+    write!(html, "<div class=\"update-account\">\n").expect("couldn't write to string");
+    write!(html, "<form action=\"{}/reset.html\" method=\"post\">{{{{{{csrf}}}}}}\n", base).expect("couldn't write to string");
+    write!(html, "<input type=\"submit\" value=\"{{{{{{l10n(users_force_reset)}}}}}}\"/>\n").expect("couldn't write to string");
+    write!(html, "</form>\n").expect("couldn't write to string");
+    write!(html, "</div>\n").expect("couldn't write to string");
+
+    // employees can take over the articles
+    let heirs = data
+        .repo
+        .users()
+        .await?
+        .into_iter()
+        .filter(|heir| heir.employee && heir.id != uid);
+    write!(html, "<div class=\"update-account\">\n").expect("couldn't write to string");
+    write!(html, "<form action=\"{}/delete.html\" method=\"post\">{{{{{{csrf}}}}}}\n", base).expect("couldn't write to string");
+    write!(html, "<label class=\"login-label\" for=\"heir\">{{{{{{l10n(users_articles_to)}}}}}}:</label>\n").expect("couldn't write to string");
+    write!(html, "<select id=\"heir\" name=\"heir\">\n").expect("couldn't write to string");
+    write!(html, "<option value=\"\">{{{{{{l10n(users_anonymize)}}}}}}</option>\n").expect("couldn't write to string");
+    for heir in heirs {
+        write!(html, "<option value=\"{}\">{}</option>\n", heir.id, escape(&heir.username)).expect("couldn't write to string");
+    }
+    write!(html, "</select>\n").expect("couldn't write to string");
+    write!(html, "<input type=\"submit\" value=\"{{{{{{l10n(users_delete)}}}}}}\"/>\n").expect("couldn't write to string");
+    write!(html, "</form>\n").expect("couldn't write to string");
+    write!(html, "</div>\n").expect("couldn't write to string");
+
+    let articles = data
+        .repo
+        .articles()
+        .await?
+        .into_iter()
+        .filter(|article| article.author == Some(uid))
+        .collect::<Vec<_>>();
+    write!(html, "<h2>{{{{{{l10n(users_articles)}}}}}}</h2>\n").expect("couldn't write to string");
+    if articles.is_empty() {
+        write!(html, "<p>{{{{{{l10n(users_none)}}}}}}</p>\n").expect("couldn't write to string");
+    } else {
+        write!(html, "<ul class=\"user-articles\">\n").expect("couldn't write to string");
+        for article in &articles {
+            write!(html, "<li><a href=\"{}\">{}</a> {}</li>\n", href(article), escape(&article.title), article.date).expect("couldn't write to string");
+        }
+        write!(html, "</ul>\n").expect("couldn't write to string");
+    }
+
+    let drafts = data.repo.drafts(uid).await?;
+    write!(html, "<h2>{{{{{{l10n(users_drafts)}}}}}}</h2>\n").expect("couldn't write to string");
+    if drafts.is_empty() {
+        write!(html, "<p>{{{{{{l10n(users_none)}}}}}}</p>\n").expect("couldn't write to string");
+    } else {
+        write!(html, "<ul class=\"user-drafts\">\n").expect("couldn't write to string");
+        for draft in &drafts {
+            write!(html, "<li>{}</li>\n", escape(draft.title.as_deref().unwrap_or(&draft.path))).expect("couldn't write to string");
+        }
+        write!(html, "</ul>\n").expect("couldn't write to string");
+    }
+    Ok(html)
+}
+
+#[get("/account/users/{id}.html")]
+pub async fn show<'a>(
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<i32>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(admin) if admin.admin => {
+            let mut body = fs::read_to_string("public/account/user.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[info.to_string()],
+            )
+            .await?;
+            Ok(HttpResponse::Ok()
+                .header(http::header::CONTENT_TYPE, "text/html")
+                .body(body))
+        }
+        _ => {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            Ok(HttpResponse::Forbidden().body(body))
+        }
+    }
+}
+
+#[post("/account/users/{id}.html")]
+pub async fn update<'a>(
+    user_data: web::Form<UpdateUserData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<i32>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(admin) if admin.admin => {
+            let user = find(&data, *info).await?;
+            let user_data = user_data.into_inner();
+            let email = user_data.email.trim().to_string();
+            if let Some(error) = validate::email(&email) {
+                return Err(Error::InvalidCreateUser(error.to_string()));
+            }
+            let update = UserUpdate {
+                email,
+                firstname: optional(user_data.firstname),
+                lastname: optional(user_data.lastname),
+            };
+            let before = format!(
+                "{} {} <{}>",
+                user.firstname.as_deref().unwrap_or(""),
+                user.lastname.as_deref().unwrap_or(""),
+                user.email
+            );
+            let after = format!(
+                "{} {} <{}>",
+                update.firstname.as_deref().unwrap_or(""),
+                update.lastname.as_deref().unwrap_or(""),
+                update.email
+            );
+            data.repo.update_user(user.id, update).await?;
+            Event::new(Some(&admin), Action::UpdateUser)
+                .target(format!("user {}", user.id))
+                .before(before)
+                .after(after)
+                .record(&data, &req)
+                .await?;
+            Ok(HttpResponse::SeeOther()
+                .header("Location", location(user.id))
+                .finish())
+        }
+        _ => {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            Ok(HttpResponse::Forbidden().body(body))
+        }
+    }
+}
+
+// disabling or banning a user ends their sessions and blocks their tokens
+#[post("/account/users/{id}/status.html")]
+pub async fn status<'a>(
+    status_data: web::Form<StatusData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<i32>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(admin) if admin.admin => {
+            let user = find(&data, *info).await?;
+            not_me(&admin, user.id)?;
+            let new_status = UserStatus::from_name(&status_data.status).ok_or_else(|| {
+                Error::InvalidQuery(format!("unknown status {:?}", status_data.status))
+            })?;
+            if data.repo.set_status(user.id, new_status).await? {
+                Event::new(Some(&admin), Action::SetStatus)
+                    .target(format!("user {}", user.id))
+                    .before(user.status.name())
+                    .after(new_status.name())
+                    .record(&data, &req)
+                    .await?;
+            }
+            Ok(HttpResponse::SeeOther()
+                .header("Location", location(user.id))
+                .finish())
+        }
+        _ => {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            Ok(HttpResponse::Forbidden().body(body))
+        }
+    }
+}
+
+// the user has to choose a new password the next time they log in
+#[post("/account/users/{id}/reset.html")]
+pub async fn force_reset<'a>(
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<i32>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(admin) if admin.admin => {
+            let user = find(&data, *info).await?;
+            not_me(&admin, user.id)?;
+            data.repo.set_reset_password(user.id, true).await?;
+            Event::new(Some(&admin), Action::ForcePasswordReset)
+                .target(format!("user {}", user.id))
+                .record(&data, &req)
+                .await?;
+            Ok(HttpResponse::SeeOther()
+                .header("Location", location(user.id))
+                .finish())
+        }
+        _ => {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            Ok(HttpResponse::Forbidden().body(body))
+        }
+    }
+}
+
+// removes the user and their drafts, the articles are reassigned or lose their author
+pub(crate) async fn delete_user(data: &ServerData<'_>, user: &User, heir: Option<i32>) -> Result<()> {
+    let drafts = data.repo.drafts(user.id).await?;
+    data.repo.delete_user(user.id, heir).await?;
+    for draft in drafts {
+        match fs::remove_file(&draft.path).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[post("/account/users/{id}/delete.html")]
+pub async fn delete<'a>(
+    delete_data: web::Form<DeleteUserData>,
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<i32>,
+) -> Result<impl Responder> {
+    let lang = data.language(&lang);
+    match data.user(&identity).await? {
+        Some(admin) if admin.admin => {
+            let user = find(&data, *info).await?;
+            not_me(&admin, user.id)?;
+            let heir = match delete_data.heir.trim() {
+                "" => None,
+                heir => {
+                    let heir = heir
+                        .parse::<i32>()
+                        .map_err(|_| Error::ResourceNotFound(format!("user {}", heir)))?;
+                    let heir = find(&data, heir).await?;
+                    if heir.id == user.id || !heir.employee {
+                        return Err(Error::IllegalResource(format!("user {}", heir.id)));
+                    }
+                    Some(heir)
+                }
+            };
+            delete_user(&data, &user, heir.as_ref().map(|heir| heir.id)).await?;
+            let mut event = Event::new(Some(&admin), Action::DeleteUser)
+                .target(format!("user {}", user.id))
+                .before(&user.username);
+            if let Some(heir) = &heir {
+                event = event.after(format!("articles to user {}", heir.id));
+            }
+            event.record(&data, &req).await?;
+            Ok(HttpResponse::SeeOther()
+                .header("Location", "/account/admin.html")
+                .finish())
+        }
+        _ => {
+            let mut body = fs::read_to_string("private/forbidden.html").await?;
+            template::search_replace_recursive(
+                &Context::new(&req, &identity),
+                &data,
+                &lang,
+                &mut body,
+                &[],
+            )
+            .await?;
+            Ok(HttpResponse::Forbidden().body(body))
+        }
+    }
+}
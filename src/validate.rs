@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+use std::io::ErrorKind;
+
+use tokio::fs;
+
+use crate::config::ValidationConfig;
+use crate::error::Result;
+use crate::form::FieldError;
+
+// the lowercased passwords of the configured list, empty if there is none
+pub(crate) async fn common_passwords(config: &ValidationConfig) -> Result<HashSet<String>> {
+    let text = match fs::read_to_string(&config.common_passwords).await {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(HashSet::new()),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect())
+}
+
+pub fn username(config: &ValidationConfig, name: &str) -> Option<FieldError> {
+    let len = name.chars().count();
+    if len < config.username_min {
+        Some(FieldError::new("validation_username_short").param("count", config.username_min))
+    } else if len > config.username_max {
+        Some(FieldError::new("validation_username_long").param("count", config.username_max))
+    } else if !name
+        .chars()
+        .all(|ch| ch.is_ascii_alphanumeric() || config.username_chars.contains(ch))
+    {
+        Some(FieldError::new("validation_username_chars"))
+    } else if !name.starts_with(|ch: char| ch.is_ascii_alphanumeric()) {
+        Some(FieldError::new("validation_username_start"))
+    } else if config
+        .reserved_usernames
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(name))
+    {
+        Some(FieldError::new("validation_username_reserved"))
+    } else {
+        None
+    }
+}
+
+// the part of RFC 5321 addresses are actually made of, without quoting and IP literals
+pub fn email(email: &str) -> Option<FieldError> {
+    let invalid = Some(FieldError::new("validation_email"));
+    if email.len() > 254 {
+        return invalid;
+    }
+    let (local, domain) = match email.rfind('@') {
+        Some(at) => (&email[..at], &email[(at + 1)..]),
+        None => return invalid,
+    };
+    let local_valid = !local.is_empty()
+        && local.len() <= 64
+        && local.split('.').all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(ch))
+        });
+    let labels = domain.split('.').collect::<Vec<_>>();
+    let domain_valid = labels.len() >= 2
+        && labels.iter().all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|ch| ch.is_ascii_alphanumeric() || ch == '-')
+        })
+        && labels.last().is_some_and(|tld| {
+            tld.len() >= 2 && tld.chars().all(|ch| ch.is_ascii_alphabetic())
+        });
+    if local_valid && domain_valid {
+        None
+    } else {
+        invalid
+    }
+}
+
+// `username` and `email` are who the password is for, it mustn't be made of them
+pub fn password(
+    config: &ValidationConfig,
+    common: &HashSet<String>,
+    password: &str,
+    username: &str,
+    email: &str,
+) -> Option<FieldError> {
+    let len = password.chars().count();
+    let lower = password.to_lowercase();
+    let personal = [username, email.split('@').next().unwrap_or("")]
+        .iter()
+        .map(|part| part.to_lowercase())
+        .any(|part| part.chars().count() >= 3 && lower.contains(&part));
+    if len < config.password_min {
+        Some(FieldError::new("validation_password_short").param("count", config.password_min))
+    } else if len > config.password_max {
+        Some(FieldError::new("validation_password_long").param("count", config.password_max))
+    } else if common.contains(&lower) {
+        Some(FieldError::new("validation_password_common"))
+    } else if personal {
+        Some(FieldError::new("validation_password_personal"))
+    } else {
+        None
+    }
+}
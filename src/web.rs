@@ -1,131 +1,166 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::iter;
+use std::sync::Arc;
 
-use actix_http::HttpMessage;
 use actix_web::{get, http, web, HttpRequest, HttpResponse, Responder};
-use tokio::{fs, task::JoinHandle};
-use tokio_postgres::{self as psql, NoTls};
+use tokio::fs;
 use actix_identity::Identity;
 use serde::{Serialize, Deserialize};
 use serde_json::json;
 
+use crate::assets;
+use crate::cache::{self, Assets};
+use crate::config::Config;
 use crate::error::Result;
-use crate::i18n::Language;
-use crate::template;
+use crate::i18n::{Lang, Language, Languages};
+use crate::path::PublicPath;
+use crate::repo::{Repository, User};
+use crate::template::{self, Context};
+use crate::token::{self, Scope};
+use crate::validate;
 
 pub struct ServerData<'a> {
-    pub(crate) client: psql::Client,
+    pub(crate) repo: Box<dyn Repository>,
     pub(crate) argon: argon2::Config<'a>,
-    pub(crate) lang: HashMap<String, Language>,
-    _handle: JoinHandle<()>,
+    pub(crate) lang: Languages,
+    pub(crate) assets: Assets,
+    pub(crate) config: Config,
+    // lowercased, see `validate::password`
+    pub(crate) common_passwords: HashSet<String>,
 }
 
 impl ServerData<'static> {
-    pub async fn new<S: Into<String>>(config: S, tls: NoTls) -> Result<Self> {
-        let (client, conn) = psql::connect(&config.into(), tls).await?;
-        let handle = tokio::spawn(async move {
-            if let Err(e) = conn.await {
-                eprintln!("connection error: {}", e);
-            }
-        });
-        let mut langs = HashMap::new();
-        let l10n = client.query("select code, path from l10n", &[]).await?;
-        for row in l10n {
-            let key = row.get::<_, &str>("code").to_string();
-            let path = row.get::<_, &str>("path");
-            let text = fs::read_to_string(path).await?;
-            let lang: Language = ron::de::from_str(&text)?;
-            langs.insert(key, lang);
-        }
+    pub async fn new(
+        repo: Box<dyn Repository>,
+        config: Config,
+        lang: Languages,
+        assets: Assets,
+    ) -> Result<Self> {
+        lang.load(&*repo, &config.i18n).await?;
+        let common_passwords = validate::common_passwords(&config.validation).await?;
         Ok(Self {
-            client,
+            repo,
             argon: argon2::Config::default(),
-            lang: langs,
-            _handle: handle,
+            lang,
+            assets,
+            config,
+            common_passwords,
         })
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct WordData {
-    which: String,
-}
+impl<'a> ServerData<'a> {
+    // the logged in user, `None` for anonymous visitors and deleted users
+    // sessions of users who were disabled or have to reset their password end as well
+    pub async fn user(&self, identity: &Identity) -> Result<Option<User>> {
+        match identity.identity() {
+            Some(username) => Ok(self
+                .repo
+                .user_by_name(&username)
+                .await?
+                .filter(User::active)),
+            None => Ok(None),
+        }
+    }
 
-#[get("/style/{sheet}.css")]
-pub async fn stylesheet<'a>(
-    _req: HttpRequest,
-    _identity: Identity,
-    _data: web::Data<ServerData<'a>>,
-    info: web::Path<String>,
-) -> Result<impl Responder> {
-    let path = format!("public/style/{}.css", info);
-    let sheet = fs::read_to_string(path).await?;
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "text/css")
-        .body(sheet))
+    // the user of an API request, authenticated by a bearer token or the identity cookie
+    // requests with a token fail if it is invalid or doesn't allow `scope`
+    pub async fn api_user(
+        &self,
+        req: &HttpRequest,
+        identity: &Identity,
+        scope: Option<Scope>,
+    ) -> Result<Option<User>> {
+        match token::bearer(req.headers()) {
+            Some(secret) => token::authenticate(self, secret, scope).await.map(Some),
+            None => self.user(identity).await,
+        }
+    }
+
+    // the first available language the visitor prefers, or the default language
+    // without any languages loaded, pages show the l10n keys instead of failing
+    pub fn language(&self, lang: &Lang) -> Arc<Language> {
+        let default = &self.config.i18n.default_language;
+        lang.codes()
+            .iter()
+            .map(String::as_str)
+            .chain(iter::once(default.as_str()))
+            .find_map(|code| self.lang.get(code))
+            .or_else(|| self.lang.any())
+            .unwrap_or_else(|| Arc::new(Language::new(default.clone(), default.clone())))
+    }
 }
 
-#[get("/frontend/{script}.js")]
-pub async fn javascript<'a>(
-    _req: HttpRequest,
-    _identity: Identity,
-    _data: web::Data<ServerData<'a>>,
-    info: web::Path<String>,
-) -> Result<impl Responder> {
-    let path = format!("public/frontend/{}.js", info);
-    let script = fs::read_to_string(path).await?;
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "text/javascript")
-        .body(script))
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WordData {
+    which: String,
 }
 
+// the frontend loads its WebAssembly relative to the current page
 #[get("/{script}.wasm")]
 pub async fn wasm<'a>(
-    _req: HttpRequest,
+    req: HttpRequest,
     _identity: Identity,
-    _data: web::Data<ServerData<'a>>,
+    data: web::Data<ServerData<'a>>,
     info: web::Path<String>,
 ) -> Result<impl Responder> {
-    let path = format!("public/frontend/{}.wasm", info);
-    let script = fs::read(path).await?;
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "application/wasm")
-        .body(script))
+    let path = PublicPath::try_from(format!("frontend/{}.wasm", info))?;
+    assets::serve(&req, &data, &path).await
+}
+
+// resolves `/articles/{article}` to the path of the article to show
+// articles with a custom slug in their front matter are reachable by it,
+// and the translation in the given language is preferred if there is one
+pub(crate) async fn article_path(repo: &dyn Repository, article: &str, code: &str) -> Result<String> {
+    let article = repo
+        .article_by_slug(article)
+        .await?
+        .map(|article| article.path)
+        .unwrap_or_else(|| format!("articles/{}", article));
+    Ok(repo
+        .translations(&article)
+        .await?
+        .into_iter()
+        .find(|translation| translation.lang.as_deref() == Some(code))
+        .map(|translation| translation.path)
+        .unwrap_or(article))
 }
 
 #[get("/articles/{article}")]
 pub async fn articles<'a>(
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
     info: web::Path<String>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
+    let lang = data.language(&lang);
+    let article = article_path(&*data.repo, &info, lang.code()).await?;
+    let modified = data.repo.articles_modified(Some(&article)).await?;
+    let file = format!("public/{}", article);
     let path = "public/articles/template.html";
     let mut body = fs::read_to_string(path).await?;
     template::search_replace_recursive(
-        &identity,
-        &data.client,
-        &data.lang[&lang],
+        &Context::new(&req, &identity),
+        &data,
+        &lang,
         &mut body,
-        &[format!("articles/{}", info)],
+        &[article],
     )
     .await?;
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "text/html")
-        .body(body))
+    cache::page(&req, &data.config.compression, body, &[path, &file], modified).await
 }
 
 #[get("/api/whoami")]
 pub async fn api_whoami<'a>(
-    _req: HttpRequest,
+    req: HttpRequest,
     identity: Identity,
-    _data: web::Data<ServerData<'a>>,
+    data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
+    let user = data.api_user(&req, &identity, None).await?;
     let body = json!({
-        "username": identity.identity().unwrap_or_else(String::new)
+        "username": user.map(|user| user.username).unwrap_or_else(String::new)
     });
     let body = body.to_string();
     Ok(HttpResponse::Ok()
@@ -135,15 +170,13 @@ pub async fn api_whoami<'a>(
 
 #[get("/api/l10n")]
 pub async fn api_l10n<'a>(
-    req: HttpRequest,
+    _req: HttpRequest,
+    lang: Lang,
     _identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
-    let body = serde_json::to_string(&data.lang[&lang])?;
+    let lang = data.language(&lang);
+    let body = serde_json::to_string(&*lang)?;
     Ok(HttpResponse::Ok()
         .header(http::header::CONTENT_TYPE, "application/json")
         .body(body))
@@ -152,16 +185,21 @@ pub async fn api_l10n<'a>(
 #[get("/api/t9n")]
 pub async fn api_t9n<'a>(
     word: web::Query<WordData>,
-    req: HttpRequest,
+    params: web::Query<HashMap<String, String>>,
+    _req: HttpRequest,
+    lang: Lang,
     _identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
+    let lang = data.language(&lang);
+    // every other query parameter fills in a placeholder, e.g. `&count=3`
+    let args = params
+        .iter()
+        .filter(|(name, _)| *name != "which")
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
     let body = json!({
-        "t9n": &data.lang[&lang][&word.which]
+        "t9n": lang.format(&word.which, &args)
     });
     Ok(HttpResponse::Ok()
         .header(http::header::CONTENT_TYPE, "application/json")
@@ -171,38 +209,63 @@ pub async fn api_t9n<'a>(
 #[get("/{res}.html")]
 pub async fn index<'a>(
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
     info: web::Path<String>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
+    let lang = data.language(&lang);
     let path = format!("public/{}.html", info);
-    let mut body = fs::read_to_string(path).await?;
-    template::search_replace_recursive(&identity, &data.client, &data.lang[&lang], &mut body, &[])
+    let mut body = fs::read_to_string(&path).await?;
+    template::search_replace_recursive(&Context::new(&req, &identity), &data, &lang, &mut body, &[])
         .await?;
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "text/html")
-        .body(body))
+    let modified = data.repo.articles_modified(None).await?;
+    cache::page(&req, &data.config.compression, body, &[&path], modified).await
 }
 
 #[get("/")]
 pub async fn root<'a>(
     req: HttpRequest,
+    lang: Lang,
     identity: Identity,
     data: web::Data<ServerData<'a>>,
 ) -> Result<impl Responder> {
-    let lang = req
-        .cookie("lang")
-        .map(|cookie| cookie.value().to_string())
-        .unwrap_or_else(|| "de".to_string());
+    let lang = data.language(&lang);
     let path = "public/index.html";
     let mut body = fs::read_to_string(path).await?;
-    template::search_replace_recursive(&identity, &data.client, &data.lang[&lang], &mut body, &[])
+    template::search_replace_recursive(&Context::new(&req, &identity), &data, &lang, &mut body, &[])
         .await?;
-    Ok(HttpResponse::Ok()
-        .header(http::header::CONTENT_TYPE, "text/html")
-        .body(body))
+    let modified = data.repo.articles_modified(None).await?;
+    cache::page(&req, &data.config.compression, body, &[path], modified).await
+}
+
+// every other file in public/, see `assets::servable` for what is served
+// directories are answered with their index page
+#[get("/{path:.*}")]
+pub async fn asset<'a>(
+    req: HttpRequest,
+    lang: Lang,
+    identity: Identity,
+    data: web::Data<ServerData<'a>>,
+    info: web::Path<String>,
+) -> Result<impl Responder> {
+    let path = PublicPath::try_from(info.as_str())?;
+    if !path.is_dir() {
+        return assets::serve(&req, &data, &path).await;
+    }
+    // relative links in the index page need the trailing slash
+    if !info.is_empty() && !info.ends_with('/') {
+        return Ok(HttpResponse::MovedPermanently()
+            .header(http::header::LOCATION, format!("/{}/", info))
+            .finish());
+    }
+    let lang = data.language(&lang);
+    let index_file = (path / &data.config.assets.index)?;
+    assets::confine(&index_file).await?;
+    let index_file = index_file.to_string();
+    let mut body = fs::read_to_string(&index_file).await?;
+    template::search_replace_recursive(&Context::new(&req, &identity), &data, &lang, &mut body, &[])
+        .await?;
+    let modified = data.repo.articles_modified(None).await?;
+    cache::page(&req, &data.config.compression, body, &[&index_file], modified).await
 }
@@ -0,0 +1,258 @@
+mod common;
+
+use std::fs;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test;
+use serde_json::json;
+
+
+use common::{body, login, post, unique, Harness};
+
+#[actix_rt::test]
+async fn save_writes_and_deletes_drafts() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("frank");
+    let user = harness.employee(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let title = unique("Draft");
+    let path = format!("private/{}/drafts/{}.md", username, title);
+
+    let req = post()
+        .uri("/account/draft.html")
+        .cookie(cookie.clone())
+        .set_json(&json!({ "title": title, "article": "# Work in progress", "delete": false }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let draft = harness.repo.draft_by_path(user.id, &path).await.unwrap().unwrap();
+    assert_eq!(draft.title.as_deref(), Some(title.as_str()));
+    assert_eq!(fs::read_to_string(&path).unwrap(), "# Work in progress");
+
+    // saving again updates the same draft
+    let req = post()
+        .uri("/account/draft.html")
+        .cookie(cookie.clone())
+        .set_json(&json!({ "title": title, "article": "# Done", "delete": false }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(harness.repo.drafts(user.id).await.unwrap(), vec![draft]);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "# Done");
+
+    let req = post()
+        .uri("/account/draft.html")
+        .cookie(cookie)
+        .set_json(&json!({ "title": title, "article": "", "delete": true }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(harness.repo.drafts(user.id).await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn save_is_only_for_employees() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("grace");
+    let user = harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = post()
+        .uri("/account/draft.html")
+        .cookie(cookie)
+        .set_json(&json!({ "title": "Nope", "article": "", "delete": false }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(harness.repo.drafts(user.id).await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn new_publishes_articles() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("heidi");
+    let user = harness.employee(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let title = unique("Published");
+
+    // publishing removes the draft of the article
+    let req = post()
+        .uri("/account/draft.html")
+        .cookie(cookie.clone())
+        .set_json(&json!({ "title": title, "article": "draft", "delete": false }))
+        .to_request();
+    test::call_service(&mut app, req).await;
+    assert_eq!(harness.repo.drafts(user.id).await.unwrap().len(), 1);
+
+    let req = post()
+        .uri("/account/editor.html")
+        .cookie(cookie.clone())
+        .set_form(&[
+            ("title", title.as_str()),
+            ("article", "Hello *world*"),
+            ("lang", "en"),
+            ("translation_of", ""),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let location = format!("/articles/{}.md", title);
+    assert_eq!(resp.headers().get(header::LOCATION).unwrap(), location.as_str());
+
+    let path = format!("articles/{}.md", title);
+    let article = harness.repo.article_by_path(&path).await.unwrap().unwrap();
+    assert_eq!(article.title, title);
+    assert_eq!(article.author, Some(user.id));
+    assert_eq!(article.lang.as_deref(), Some("en"));
+    assert!(harness.repo.drafts(user.id).await.unwrap().is_empty());
+
+    let req = test::TestRequest::get().uri(&location).to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body = body(resp).await;
+    assert!(body.contains(&format!("<h1>{}</h1>", title)));
+    assert!(body.contains("Hello <em>world</em>"));
+    assert!(body.contains(&username));
+
+    // the same title can't be published twice
+    let req = post()
+        .uri("/account/editor.html")
+        .cookie(cookie)
+        .set_form(&[("title", title.as_str()), ("article", "again")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn new_links_translations() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("ivan");
+    let user = harness.employee(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let original = harness
+        .article(&user, &unique("original"), "Hello", Some("en"), None)
+        .await;
+    let title = unique("Übersetzung");
+
+    // the original's title is taken, so the translation starts without one
+    let req = test::TestRequest::get()
+        .uri(&format!("/account/translate/{}.html", original.id))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let page = body(resp).await;
+    assert!(page.contains("name=\"title\" value=\"\""));
+    assert!(page.contains(&format!("name=\"translation_of\" value=\"{}\"", original.id)));
+
+    let translation_of = original.id.to_string();
+    let translate = |title: &str| {
+        post()
+            .uri("/account/editor.html")
+            .cookie(cookie.clone())
+            .set_form(&[
+                ("title", title),
+                ("article", "Hallo"),
+                ("lang", "de"),
+                ("translation_of", translation_of.as_str()),
+            ])
+            .to_request()
+    };
+    let resp = test::call_service(&mut app, translate(&title)).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+
+    // there is a German translation already
+    let other = unique("Zweite Übersetzung");
+    let resp = test::call_service(&mut app, translate(&other)).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(harness.repo.article_by_title(&other).await.unwrap().is_none());
+
+    let translations = harness.repo.translations(&original.path).await.unwrap();
+    let langs = translations
+        .iter()
+        .map(|article| article.lang.as_deref())
+        .collect::<Vec<_>>();
+    assert_eq!(langs, vec![Some("de"), Some("en")]);
+}
+
+#[actix_rt::test]
+async fn new_is_only_for_employees() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("judy");
+    harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let title = unique("Forbidden");
+
+    let req = post()
+        .uri("/account/editor.html")
+        .cookie(cookie)
+        .set_form(&[("title", title.as_str()), ("article", "text")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let path = format!("articles/{}.md", title);
+    assert!(harness.repo.article_by_path(&path).await.unwrap().is_none());
+}
+
+#[actix_rt::test]
+async fn setadmin_grants_and_revokes() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("mallory");
+    harness.admin(&username, "hunter2").await;
+    let other = harness.user(&unique("niaj"), "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let uid = other.id.to_string();
+
+    for (value, status, admin) in &[
+        ("true", StatusCode::OK, true),
+        // making someone an admin twice is a mistake
+        ("true", StatusCode::BAD_REQUEST, true),
+        ("false", StatusCode::OK, false),
+    ] {
+        let req = post()
+            .uri("/api/setadmin")
+            .cookie(cookie.clone())
+            .set_form(&[("value", *value), ("uid", uid.as_str())])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), *status);
+        let json: serde_json::Value = serde_json::from_str(&body(resp).await).unwrap();
+        assert_eq!(json["success"], *status == StatusCode::OK);
+        let user = harness.repo.user(other.id).await.unwrap().unwrap();
+        assert_eq!(user.admin, *admin);
+    }
+}
+
+#[actix_rt::test]
+async fn setadmin_is_only_for_admins() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("oscar");
+    let user = harness.employee(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let uid = user.id.to_string();
+
+    let req = post()
+        .uri("/api/setadmin")
+        .cookie(cookie)
+        .set_form(&[("value", "true"), ("uid", uid.as_str())])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(!harness.repo.user(user.id).await.unwrap().unwrap().admin);
+
+    // nor for visitors who aren't logged in
+    let req = post()
+        .uri("/api/setadmin")
+        .set_form(&[("value", "true"), ("uid", uid.as_str())])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
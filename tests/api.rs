@@ -0,0 +1,258 @@
+mod common;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test;
+use serde_json::{json, Value};
+
+
+use common::{body, delete, login, post, put, unique, Harness};
+
+async fn json_body(resp: actix_web::dev::ServiceResponse) -> Value {
+    serde_json::from_str(&body(resp).await).expect("the body isn't JSON")
+}
+
+#[actix_rt::test]
+async fn list_filters_sorts_and_paginates() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let author = harness.employee(&unique("ada"), "hunter2").await;
+    let mut names = Vec::new();
+    for (name, lang) in &[("banana", "en"), ("apple", "de"), ("cherry", "en")] {
+        let name = unique(name);
+        harness.article(&author, &name, "text", Some(*lang), None).await;
+        names.push(name);
+    }
+
+    let uri = format!("/api/v1/articles?author={}&sort=title&per_page=2", author.id);
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let json = json_body(resp).await;
+    assert_eq!(json["total"], 3);
+    assert_eq!(json["page"], 1);
+    let titles = json["articles"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|article| article["title"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(titles, vec![names[1].clone(), names[0].clone()]);
+
+    let uri = format!("/api/v1/articles?author={}&lang=en&sort=-title", author.id);
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let json = json_body(test::call_service(&mut app, req).await).await;
+    assert_eq!(json["total"], 2);
+    assert_eq!(json["articles"][0]["title"], names[2].as_str());
+
+    let req = test::TestRequest::get()
+        .uri("/api/v1/articles?sort=length")
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let json = json_body(resp).await;
+    assert_eq!(json["error"]["status"], 400);
+}
+
+#[actix_rt::test]
+async fn show_returns_markdown_and_html() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let author = harness.employee(&unique("babbage"), "hunter2").await;
+    let article = harness
+        .article(&author, &unique("show"), "Some *text*", None, None)
+        .await;
+
+    let uri = format!("/api/v1/articles/{}", article.id);
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let json = json_body(resp).await;
+    assert_eq!(json["title"], article.title.as_str());
+    assert_eq!(json["markdown"], "Some *text*");
+    assert_eq!(json["html"], "<p>Some <em>text</em></p>\n");
+
+    let req = test::TestRequest::get()
+        .uri("/api/v1/articles/nope")
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(json_body(resp).await["error"]["status"], 404);
+}
+
+#[actix_rt::test]
+async fn create_publishes_for_employees() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("curie");
+    let author = harness.employee(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let title = unique("Created");
+    let article = json!({ "title": title, "markdown": "# Hello", "lang": "en" });
+
+    let req = post()
+        .uri("/api/v1/articles")
+        .cookie(cookie.clone())
+        .set_json(&article)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let location = resp.headers().get(header::LOCATION).unwrap().clone();
+    let json = json_body(resp).await;
+    assert_eq!(location, format!("/api/v1/articles/{}", json["id"]).as_str());
+    assert_eq!(json["author"], author.id);
+    let created = harness.repo.article_by_title(&title).await.unwrap().unwrap();
+    assert_eq!(created.lang.as_deref(), Some("en"));
+
+    // the title is taken now
+    let req = post()
+        .uri("/api/v1/articles")
+        .cookie(cookie.clone())
+        .set_json(&article)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    // and so is its language
+    let translation = json!({
+        "title": unique("Translated"),
+        "markdown": "# Hello again",
+        "lang": "en",
+        "translation_of": created.id,
+    });
+    let req = post()
+        .uri("/api/v1/articles")
+        .cookie(cookie.clone())
+        .set_json(&translation)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+
+    let req = post()
+        .uri("/api/v1/articles")
+        .cookie(cookie)
+        .set_json(&json!({ "markdown": "no title" }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn create_needs_an_employee() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("dijkstra");
+    harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let article = json!({ "title": unique("Nope"), "markdown": "text" });
+
+    let req = post()
+        .uri("/api/v1/articles")
+        .set_json(&article)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+    let req = post()
+        .uri("/api/v1/articles")
+        .cookie(cookie)
+        .set_json(&article)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(json_body(resp).await["error"]["status"], 403);
+}
+
+#[actix_rt::test]
+async fn update_and_remove_are_for_the_author() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("euler");
+    let author = harness.employee(&username, "hunter2").await;
+    let other = unique("fermat");
+    harness.employee(&other, "hunter2").await;
+    let article = harness
+        .article(&author, &unique("mine"), "old", None, None)
+        .await;
+    let uri = format!("/api/v1/articles/{}", article.id);
+    let title = unique("Renamed");
+
+    let cookie = login(&mut app, &other, "hunter2").await;
+    let req = put()
+        .uri(&uri)
+        .cookie(cookie.clone())
+        .set_json(&json!({ "title": title }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let req = delete()
+        .uri(&uri)
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let req = put()
+        .uri(&uri)
+        .cookie(cookie.clone())
+        .set_json(&json!({ "title": title, "markdown": "new" }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(json_body(resp).await["title"], title.as_str());
+    let updated = harness.repo.article(article.id).await.unwrap().unwrap();
+    assert_eq!(updated.title, title);
+    // the path doesn't change with the title
+    assert_eq!(updated.path, article.path);
+
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let json = json_body(test::call_service(&mut app, req).await).await;
+    assert_eq!(json["markdown"], "new");
+
+    let req = delete()
+        .uri(&uri)
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    assert!(harness.repo.article(article.id).await.unwrap().is_none());
+
+    let req = test::TestRequest::get().uri(&uri).to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_rt::test]
+async fn remove_keeps_translations_linked() {
+    let harness = Harness::new().await;
+    let author = harness.employee(&unique("gauss"), "hunter2").await;
+    let original = harness
+        .article(&author, &unique("original"), "Hello", Some("en"), None)
+        .await;
+    let de = harness
+        .article(&author, &unique("de"), "Hallo", Some("de"), Some(original.id))
+        .await;
+    let pl = harness
+        .article(&author, &unique("pl"), "Cześć", Some("pl"), Some(original.id))
+        .await;
+
+    harness.repo.delete_article(original.id).await.unwrap();
+    let translations = harness.repo.translations(&pl.path).await.unwrap();
+    let ids = translations.iter().map(|article| article.id).collect::<Vec<_>>();
+    assert_eq!(ids, vec![de.id, pl.id]);
+}
+
+#[actix_rt::test]
+async fn openapi_describes_the_articles() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let req = test::TestRequest::get()
+        .uri("/api/v1/openapi.json")
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let json = json_body(resp).await;
+    assert!(json["openapi"].as_str().unwrap().starts_with("3."));
+    assert!(json["paths"]["/api/v1/articles"]["get"].is_object());
+    assert!(json["paths"]["/api/v1/articles/{id}"]["delete"].is_object());
+}
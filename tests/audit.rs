@@ -0,0 +1,249 @@
+mod common;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+
+use circus_backend::repo::AuditFilter;
+
+use common::{body, login, post, unique, Harness};
+
+#[actix_rt::test]
+async fn setadmin_is_recorded() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("ursula");
+    let admin = harness.admin(&username, "hunter2").await;
+    let other = harness.user(&unique("viktor"), "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = post()
+        .uri("/api/setadmin")
+        .peer_addr("192.0.2.7:50000".parse().unwrap())
+        .cookie(cookie)
+        .set_form(&[("value", "true"), ("uid", other.id.to_string().as_str())])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let entries = harness
+        .repo
+        .audit_log(&AuditFilter {
+            actor: Some(admin.id),
+            action: Some("set_admin".to_string()),
+            ..AuditFilter::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(entries.len(), 1);
+    let entry = &entries[0];
+    assert_eq!(entry.target.as_deref(), Some(format!("user {}", other.id).as_str()));
+    assert_eq!(entry.before.as_deref(), Some("false"));
+    assert_eq!(entry.after.as_deref(), Some("true"));
+    assert!(entry.ip.as_deref().unwrap().starts_with("192.0.2.7"));
+}
+
+#[actix_rt::test]
+async fn forwarded_addresses_need_a_trusted_proxy() {
+    let mut harness = Harness::new().await;
+    harness.config.server.trusted_proxies = vec!["10.0.0.1".parse().unwrap()];
+    let mut app = harness.app().await;
+    let attempt = |name: &str, peer: &str, forwarded: &str| {
+        post()
+            .uri("/auth/login.html")
+            .peer_addr(peer.parse().unwrap())
+            .header("X-Forwarded-For", forwarded)
+            .set_form(&[("username", name), ("password", "nothing")])
+            .to_request()
+    };
+    let ip = |name: String| {
+        let repo = &harness.repo;
+        async move {
+            let entries = repo
+                .audit_log(&AuditFilter {
+                    action: Some("login_failed".to_string()),
+                    target: Some(name),
+                    ..AuditFilter::default()
+                })
+                .await
+                .unwrap();
+            entries[0].ip.clone()
+        }
+    };
+
+    // anyone can send the header
+    let name = unique("forger");
+    let req = attempt(&name, "192.0.2.7:50000", "198.51.100.1");
+    test::call_service(&mut app, req).await;
+    assert_eq!(ip(name).await.as_deref(), Some("192.0.2.7"));
+
+    // the client is the last address the proxies didn't add themselves
+    let name = unique("proxied");
+    let req = attempt(&name, "10.0.0.1:50000", "198.51.100.1, 192.0.2.9, 10.0.0.1");
+    test::call_service(&mut app, req).await;
+    assert_eq!(ip(name).await.as_deref(), Some("192.0.2.9"));
+}
+
+#[actix_rt::test]
+async fn logins_are_recorded() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("wanda");
+    let user = harness.user(&username, "hunter2").await;
+
+    let req = post()
+        .uri("/auth/login.html")
+        .set_form(&[("username", username.as_str()), ("password", "wrong")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    login(&mut app, &username, "hunter2").await;
+
+    let failed = harness
+        .repo
+        .audit_log(&AuditFilter {
+            action: Some("login_failed".to_string()),
+            target: Some(username.clone()),
+            ..AuditFilter::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].actor, None);
+
+    let logins = harness
+        .repo
+        .audit_log(&AuditFilter {
+            actor: Some(user.id),
+            action: Some("login".to_string()),
+            ..AuditFilter::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(logins.len(), 1);
+}
+
+#[actix_rt::test]
+async fn the_log_is_only_for_admins() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("xaver");
+    harness.employee(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    for uri in &["/account/audit.html", "/account/audit.csv", "/account/audit.json"] {
+        let req = TestRequest::get()
+            .uri(uri)
+            .cookie(cookie.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    let req = TestRequest::get().uri("/account/audit.html").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn the_log_can_be_filtered() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("yvonne");
+    harness.admin(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    // the name is rendered as data, not as a pattern
+    let attempt = format!("{}{{{{{{me.email}}}}}}", unique("zeno"));
+    let req = post()
+        .uri("/auth/login.html")
+        .set_form(&[("username", attempt.as_str()), ("password", "nothing")])
+        .to_request();
+    test::call_service(&mut app, req).await;
+
+    let req = TestRequest::get()
+        .uri(&format!("/account/audit.html?action=login&actor={}", username))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let page = body(resp).await;
+    assert!(page.contains("<td>login</td>"));
+    assert!(!page.contains("<td>login_failed</td>"));
+
+    let req = TestRequest::get()
+        .uri("/account/audit.html?action=login_failed&target=zeno")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let page = body(resp).await;
+    assert!(page.contains("&#123;&#123;&#123;me.email}}}"));
+
+    let req = TestRequest::get()
+        .uri("/account/audit.html?since=yesterday")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+}
+
+#[actix_rt::test]
+async fn the_log_can_be_exported() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("agnes");
+    harness.admin(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let attempt = format!("=cmd|{}", unique("bruno"));
+    let req = post()
+        .uri("/auth/login.html")
+        .set_form(&[("username", attempt.as_str()), ("password", "nothing")])
+        .to_request();
+    test::call_service(&mut app, req).await;
+
+    let req = TestRequest::get()
+        .uri(&format!("/account/audit.csv?actor={}", username))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let csv = body(resp).await;
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("id,time,actor_id,actor,action,target,before,after,ip")
+    );
+    assert!(lines.all(|line| line.contains(&format!(",{},login,", username))));
+
+    // spreadsheets don't run what was typed into the login form
+    let req = TestRequest::get()
+        .uri("/account/audit.csv?action=login_failed&target=bruno")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert!(body(resp).await.contains(&format!(",'{},", attempt)));
+
+    let req = TestRequest::get()
+        .uri(&format!("/account/audit.json?actor={}", username))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_str(&body(resp).await).unwrap();
+    let entries = json.as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["actor"], username.as_str());
+    assert_eq!(entries[0]["action"], "login");
+
+    let req = TestRequest::get()
+        .uri("/account/audit.xml")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
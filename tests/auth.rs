@@ -0,0 +1,243 @@
+mod common;
+
+use actix_web::cookie::{Cookie, CookieJar, Key};
+use actix_web::http::{header, StatusCode};
+use actix_web::test;
+
+use common::{body, login, post, unique, Harness};
+
+fn create_form<'a>(
+    username: &'a str,
+    password: &'a str,
+    password2: &'a str,
+) -> Vec<(&'a str, &'a str)> {
+    vec![
+        ("firstname", "Alice"),
+        ("lastname", ""),
+        ("username", username),
+        ("email", "alice@example.com"),
+        ("password", password),
+        ("password2", password2),
+    ]
+}
+
+#[actix_rt::test]
+async fn create_registers_a_user() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("alice");
+    let req = post()
+        .uri("/auth/create.html")
+        .set_form(&create_form(&username, "correct horse battery", "correct horse battery"))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    assert_eq!(resp.headers().get(header::LOCATION).unwrap(), "/");
+
+    let user = harness.repo.user_by_name(&username).await.unwrap().unwrap();
+    assert_eq!(user.email, "alice@example.com");
+    assert_eq!(user.firstname.as_deref(), Some("Alice"));
+    assert_eq!(user.lastname, None);
+    assert!(!user.employee && !user.admin);
+    assert!(argon2::verify_encoded(&user.pwhash, b"correct horse battery").unwrap());
+}
+
+#[actix_rt::test]
+async fn create_rejects_taken_usernames() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("bob");
+    harness.user(&username, "hunter2").await;
+    let req = post()
+        .uri("/auth/create.html")
+        .set_form(&create_form(&username, "correct horse battery", "correct horse battery"))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(body(resp).await.contains(&username));
+
+    // the existing user keeps their password
+    let user = harness.repo.user_by_name(&username).await.unwrap().unwrap();
+    assert!(argon2::verify_encoded(&user.pwhash, b"hunter2").unwrap());
+}
+
+#[actix_rt::test]
+async fn create_rejects_mismatched_passwords() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("carol");
+    let req = post()
+        .uri("/auth/create.html")
+        .set_form(&create_form(&username, "correct horse battery", "staple"))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert!(!resp.status().is_success() && !resp.status().is_redirection());
+    assert!(harness.repo.user_by_name(&username).await.unwrap().is_none());
+}
+
+#[actix_rt::test]
+async fn login_remembers_the_user() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("dave");
+    harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = test::TestRequest::get()
+        .uri("/api/whoami")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let whoami: serde_json::Value = serde_json::from_str(&body(resp).await).unwrap();
+    assert_eq!(whoami["username"], username.as_str());
+}
+
+#[actix_rt::test]
+async fn login_cookies_cant_be_forged() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("dora");
+    harness.admin(&username, "hunter2").await;
+
+    // encrypted with a key that isn't derived from the secret
+    let mut jar = CookieJar::new();
+    jar.private(&Key::from_master(&[0; 64]))
+        .add(Cookie::new("auth-cookie", username.clone()));
+    let forged = jar.get("auth-cookie").unwrap().clone();
+    let req = test::TestRequest::get()
+        .uri("/api/whoami")
+        .cookie(forged)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let whoami: serde_json::Value = serde_json::from_str(&body(resp).await).unwrap();
+    assert_eq!(whoami["username"], "");
+}
+
+#[actix_rt::test]
+async fn login_rejects_wrong_passwords() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("erin");
+    harness.user(&username, "hunter2").await;
+    for (username, password) in &[(username.as_str(), "hunter3"), ("nobody", "hunter2")] {
+        let req = post()
+            .uri("/auth/login.html")
+            .set_form(&[("username", *username), ("password", *password)])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_ne!(resp.status(), StatusCode::SEE_OTHER);
+        assert!(resp
+            .response()
+            .cookies()
+            .all(|cookie| cookie.name() != "auth-cookie"));
+    }
+}
+
+#[actix_rt::test]
+async fn login_shows_what_was_wrong() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("frank");
+    harness.user(&username, "hunter2").await;
+    let req = post()
+        .uri("/auth/login.html")
+        // the messages are compared in English
+        .header(header::ACCEPT_LANGUAGE, "en")
+        .set_form(&[("username", username.as_str()), ("password", "hunter3")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let page = body(resp).await;
+    assert!(page.contains(&format!("value=\"{}\"", username)));
+    assert!(page.contains("The user name or password is wrong."));
+    assert!(!page.contains("hunter3"));
+}
+
+#[actix_rt::test]
+async fn change_password_shows_what_was_wrong() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("gina");
+    harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let change = |old: &'static str, new: &'static str, new2: &'static str| {
+        post()
+            .uri("/auth/update-password.html")
+            .header(header::ACCEPT_LANGUAGE, "en")
+            .cookie(cookie.clone())
+            .set_form(&[
+                ("old_password", old),
+                ("new_password", new),
+                ("new_password2", new2),
+            ])
+            .to_request()
+    };
+
+    let req = change("hunter3", "correct horse battery", "correct horse battery");
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(body(resp).await.contains("The password is wrong."));
+
+    let req = change("hunter2", "correct horse battery", "staple");
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let page = body(resp).await;
+    assert!(page.contains("The passwords don't match."));
+    assert!(!page.contains("correct horse battery"));
+
+    let user = harness.repo.user_by_name(&username).await.unwrap().unwrap();
+    assert!(argon2::verify_encoded(&user.pwhash, b"hunter2").unwrap());
+}
+
+#[actix_rt::test]
+async fn flashes_are_shown_once() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("hank");
+    harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let req = post()
+        .uri("/auth/update-password.html")
+        .cookie(cookie.clone())
+        .set_form(&[
+            ("old_password", "hunter2"),
+            ("new_password", "correct horse battery"),
+            ("new_password2", "correct horse battery"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let flash = resp
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "flash")
+        .expect("no flash cookie was set")
+        .into_owned();
+    // signed, not just the key of the message
+    assert_ne!(flash.value(), "flash_password_changed");
+
+    let me = |flash: Cookie<'static>| {
+        test::TestRequest::get()
+            .uri("/account/me.html")
+            .header(header::ACCEPT_LANGUAGE, "en")
+            .cookie(cookie.clone())
+            .cookie(flash)
+            .to_request()
+    };
+    let resp = test::call_service(&mut app, me(flash)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let removed = resp
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "flash")
+        .expect("the flash cookie wasn't removed")
+        .into_owned();
+    assert_eq!(removed.value(), "");
+    assert!(body(resp).await.contains("Your password was changed."));
+
+    // made up messages are ignored
+    let forged = Cookie::new("flash", "flash_password_changed");
+    let resp = test::call_service(&mut app, me(forged)).await;
+    assert!(!body(resp).await.contains("Your password was changed."));
+}
@@ -0,0 +1,317 @@
+// shared by the integration tests, not every test uses everything
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::thread;
+
+use actix_http::Request;
+use actix_service::Service;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Body, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+
+use circus_backend::cache::Assets;
+use circus_backend::config::Config;
+use circus_backend::db::{self, Pool};
+use circus_backend::i18n::{Lang, Language, Languages};
+use circus_backend::repo::{Article, Memory, NewArticle, NewUser, Postgres, Repository, User};
+use circus_backend::template::{self, Context};
+use circus_backend::web::ServerData;
+
+// a connection string like `host=localhost user=circus password=circus dbname=circus_test`
+// runs the tests against that database instead of the in-memory store
+// the tables are created if they don't exist, the data in them is left alone
+const DATABASE: &str = "CIRCUS_TEST_DATABASE";
+
+const LANGUAGES: &[&str] = &["en", "de", "pl"];
+
+// what a browser sends along with requests from a page of the site, see `csrf`
+// `localhost:8080` is the host of test requests
+pub const ORIGIN: &str = "http://localhost:8080";
+pub const CSRF_TOKEN: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+// the nonce of pages rendered by `Harness::render`
+pub const NONCE: &str = "fedcba9876543210fedcba9876543210";
+
+static SITE: Once = Once::new();
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn copy(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
+
+fn postgres(connection: &str, config: &Config) -> Postgres {
+    let connector = db::connector(&config.db).expect("couldn't create the TLS connector");
+    Postgres::new(Pool::new(connection, connector, config.db.clone()))
+}
+
+// the handlers write drafts and articles, so the tests run in a copy of
+// public/ and private/, shared by all tests of a test binary
+fn site() {
+    SITE.call_once(|| {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let root = env::temp_dir().join(format!("circus-backend-test-{}", process::id()));
+        for dir in &["public", "private"] {
+            copy(&manifest.join(dir), &root.join(dir)).expect("couldn't copy the site");
+        }
+        env::set_current_dir(&root).expect("couldn't change into the site");
+        if let Ok(connection) = env::var(DATABASE) {
+            // every test has its own runtime, this one only lives to create the tables
+            thread::spawn(move || {
+                actix_rt::System::new("init-tables").block_on(async move {
+                    postgres(&connection, &Config::default())
+                        .init_tables()
+                        .await
+                        .expect("couldn't create the tables");
+                })
+            })
+            .join()
+            .expect("couldn't create the tables");
+        }
+    });
+}
+
+// a name no other fixture has, in this run or an earlier one against the same database
+pub fn unique(name: &str) -> String {
+    let count = COUNTER.fetch_add(1, Ordering::SeqCst);
+    format!("{}-{}-{}", name, process::id(), count)
+}
+
+#[derive(Clone)]
+enum Store {
+    Memory(Memory),
+    Postgres(String),
+}
+
+impl Store {
+    fn repo(&self, config: &Config) -> Box<dyn Repository> {
+        match self {
+            Store::Memory(memory) => Box::new(memory.clone()),
+            Store::Postgres(connection) => Box::new(postgres(connection, config)),
+        }
+    }
+}
+
+pub struct Harness {
+    store: Store,
+    // for seeding fixtures and looking at what the handlers did
+    pub repo: Box<dyn Repository>,
+    pub config: Config,
+    pub langs: Languages,
+    pub assets: Assets,
+}
+
+impl Harness {
+    pub async fn new() -> Self {
+        site();
+        let config = Config::default();
+        let store = match env::var(DATABASE) {
+            Ok(connection) => Store::Postgres(connection),
+            Err(_) => Store::Memory(Memory::new()),
+        };
+        let repo = store.repo(&config);
+        for code in LANGUAGES {
+            repo.add_language(code, &format!("public/l10n/{}.ron", code))
+                .await
+                .expect("couldn't add a language");
+        }
+        Self {
+            store,
+            repo,
+            assets: Assets::new(config.compression.clone()),
+            langs: Languages::default(),
+            config,
+        }
+    }
+
+    // the state of a worker
+    pub async fn data(&self) -> ServerData<'static> {
+        ServerData::new(
+            self.store.repo(&self.config),
+            self.config.clone(),
+            self.langs.clone(),
+            self.assets.clone(),
+        )
+        .await
+        .expect("couldn't create the server data")
+    }
+
+    // the app exactly as `main` builds it
+    pub async fn app(
+        &self,
+    ) -> impl Service<Request = Request, Response = ServiceResponse<Body>, Error = actix_web::Error>
+    {
+        let store = self.store.clone();
+        let config = self.config.clone();
+        let langs = self.langs.clone();
+        let assets = self.assets.clone();
+        let data = move || {
+            ServerData::new(
+                store.repo(&config),
+                config.clone(),
+                langs.clone(),
+                assets.clone(),
+            )
+        };
+        test::init_service(circus_backend::app(data, &self.config.server)).await
+    }
+
+    pub async fn user(&self, username: &str, password: &str) -> User {
+        let pwhash = argon2::hash_encoded(
+            password.as_bytes(),
+            &circus_backend::auth::salt(),
+            &argon2::Config::default(),
+        )
+        .expect("couldn't hash the password");
+        let id = self
+            .repo
+            .create_user(NewUser {
+                username: username.to_string(),
+                pwhash,
+                email: format!("{}@example.com", username),
+                firstname: None,
+                lastname: None,
+            })
+            .await
+            .expect("couldn't create the user");
+        self.repo
+            .user(id)
+            .await
+            .expect("couldn't read the user")
+            .expect("the user wasn't created")
+    }
+
+    pub async fn employee(&self, username: &str, password: &str) -> User {
+        let user = self.user(username, password).await;
+        self.repo
+            .set_employee(user.id, true)
+            .await
+            .expect("couldn't make the user an employee");
+        User {
+            employee: true,
+            ..user
+        }
+    }
+
+    pub async fn admin(&self, username: &str, password: &str) -> User {
+        let user = self.user(username, password).await;
+        self.repo
+            .set_admin(user.id, true)
+            .await
+            .expect("couldn't make the user an admin");
+        User { admin: true, ..user }
+    }
+
+    // writes public/articles/{name}.md and registers it
+    pub async fn article(
+        &self,
+        author: &User,
+        name: &str,
+        text: &str,
+        lang: Option<&str>,
+        translation_of: Option<i32>,
+    ) -> Article {
+        let path = format!("articles/{}.md", name);
+        fs::write(format!("public/{}", path), text).expect("couldn't write the article");
+        let id = self
+            .repo
+            .create_article(NewArticle {
+                path,
+                title: name.to_string(),
+                author: author.id,
+                date: Some("2020-01-31".to_string()),
+                lang: lang.map(str::to_string),
+                translation_of,
+            })
+            .await
+            .expect("couldn't create the article");
+        self.repo
+            .article(id)
+            .await
+            .expect("couldn't read the article")
+            .expect("the article wasn't created")
+    }
+
+    // renders a template like the handlers do, in the default language
+    pub async fn render(
+        &self,
+        user: Option<&User>,
+        input: &str,
+        args: &[String],
+    ) -> circus_backend::error::Result<String> {
+        let data = self.data().await;
+        let lang = data.language(&Lang::default());
+        let ctx = Context {
+            user: user.map(|user| user.username.clone()),
+            csrf: Some(CSRF_TOKEN.to_string()),
+            nonce: Some(NONCE.to_string()),
+            form: None,
+            flash: None,
+        };
+        let mut output = input.to_string();
+        template::search_replace_recursive(&ctx, &data, &lang, &mut output, args).await?;
+        Ok(output)
+    }
+
+    pub async fn language(&self) -> std::sync::Arc<Language> {
+        self.data().await.language(&Lang::default())
+    }
+}
+
+// a request with the CSRF cookie, its token and the origin of the site
+pub fn same_site(req: TestRequest) -> TestRequest {
+    req.header(header::ORIGIN, ORIGIN)
+        .header("X-CSRF-Token", CSRF_TOKEN)
+        .cookie(Cookie::new("csrf-token", CSRF_TOKEN))
+}
+
+pub fn post() -> TestRequest {
+    same_site(TestRequest::post())
+}
+
+pub fn put() -> TestRequest {
+    same_site(TestRequest::put())
+}
+
+pub fn delete() -> TestRequest {
+    same_site(TestRequest::delete())
+}
+
+// logs in and returns the identity cookie
+pub async fn login<S>(app: &mut S, username: &str, password: &str) -> Cookie<'static>
+where
+    S: Service<Request = Request, Response = ServiceResponse<Body>, Error = actix_web::Error>,
+{
+    let req = post()
+        .uri("/auth/login.html")
+        .set_form(&[("username", username), ("password", password)])
+        .to_request();
+    let resp = test::call_service(app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    resp.response()
+        .cookies()
+        .find(|cookie| cookie.name() == "auth-cookie")
+        .expect("no identity cookie was set")
+        .into_owned()
+}
+
+pub async fn body(resp: ServiceResponse<Body>) -> String {
+    let body = test::read_body(resp).await;
+    String::from_utf8(body.to_vec()).expect("the body isn't UTF-8")
+}
@@ -0,0 +1,152 @@
+mod common;
+
+use actix_web::cookie::Cookie;
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+
+use common::{body, login, post, unique, Harness, CSRF_TOKEN, ORIGIN};
+
+fn login_form<'a>(username: &'a str, password: &'a str) -> Vec<(&'a str, &'a str)> {
+    vec![("username", username), ("password", password)]
+}
+
+#[actix_rt::test]
+async fn pages_hand_out_a_token() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let req = TestRequest::get().uri("/login.html").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let cookie = resp
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "csrf-token")
+        .expect("no CSRF cookie was set")
+        .into_owned();
+    let page = body(resp).await;
+    let field = format!(
+        "<input type=\"hidden\" name=\"csrf_token\" value=\"{}\"/>",
+        cookie.value()
+    );
+    assert!(page.contains(&field));
+    assert!(page.contains(&format!("content=\"{}\"", cookie.value())));
+
+    // the token stays the same once the browser has it
+    let req = TestRequest::get()
+        .uri("/login.html")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert!(resp.response().cookies().next().is_none());
+    assert!(body(resp).await.contains(&field));
+}
+
+#[actix_rt::test]
+async fn posts_need_the_token() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("oskar");
+    harness.user(&username, "hunter2").await;
+
+    let req = TestRequest::post()
+        .uri("/auth/login.html")
+        .header(header::ORIGIN, ORIGIN)
+        .set_form(&login_form(&username, "hunter2"))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let req = TestRequest::post()
+        .uri("/auth/login.html")
+        .header(header::ORIGIN, ORIGIN)
+        .header("X-CSRF-Token", "0".repeat(64))
+        .cookie(Cookie::new("csrf-token", CSRF_TOKEN))
+        .set_form(&login_form(&username, "hunter2"))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // forms send the token as a field instead of a header
+    let mut form = login_form(&username, "hunter2");
+    form.push(("csrf_token", CSRF_TOKEN));
+    let req = TestRequest::post()
+        .uri("/auth/login.html")
+        .header(header::ORIGIN, ORIGIN)
+        .cookie(Cookie::new("csrf-token", CSRF_TOKEN))
+        .set_form(&form)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+}
+
+#[actix_rt::test]
+async fn posts_have_to_come_from_the_site() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("petra");
+    harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let requests = vec![
+        TestRequest::post().header(header::ORIGIN, "http://evil.example"),
+        TestRequest::post().header(header::ORIGIN, "null"),
+        TestRequest::post().header(header::REFERER, "http://evil.example/localhost:8080"),
+        TestRequest::post(),
+    ];
+    for req in requests {
+        let req = req
+            .uri("/account/tokens.html")
+            .header("X-CSRF-Token", CSRF_TOKEN)
+            .cookie(Cookie::new("csrf-token", CSRF_TOKEN))
+            .cookie(cookie.clone())
+            .set_form(&[("name", "stolen")])
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    // without `Origin` the `Referer` is checked
+    let req = TestRequest::post()
+        .uri("/account/tokens.html")
+        .header(header::REFERER, format!("{}/account/me.html", ORIGIN))
+        .header("X-CSRF-Token", CSRF_TOKEN)
+        .cookie(Cookie::new("csrf-token", CSRF_TOKEN))
+        .cookie(cookie)
+        .set_form(&[("name", "mine")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+}
+
+#[actix_rt::test]
+async fn logout_is_a_post() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("quirin");
+    harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = TestRequest::get()
+        .uri("/auth/logout.html")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_ne!(resp.status(), StatusCode::SEE_OTHER);
+    assert!(resp
+        .response()
+        .cookies()
+        .all(|cookie| cookie.name() != "auth-cookie"));
+
+    let req = post()
+        .uri("/auth/logout.html")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let removed = resp
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "auth-cookie")
+        .expect("the identity cookie wasn't removed");
+    assert_eq!(removed.value(), "");
+}
@@ -0,0 +1,178 @@
+mod common;
+
+use std::fs;
+use std::io::{Cursor, Read};
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+
+use circus_backend::gdpr;
+use circus_backend::repo::Profile;
+
+use common::{body, login, post, unique, Harness};
+
+#[actix_rt::test]
+async fn users_export_their_data() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("adele");
+    let user = harness.employee(&username, "hunter2").await;
+    let article = harness.article(&user, &unique("mine"), "# Mine", None, None).await;
+    let draft = format!("private/{}/drafts/unfinished.md", username);
+    fs::create_dir_all(format!("private/{}/drafts", username)).unwrap();
+    fs::write(&draft, "# Unfinished").unwrap();
+    harness.repo.save_draft(user.id, &draft, "Unfinished").await.unwrap();
+    let profile = Profile {
+        firstname: None,
+        lastname: None,
+        bio: Some("Writes things".to_string()),
+        avatar: Some("https://example.org/adele.png".to_string()),
+        website: Some("https://example.org/".to_string()),
+    };
+    harness.repo.update_profile(user.id, profile).await.unwrap();
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = TestRequest::get()
+        .uri("/account/export.json")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .starts_with("attachment"));
+    let text = body(resp).await;
+    assert!(!text.contains("pwhash"));
+    assert!(!text.contains(&user.pwhash));
+    let json: serde_json::Value = serde_json::from_str(&text).unwrap();
+    assert_eq!(json["user"]["username"], username.as_str());
+    assert_eq!(json["user"]["bio"], "Writes things");
+    assert_eq!(json["user"]["avatar"], "https://example.org/adele.png");
+    assert_eq!(json["user"]["website"], "https://example.org/");
+    assert_eq!(json["articles"][0]["path"], article.path.as_str());
+    assert_eq!(json["articles"][0]["content"], "# Mine");
+    assert_eq!(json["drafts"][0]["content"], "# Unfinished");
+    let actions = json["audit_log"].as_array().unwrap();
+    assert!(actions.iter().any(|entry| entry["action"] == "login"));
+
+    let req = TestRequest::get()
+        .uri("/account/export.zip")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let bytes = test::read_body(resp).await;
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes.to_vec())).unwrap();
+    let mut markdown = String::new();
+    archive
+        .by_name(&article.path)
+        .unwrap()
+        .read_to_string(&mut markdown)
+        .unwrap();
+    assert_eq!(markdown, "# Mine");
+    assert!(archive.by_name("drafts/unfinished.md").is_ok());
+    assert!(archive.by_name("data.json").is_ok());
+
+    let req = TestRequest::get()
+        .uri("/account/export.xml")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    let req = TestRequest::get().uri("/account/export.json").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[actix_rt::test]
+async fn logging_in_cancels_a_deletion() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("bertha");
+    let user = harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let request = |password: &'static str| {
+        post()
+            .uri("/account/delete.html")
+            .cookie(cookie.clone())
+            .set_form(&[("password", password)])
+            .to_request()
+    };
+    let resp = test::call_service(&mut app, request("wrong")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let unchanged = harness.repo.user(user.id).await.unwrap().unwrap();
+    assert_eq!(unchanged.delete_after, None);
+
+    let resp = test::call_service(&mut app, request("hunter2")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let pending = harness.repo.user(user.id).await.unwrap().unwrap();
+    let grace = Duration::from_secs(29 * 24 * 60 * 60);
+    assert!(pending.delete_after.unwrap() > SystemTime::now() + grace);
+
+    // the session ends
+    let req = TestRequest::get()
+        .uri("/account/me.html")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    login(&mut app, &username, "hunter2").await;
+    let kept = harness.repo.user(user.id).await.unwrap().unwrap();
+    assert_eq!(kept.delete_after, None);
+}
+
+#[actix_rt::test]
+async fn accounts_are_purged_after_the_grace_period() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("clara");
+    let user = harness.employee(&username, "hunter2").await;
+    let article = harness.article(&user, &unique("left"), "# Left", None, None).await;
+    let other = harness.user(&unique("dora"), "hunter2").await;
+    let yesterday = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+    harness
+        .repo
+        .set_delete_after(user.id, Some(yesterday))
+        .await
+        .unwrap();
+    harness
+        .repo
+        .set_delete_after(other.id, Some(SystemTime::now() + Duration::from_secs(60)))
+        .await
+        .unwrap();
+
+    let purged = gdpr::purge(&harness.data().await).await.unwrap();
+    assert!(purged >= 1);
+    assert!(harness.repo.user(user.id).await.unwrap().is_none());
+    assert!(harness.repo.user(other.id).await.unwrap().is_some());
+    // the article stays, without anyone credited for it
+    let article = harness.repo.article(article.id).await.unwrap().unwrap();
+    assert_eq!(article.author, None);
+    let req = TestRequest::get()
+        .uri(&format!("/api/v1/articles/{}", article.id))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let json: serde_json::Value = serde_json::from_str(&body(resp).await).unwrap();
+    assert_eq!(json["author"], serde_json::Value::Null);
+    let req = TestRequest::get()
+        .uri(&format!("/api/v1/articles?author={}", user.id))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let json: serde_json::Value = serde_json::from_str(&body(resp).await).unwrap();
+    assert_eq!(json["total"], 0);
+
+    let req = post()
+        .uri("/auth/login.html")
+        .set_form(&[("username", username.as_str()), ("password", "hunter2")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,159 @@
+mod common;
+
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Body, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+
+use circus_backend::config::RouteHeaders;
+
+use common::{body, login, unique, Harness, CSRF_TOKEN};
+
+fn get(resp: &ServiceResponse<Body>, name: &str) -> Option<String> {
+    resp.headers()
+        .get(name)
+        .map(|value| value.to_str().unwrap().to_string())
+}
+
+// the nonce the policy allows
+fn nonce(resp: &ServiceResponse<Body>) -> String {
+    let csp = get(resp, "content-security-policy").expect("there is no policy");
+    let start = csp.find("'nonce-").expect("the policy has no nonce") + "'nonce-".len();
+    let end = start + csp[start..].find('\'').unwrap();
+    csp[start..end].to_string()
+}
+
+#[actix_rt::test]
+async fn pages_carry_the_security_headers() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let req = TestRequest::get().uri("/login.html").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+
+    let csp = get(&resp, "content-security-policy").unwrap();
+    assert!(csp.starts_with("default-src 'self'"));
+    assert!(csp.ends_with("frame-ancestors 'none'"));
+    assert!(csp.contains("report-uri /csp-report"));
+    assert_eq!(get(&resp, "x-content-type-options").unwrap(), "nosniff");
+    assert_eq!(
+        get(&resp, "referrer-policy").unwrap(),
+        "strict-origin-when-cross-origin"
+    );
+    assert!(get(&resp, "permissions-policy").unwrap().contains("camera=()"));
+
+    // scripts of the page get the nonce
+    let nonce = nonce(&resp);
+    assert!(body(resp)
+        .await
+        .contains(&format!("<script async nonce=\"{}\"", nonce)));
+}
+
+#[actix_rt::test]
+async fn inline_scripts_get_a_new_nonce_every_time() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("rosa");
+    harness.employee(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let mut nonces = Vec::new();
+    for _ in 0..2 {
+        let req = TestRequest::get()
+            .uri("/account/editor.html")
+            .cookie(cookie.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let nonce = nonce(&resp);
+        assert!(body(resp)
+            .await
+            .contains(&format!("<script nonce=\"{}\">", nonce)));
+        nonces.push(nonce);
+    }
+    assert_ne!(nonces[0], nonces[1]);
+}
+
+#[actix_rt::test]
+async fn pages_still_revalidate() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    // the CSRF token is part of the page, the nonce isn't
+    let csrf = Cookie::new("csrf-token", CSRF_TOKEN);
+    let req = TestRequest::get()
+        .uri("/")
+        .cookie(csrf.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let etag = get(&resp, "etag").unwrap();
+
+    let req = TestRequest::get()
+        .uri("/")
+        .cookie(csrf)
+        .header(header::IF_NONE_MATCH, etag)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+}
+
+#[actix_rt::test]
+async fn routes_override_the_headers() {
+    let mut harness = Harness::new().await;
+    harness.config.server.headers.routes.insert(
+        0,
+        RouteHeaders {
+            prefix: "/login.html".to_string(),
+            frame_ancestors: Some("'self'".to_string()),
+            referrer_policy: Some(String::new()),
+            ..RouteHeaders::default()
+        },
+    );
+    let mut app = harness.app().await;
+
+    let req = TestRequest::get().uri("/login.html").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let csp = get(&resp, "content-security-policy").unwrap();
+    assert!(csp.starts_with("default-src 'self'"));
+    assert!(csp.ends_with("frame-ancestors 'self'"));
+    assert_eq!(get(&resp, "referrer-policy"), None);
+
+    // the default route for the API
+    let req = TestRequest::get().uri("/api/v1/openapi.json").to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(
+        get(&resp, "content-security-policy").unwrap(),
+        "default-src 'none'; frame-ancestors 'none'"
+    );
+}
+
+#[actix_rt::test]
+async fn rejected_requests_carry_the_headers() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let req = TestRequest::post()
+        .uri("/auth/login.html")
+        .set_form(&[("username", "nobody"), ("password", "nothing")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert_eq!(get(&resp, "x-content-type-options").unwrap(), "nosniff");
+}
+
+#[actix_rt::test]
+async fn violations_are_reported() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let report = r#"{"csp-report": {
+        "document-uri": "http://localhost:8080/",
+        "violated-directive": "script-src",
+        "blocked-uri": "inline"
+    }}"#;
+    // browsers send reports without a CSRF token
+    let req = TestRequest::post()
+        .uri("/csp-report")
+        .header(header::CONTENT_TYPE, "application/csp-report")
+        .set_payload(report)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use std::path::Path;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+use circus_backend::cache::Assets;
+use circus_backend::config::Config;
+use circus_backend::i18n::{self, Lang, Languages};
+use circus_backend::repo::Memory;
+use circus_backend::web::ServerData;
+
+use common::Harness;
+
+#[actix_rt::test]
+async fn language_links_only_redirect_within_the_site() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let cases = &[
+        ("/articles/foo.md", "/articles/foo.md"),
+        ("//evil.example", "/"),
+        ("/%5Cevil.example", "/"),
+        ("/%09/evil.example", "/"),
+        ("https://evil.example", "/"),
+    ];
+    for (redirect, location) in cases {
+        let req = TestRequest::get()
+            .uri(&format!("/lang/de.html?redirect={}", redirect))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        assert_eq!(resp.headers().get(header::LOCATION).unwrap(), location);
+    }
+}
+
+#[actix_rt::test]
+async fn pages_render_without_languages() {
+    let config = Config::default();
+    let assets = Assets::new(config.compression.clone());
+    let default = config.i18n.default_language.clone();
+    let data = ServerData::new(Box::new(Memory::new()), config, Languages::default(), assets)
+        .await
+        .unwrap();
+    let lang = data.language(&Lang::default());
+    assert_eq!(lang.code(), default);
+    assert_eq!(lang.format("login", &[]), "login");
+}
+
+#[test]
+fn l10n_check_passes_on_the_site() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let roots = ["public", "private", "src"]
+        .iter()
+        .map(|root| manifest.join(root))
+        .collect::<Vec<_>>();
+    let missing = i18n::missing_translations(&roots, manifest.join("public/l10n")).unwrap();
+    assert!(missing.is_empty(), "{:?}", missing);
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+
+use circus_backend::repo::Profile;
+
+use common::{body, login, post, unique, Harness};
+
+#[actix_rt::test]
+async fn users_edit_their_profile() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("emmy");
+    let user = harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = post()
+        .uri("/account/profile.html")
+        .cookie(cookie.clone())
+        .set_form(&[
+            ("firstname", "Emmy"),
+            ("lastname", "Noether"),
+            ("bio", "Rings & \"ideals\""),
+            ("avatar", "https://example.org/emmy.png"),
+            ("website", "https://example.org/"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let changed = harness.repo.user(user.id).await.unwrap().unwrap();
+    assert_eq!(changed.firstname.as_deref(), Some("Emmy"));
+    assert_eq!(changed.bio.as_deref(), Some("Rings & \"ideals\""));
+    assert_eq!(changed.website.as_deref(), Some("https://example.org/"));
+
+    let req = TestRequest::get()
+        .uri("/account/me.html")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(body(resp).await.contains(">Rings &amp; &quot;ideals&quot;</textarea>"));
+
+    // only web links, the form shows what was entered again
+    let req = post()
+        .uri("/account/profile.html")
+        .cookie(cookie)
+        .header(header::ACCEPT_LANGUAGE, "en")
+        .set_form(&[
+            ("firstname", ""),
+            ("lastname", ""),
+            ("bio", "Fields"),
+            ("avatar", ""),
+            ("website", "javascript:alert(1)"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let page = body(resp).await;
+    assert!(page.contains("This is not a link starting with http:// or https://."));
+    assert!(page.contains(">Fields</textarea>"));
+    assert!(page.contains("value=\"javascript:alert(1)\""));
+    let unchanged = harness.repo.user(user.id).await.unwrap().unwrap();
+    assert_eq!(unchanged.website.as_deref(), Some("https://example.org/"));
+}
+
+#[actix_rt::test]
+async fn authors_have_a_public_page() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("felix");
+    let author = harness.employee(&username, "hunter2").await;
+    harness
+        .repo
+        .update_profile(
+            author.id,
+            Profile {
+                firstname: Some("Felix".to_string()),
+                bio: Some("<script>alert(1)</script>".to_string()),
+                ..Profile::default()
+            },
+        )
+        .await
+        .unwrap();
+    let name = unique("written");
+    let article = harness.article(&author, &name, "# Written", None, None).await;
+    let reader = harness.user(&unique("gerda"), "hunter2").await;
+
+    let req = TestRequest::get()
+        .uri(&format!("/authors/{}.html", username))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let page = body(resp).await;
+    assert!(page.contains(&format!("Felix &quot;{}&quot;", username)));
+    assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(page.contains(&format!("href=\"/{}\"", article.path)));
+
+    // the byline links to the page
+    let output = harness
+        .render(None, "{{{article%1}}}", std::slice::from_ref(&article.path))
+        .await
+        .unwrap();
+    assert!(output.contains(&format!(
+        "<a href=\"/authors/{}.html\" rel=\"author\">",
+        username
+    )));
+
+    // users who don't write have no page
+    for name in &[reader.username.clone(), unique("nobody")] {
+        let req = TestRequest::get()
+            .uri(&format!("/authors/{}.html", name))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,265 @@
+mod common;
+
+use circus_backend::error::Error;
+
+use common::{unique, Harness};
+
+#[actix_rt::test]
+async fn me_shows_the_logged_in_user() {
+    let harness = Harness::new().await;
+    let user = harness.user(&unique("peggy"), "hunter2").await;
+    let input = "{{{me.id}}} {{{me.username}}} {{{me.email}}}";
+    let output = harness.render(Some(&user), input, &[]).await.unwrap();
+    assert_eq!(
+        output,
+        format!("{} {} {}", user.id, user.username, user.email)
+    );
+
+    // visitors who aren't logged in see nothing
+    let output = harness.render(None, input, &[]).await.unwrap();
+    assert_eq!(output, "  ");
+}
+
+#[actix_rt::test]
+async fn me_hides_the_password() {
+    let harness = Harness::new().await;
+    let user = harness.user(&unique("rupert"), "hunter2").await;
+    let output = harness
+        .render(Some(&user), "{{{me.pwhash}}}", &[])
+        .await
+        .unwrap();
+    assert_eq!(output, "No passwords for you!");
+    assert!(!output.contains(&user.pwhash));
+
+    // and any other column that isn't meant to be shown
+    let err = harness
+        .render(Some(&user), "{{{me.admin}}}", &[])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::InvalidPattern(_)));
+}
+
+#[actix_rt::test]
+async fn maybe_hides_what_users_may_not_see() {
+    let harness = Harness::new().await;
+    let user = harness.user(&unique("sybil"), "hunter2").await;
+    let employee = harness.employee(&unique("trent"), "hunter2").await;
+    let input = "[{{{maybe(editor)}}}]";
+
+    let output = harness.render(None, input, &[]).await.unwrap();
+    assert_eq!(output, "[]");
+    let output = harness.render(Some(&user), input, &[]).await.unwrap();
+    assert_eq!(output, "[]");
+    let output = harness.render(Some(&employee), input, &[]).await.unwrap();
+    assert!(output.contains("href=\"/account/editor.html\""));
+
+    // without `maybe` the page isn't rendered at all
+    let err = harness
+        .render(Some(&user), "{{{editor}}}", &[])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::AuthorizationFailed));
+}
+
+#[actix_rt::test]
+async fn admin_panel_lists_users_for_admins() {
+    let harness = Harness::new().await;
+    let admin = harness.admin(&unique("uma"), "hunter2").await;
+    let employee = harness.employee(&unique("victor"), "hunter2").await;
+
+    let output = harness
+        .render(Some(&admin), "{{{admin-panel}}}", &[])
+        .await
+        .unwrap();
+    assert!(output.contains(&format!("<td>{}</td>", admin.username)));
+    assert!(output.contains(&format!("<td>{}</td>", employee.username)));
+    assert!(output.contains(&format!("data-uid=\"{}\"", employee.id)));
+
+    let err = harness
+        .render(Some(&employee), "{{{admin-panel}}}", &[])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::AuthorizationFailed));
+}
+
+#[actix_rt::test]
+async fn l10n_uses_the_language() {
+    let harness = Harness::new().await;
+    let lang = harness.language().await;
+    let output = harness
+        .render(None, "{{{l10n(login)}}}", &[])
+        .await
+        .unwrap();
+    assert_eq!(output, lang.format("login", &[]));
+    assert!(!output.is_empty());
+}
+
+#[actix_rt::test]
+async fn login_escapes_the_username() {
+    let harness = Harness::new().await;
+    let user = harness.user(&unique("<b>{{{csrf}}}"), "hunter2").await;
+
+    let output = harness.render(Some(&user), "{{{login}}}", &[]).await.unwrap();
+    assert!(output.contains("&lt;b&gt;&#123;&#123;&#123;csrf}}}"));
+    assert!(!output.contains("<b>"));
+}
+
+#[actix_rt::test]
+async fn articles_render_by_title() {
+    let harness = Harness::new().await;
+    let author = harness.employee(&unique("walter"), "hunter2").await;
+    let name = unique("article");
+    let article = harness
+        .article(&author, &name, "Some *text* to read", None, None)
+        .await;
+
+    let output = harness
+        .render(None, &format!("{{{{{{article {}}}}}}}", name), &[])
+        .await
+        .unwrap();
+    assert!(output.starts_with(&format!("<article><h1>{}</h1>{}", name, article.date)));
+    assert!(output.contains(&author.username));
+    assert!(output.contains("Some <em>text</em> to read"));
+
+    let output = harness
+        .render(None, &format!("{{{{{{preview {}}}}}}}", name), &[])
+        .await
+        .unwrap();
+    assert!(output.contains(&format!("<a href=\"/{}\">{}</a>", article.path, name)));
+    assert!(output.contains("Some text to read"));
+    assert!(!output.contains("<em>"));
+
+    let err = harness
+        .render(None, &format!("{{{{{{article {}}}}}}}", unique("missing")), &[])
+        .await
+        .unwrap_err();
+    assert!(matches!(err, Error::ResourceNotFound(_)));
+}
+
+#[actix_rt::test]
+async fn previews_escape_the_front_matter() {
+    let harness = Harness::new().await;
+    let author = harness.employee(&unique("vera"), "hunter2").await;
+    let name = unique("escaped");
+    let text = "---\nsummary: <script>alert(1)</script> {{{login}}}\ncover: x\" onerror=\"alert(1)\n---\nText";
+    harness.article(&author, &name, text, None, None).await;
+
+    let output = harness
+        .render(None, &format!("{{{{{{preview {}}}}}}}", name), &[])
+        .await
+        .unwrap();
+    assert!(output.contains("&lt;script&gt;alert(1)&lt;/script&gt; &#123;&#123;&#123;login}}}"));
+    assert!(output.contains("src=\"x&quot; onerror=&quot;alert(1)\""));
+    assert!(!output.contains("<script>"));
+
+    let name = unique("braces");
+    harness.article(&author, &name, "Some {{{login}}} text", None, None).await;
+    let output = harness
+        .render(None, &format!("{{{{{{preview {}}}}}}}", name), &[])
+        .await
+        .unwrap();
+    assert!(output.contains("Some &#123;&#123;&#123;login}}} text"));
+}
+
+#[actix_rt::test]
+async fn broken_front_matter_still_renders() {
+    let harness = Harness::new().await;
+    let author = harness.employee(&unique("wanda"), "hunter2").await;
+    let slug = unique("taken");
+    let text = format!("---\nslug: {}\n---\nFirst", slug);
+    let first = unique("first");
+    harness.article(&author, &first, &text, None, None).await;
+    harness
+        .render(None, &format!("{{{{{{article {}}}}}}}", first), &[])
+        .await
+        .unwrap();
+
+    // the slug is taken, and there is no 13th month
+    for (text, body) in &[
+        (format!("---\nslug: {}\n---\nSecond", slug), "Second"),
+        ("---\ndate: 2020-13-45\n---\nThird".to_string(), "Third"),
+    ] {
+        let name = unique("broken");
+        harness.article(&author, &name, text, None, None).await;
+        let output = harness
+            .render(None, &format!("{{{{{{article {}}}}}}}", name), &[])
+            .await
+            .unwrap();
+        assert!(output.contains(body));
+    }
+}
+
+#[actix_rt::test]
+async fn articles_render_by_argument() {
+    let harness = Harness::new().await;
+    let author = harness.employee(&unique("xavier"), "hunter2").await;
+    let name = unique("argument");
+    let article = harness.article(&author, &name, "Text", None, None).await;
+
+    let output = harness
+        .render(None, "{{{article%1}}}", std::slice::from_ref(&article.path))
+        .await
+        .unwrap();
+    assert!(output.starts_with(&format!("<article><h1>{}</h1>", name)));
+}
+
+#[actix_rt::test]
+async fn drafts_lists_the_users_drafts() {
+    let harness = Harness::new().await;
+    let author = harness.employee(&unique("yvonne"), "hunter2").await;
+    let other = harness.employee(&unique("zoe"), "hunter2").await;
+    let title = unique("Notes");
+    let path = format!("private/{}/drafts/{}.md", author.username, title);
+    harness
+        .repo
+        .save_draft(author.id, &path, &title)
+        .await
+        .unwrap();
+    let draft = harness
+        .repo
+        .draft_by_path(author.id, &path)
+        .await
+        .unwrap()
+        .unwrap();
+
+    let output = harness
+        .render(Some(&author), "{{{drafts}}}", &[])
+        .await
+        .unwrap();
+    assert!(output.contains(&format!("<option value=\"{}\">{}</option>", draft.id, title)));
+
+    // other users don't see them
+    let output = harness
+        .render(Some(&other), "{{{drafts}}}", &[])
+        .await
+        .unwrap();
+    assert_eq!(output, "");
+}
+
+#[actix_rt::test]
+async fn translations_link_each_language() {
+    let harness = Harness::new().await;
+    let author = harness.employee(&unique("alan"), "hunter2").await;
+    let original = harness
+        .article(&author, &unique("hello"), "Hello", Some("en"), None)
+        .await;
+    let args = [original.path.clone()];
+
+    // a single language needs no navigation
+    let output = harness
+        .render(None, "{{{translations%1}}}", &args)
+        .await
+        .unwrap();
+    assert_eq!(output, "");
+
+    let translation = harness
+        .article(&author, &unique("hallo"), "Hallo", Some("de"), Some(original.id))
+        .await;
+    let output = harness
+        .render(None, "{{{translations%1}}}", &args)
+        .await
+        .unwrap();
+    assert!(output.starts_with("<nav class=\"translations\">"));
+    assert!(output.contains(&format!("/lang/de.html?redirect=/{}", translation.path)));
+    assert!(output.contains(&format!("/lang/en.html?redirect=/{}", original.path)));
+}
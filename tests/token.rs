@@ -0,0 +1,206 @@
+mod common;
+
+use std::time::{Duration, SystemTime};
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test;
+use serde_json::json;
+
+use circus_backend::repo::{NewApiToken, User};
+use circus_backend::token;
+
+use common::{body, login, post, unique, Harness};
+
+// a token for `user` without going through the form
+async fn seed(
+    harness: &Harness,
+    user: &User,
+    scopes: &[&str],
+    expires: Option<SystemTime>,
+) -> String {
+    let secret = token::generate();
+    harness
+        .repo
+        .create_token(NewApiToken {
+            user: user.id,
+            name: "seed".to_string(),
+            hash: token::hash(&secret),
+            scopes: scopes.iter().map(|scope| scope.to_string()).collect(),
+            expires,
+        })
+        .await
+        .unwrap();
+    secret
+}
+
+fn bearer(secret: &str) -> String {
+    format!("Bearer {}", secret)
+}
+
+#[actix_rt::test]
+async fn create_shows_the_secret_once() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("heinz");
+    let user = harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = post()
+        .uri("/account/tokens.html")
+        .cookie(cookie)
+        .set_form(&[
+            ("name", "ci"),
+            ("expires", "30"),
+            ("scope", "articles"),
+            ("scope", "drafts"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get(header::CACHE_CONTROL).unwrap(), "no-store");
+    let body = body(resp).await;
+    let start = body.find("<code>").expect("the page doesn't show the token") + "<code>".len();
+    let end = start + body[start..].find("</code>").unwrap();
+    let secret = &body[start..end];
+    assert!(secret.starts_with("circus_"));
+
+    let tokens = harness.repo.tokens(user.id).await.unwrap();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].name, "ci");
+    assert_eq!(tokens[0].scopes, vec!["articles", "drafts"]);
+    assert!(tokens[0].expires.unwrap() > SystemTime::now());
+    // only the hash is stored
+    let stored = harness.repo.token_by_hash(&token::hash(secret)).await.unwrap();
+    assert_eq!(stored.as_ref(), Some(&tokens[0]));
+}
+
+#[actix_rt::test]
+async fn create_rejects_unknown_scopes() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("ingrid");
+    let user = harness.user(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = post()
+        .uri("/account/tokens.html")
+        .cookie(cookie)
+        .set_form(&[("name", "ci"), ("scope", "everything")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(harness.repo.tokens(user.id).await.unwrap().is_empty());
+}
+
+#[actix_rt::test]
+async fn bearer_tokens_publish_articles() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let user = harness.employee(&unique("jakob"), "hunter2").await;
+    let secret = seed(&harness, &user, &["articles"], None).await;
+    let title = unique("Scripted");
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/articles")
+        .header(header::AUTHORIZATION, bearer(&secret))
+        .set_json(&json!({ "title": title, "markdown": "from CI" }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let article = harness.repo.article_by_title(&title).await.unwrap().unwrap();
+    assert_eq!(article.author, Some(user.id));
+
+    let tokens = harness.repo.tokens(user.id).await.unwrap();
+    assert!(tokens[0].last_used.is_some());
+}
+
+#[actix_rt::test]
+async fn bearer_tokens_need_the_scope() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let user = harness.employee(&unique("klara"), "hunter2").await;
+    let secret = seed(&harness, &user, &["drafts"], None).await;
+
+    let req = test::TestRequest::post()
+        .uri("/api/v1/articles")
+        .header(header::AUTHORIZATION, bearer(&secret))
+        .set_json(&json!({ "title": unique("Unscoped"), "markdown": "text" }))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // any token tells who it belongs to
+    let req = test::TestRequest::get()
+        .uri("/api/whoami")
+        .header(header::AUTHORIZATION, bearer(&secret))
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    let whoami: serde_json::Value = serde_json::from_str(&body(resp).await).unwrap();
+    assert_eq!(whoami["username"], user.username.as_str());
+}
+
+#[actix_rt::test]
+async fn expired_and_revoked_tokens_are_rejected() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("lukas");
+    let user = harness.employee(&username, "hunter2").await;
+    let yesterday = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+    let expired = seed(&harness, &user, &["articles"], Some(yesterday)).await;
+    let revoked = seed(&harness, &user, &["articles"], None).await;
+
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let id = harness
+        .repo
+        .token_by_hash(&token::hash(&revoked))
+        .await
+        .unwrap()
+        .unwrap()
+        .id
+        .to_string();
+    let req = post()
+        .uri("/account/tokens/revoke.html")
+        .cookie(cookie)
+        .set_form(&[("id", id.as_str())])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+
+    for secret in &[expired, revoked, "circus_made-up".to_string()] {
+        let req = test::TestRequest::post()
+            .uri("/api/v1/articles")
+            .header(header::AUTHORIZATION, bearer(secret))
+            .set_json(&json!({ "title": unique("Rejected"), "markdown": "text" }))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[actix_rt::test]
+async fn users_only_revoke_their_own_tokens() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let owner = harness.user(&unique("marta"), "hunter2").await;
+    let username = unique("nils");
+    harness.user(&username, "hunter2").await;
+    let secret = seed(&harness, &owner, &[], None).await;
+    let id = harness
+        .repo
+        .token_by_hash(&token::hash(&secret))
+        .await
+        .unwrap()
+        .unwrap()
+        .id
+        .to_string();
+
+    let cookie = login(&mut app, &username, "hunter2").await;
+    let req = post()
+        .uri("/account/tokens/revoke.html")
+        .cookie(cookie)
+        .set_form(&[("id", id.as_str())])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    assert_eq!(harness.repo.tokens(owner.id).await.unwrap().len(), 1);
+}
@@ -0,0 +1,82 @@
+mod common;
+
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+
+use common::{body, login, post, unique, Harness};
+
+// saving replaces every translation, this test binary has a copy of the site of its own
+#[actix_rt::test]
+async fn plain_messages_keep_their_own_row() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("petra");
+    harness.admin(&username, "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let save = |fields: &[(&str, &str)]| {
+        post()
+            .uri("/account/translations.html")
+            .cookie(cookie.clone())
+            .set_form(&fields)
+            .to_request()
+    };
+    let page = || {
+        TestRequest::get()
+            .uri("/account/translations.html")
+            .cookie(cookie.clone())
+            .to_request()
+    };
+
+    let resp = test::call_service(
+        &mut app,
+        save(&[
+            ("en:greeting", "Hello"),
+            ("de:greeting:one", "Ein Gruß"),
+            ("de:greeting:other", "{count} Grüße"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let resp = test::call_service(&mut app, page()).await;
+    let text = body(resp).await;
+    assert!(text.contains("<textarea name=\"en:greeting\">Hello</textarea>"));
+    assert!(text.contains("<textarea name=\"en:greeting:other\"></textarea>"));
+    assert!(!text.contains("name=\"de:greeting\""));
+
+    // the plural rows of the plain message are left empty
+    let resp = test::call_service(
+        &mut app,
+        save(&[
+            ("en:greeting", "Hello"),
+            ("en:greeting:one", ""),
+            ("en:greeting:other", ""),
+            ("de:greeting:one", "Ein Gruß"),
+            ("de:greeting:other", "{count} Grüße"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let resp = test::call_service(&mut app, page()).await;
+    assert!(body(resp)
+        .await
+        .contains("<textarea name=\"en:greeting\">Hello</textarea>"));
+
+    // a plural form turns the plain message into the `other` form
+    let resp = test::call_service(
+        &mut app,
+        save(&[
+            ("en:greeting", "Hello"),
+            ("en:greeting:one", "One hello"),
+            ("de:greeting:one", "Ein Gruß"),
+            ("de:greeting:other", "{count} Grüße"),
+        ]),
+    )
+    .await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let resp = test::call_service(&mut app, page()).await;
+    let text = body(resp).await;
+    assert!(text.contains("<textarea name=\"en:greeting:one\">One hello</textarea>"));
+    assert!(text.contains("<textarea name=\"en:greeting:other\">Hello</textarea>"));
+    assert!(!text.contains("name=\"en:greeting\""));
+}
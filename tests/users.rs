@@ -0,0 +1,330 @@
+mod common;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+
+use circus_backend::repo::{UserFilter, UserStatus};
+
+use common::{body, login, post, unique, Harness};
+
+#[actix_rt::test]
+async fn the_panel_searches_users() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("ada");
+    harness.admin(&username, "hunter2").await;
+    let found = harness.user(&unique("grace-hopper"), "hunter2").await;
+    let other = harness.user(&unique("linus"), "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    // ignoring case
+    let query = found.username.to_uppercase();
+    let req = TestRequest::get()
+        .uri(&format!("/account/admin.html?q={}", query))
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let page = body(resp).await;
+    assert!(page.contains(&format!("<td>{}</td>", found.username)));
+    assert!(page.contains(&format!("href=\"/account/users/{}.html\"", found.id)));
+    assert!(!page.contains(&format!("<td>{}</td>", other.username)));
+    assert!(page.contains(&format!("value=\"{}\"", query)));
+}
+
+#[actix_rt::test]
+async fn searches_are_paginated() {
+    let harness = Harness::new().await;
+    let prefix = unique("page");
+    let mut ids = Vec::new();
+    for i in 0..3 {
+        ids.push(harness.user(&format!("{}-{}", prefix, i), "hunter2").await.id);
+    }
+
+    let users = harness
+        .repo
+        .search_users(&UserFilter {
+            query: Some(prefix.to_uppercase()),
+            limit: Some(2),
+            offset: 1,
+        })
+        .await
+        .unwrap();
+    let found = users.iter().map(|user| user.id).collect::<Vec<_>>();
+    assert_eq!(found, &ids[1..]);
+}
+
+#[actix_rt::test]
+async fn admins_edit_users() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("barbara");
+    harness.admin(&username, "hunter2").await;
+    let user = harness.user(&unique("charles"), "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = post()
+        .uri(&format!("/account/users/{}.html", user.id))
+        .cookie(cookie.clone())
+        .set_form(&[
+            ("firstname", "Charles"),
+            ("lastname", ""),
+            ("email", "charles@example.org"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    let changed = harness.repo.user(user.id).await.unwrap().unwrap();
+    assert_eq!(changed.firstname.as_deref(), Some("Charles"));
+    assert_eq!(changed.lastname, None);
+    assert_eq!(changed.email, "charles@example.org");
+
+    let req = TestRequest::get()
+        .uri(&format!("/account/users/{}.html", user.id))
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(body(resp).await.contains("value=\"charles@example.org\""));
+}
+
+#[actix_rt::test]
+async fn only_admins_manage_users() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("dorothy");
+    harness.employee(&username, "hunter2").await;
+    let user = harness.user(&unique("edsger"), "hunter2").await;
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = TestRequest::get()
+        .uri(&format!("/account/users/{}.html", user.id))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let req = post()
+        .uri(&format!("/account/users/{}/status.html", user.id))
+        .cookie(cookie)
+        .set_form(&[("status", "banned")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let unchanged = harness.repo.user(user.id).await.unwrap().unwrap();
+    assert_eq!(unchanged.status, UserStatus::Active);
+}
+
+#[actix_rt::test]
+async fn disabled_users_are_locked_out() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("frances");
+    let admin = harness.admin(&username, "hunter2").await;
+    let name = unique("guido");
+    let user = harness.user(&name, "hunter2").await;
+    let admin_cookie = login(&mut app, &username, "hunter2").await;
+    let cookie = login(&mut app, &name, "hunter2").await;
+
+    let req = post()
+        .uri(&format!("/account/users/{}/status.html", user.id))
+        .cookie(admin_cookie.clone())
+        .set_form(&[("status", "disabled")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+
+    // the session ends
+    let req = TestRequest::get()
+        .uri("/account/me.html")
+        .cookie(cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let req = post()
+        .uri("/auth/login.html")
+        .set_form(&[("username", name.as_str()), ("password", "hunter2")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    // admins can't lock themselves out
+    let req = post()
+        .uri(&format!("/account/users/{}/status.html", admin.id))
+        .cookie(admin_cookie)
+        .set_form(&[("status", "disabled")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_rt::test]
+async fn banned_addresses_cant_register_again() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let name = unique("hedy");
+    let user = harness.user(&name, "hunter2").await;
+    harness
+        .repo
+        .set_status(user.id, UserStatus::Banned)
+        .await
+        .unwrap();
+
+    let other = unique("hedy");
+    let email = user.email.to_uppercase();
+    let req = post()
+        .uri("/auth/create.html")
+        .set_form(&[
+            ("firstname", ""),
+            ("lastname", ""),
+            ("username", other.as_str()),
+            ("email", email.as_str()),
+            ("password", "correct horse battery"),
+            ("password2", "correct horse battery"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(harness.repo.user_by_name(&other).await.unwrap().is_none());
+}
+
+#[actix_rt::test]
+async fn banned_sessions_cant_change_the_account() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let name = unique("hugo");
+    let user = harness.user(&name, "hunter2").await;
+    let cookie = login(&mut app, &name, "hunter2").await;
+    harness
+        .repo
+        .set_status(user.id, UserStatus::Banned)
+        .await
+        .unwrap();
+
+    // moving off the banned address would let it register again
+    let req = post()
+        .uri("/auth/update-email.html")
+        .cookie(cookie.clone())
+        .set_form(&[("email", "elsewhere@example.com"), ("password", "hunter2")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let req = post()
+        .uri("/auth/update-password.html")
+        .cookie(cookie)
+        .set_form(&[
+            ("old_password", "hunter2"),
+            ("new_password", "correct horse battery"),
+            ("new_password2", "correct horse battery"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+    let user = harness.repo.user(user.id).await.unwrap().unwrap();
+    assert_eq!(user.email, format!("{}@example.com", name));
+    assert!(argon2::verify_encoded(&user.pwhash, b"hunter2").unwrap());
+}
+
+#[actix_rt::test]
+async fn forced_resets_need_a_new_password() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("ida");
+    harness.admin(&username, "hunter2").await;
+    let name = unique("james");
+    let user = harness.user(&name, "hunter2").await;
+    let admin_cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = post()
+        .uri(&format!("/account/users/{}/reset.html", user.id))
+        .cookie(admin_cookie)
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+
+    // logging in asks for a new password instead
+    let req = post()
+        .uri("/auth/login.html")
+        .set_form(&[("username", name.as_str()), ("password", "hunter2")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert!(resp
+        .response()
+        .cookies()
+        .all(|cookie| cookie.name() != "auth-cookie"));
+    assert!(body(resp).await.contains("action=\"/auth/reset-password.html\""));
+
+    let reset = |old: &'static str, new: &'static str| {
+        post()
+            .uri("/auth/reset-password.html")
+            .header(header::ACCEPT_LANGUAGE, "en")
+            .set_form(&[
+                ("username", name.as_str()),
+                ("old_password", old),
+                ("new_password", new),
+                ("new_password2", new),
+            ])
+            .to_request()
+    };
+    // mistakes show the form again, still for the same user
+    let resp = test::call_service(&mut app, reset("hunter2", "hunter2")).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let page = body(resp).await;
+    assert!(page.contains("The new password has to differ from the old one."));
+    assert!(page.contains(&format!("name=\"username\" value=\"{}\"", name)));
+    let resp = test::call_service(&mut app, reset("hunter3", "correct horse battery")).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(body(resp).await.contains("The password is wrong."));
+    let resp = test::call_service(&mut app, reset("hunter2", "correct horse battery")).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+
+    let changed = harness.repo.user(user.id).await.unwrap().unwrap();
+    assert!(!changed.reset_password);
+    login(&mut app, &name, "correct horse battery").await;
+}
+
+#[actix_rt::test]
+async fn deleted_users_leave_their_articles() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("katherine");
+    harness.admin(&username, "hunter2").await;
+    let heir = harness.employee(&unique("leslie"), "hunter2").await;
+    let first = harness.employee(&unique("margaret"), "hunter2").await;
+    let second = harness.employee(&unique("niklaus"), "hunter2").await;
+    let kept = harness.article(&first, &unique("kept"), "# Kept", None, None).await;
+    let orphan = harness.article(&second, &unique("orphan"), "# Orphan", None, None).await;
+    harness
+        .repo
+        .save_draft(second.id, "private/nowhere/drafts/gone.md", "gone")
+        .await
+        .unwrap();
+    let cookie = login(&mut app, &username, "hunter2").await;
+
+    let req = post()
+        .uri(&format!("/account/users/{}/delete.html", first.id))
+        .cookie(cookie.clone())
+        .set_form(&[("heir", heir.id.to_string().as_str())])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    assert!(harness.repo.user(first.id).await.unwrap().is_none());
+    let kept = harness.repo.article(kept.id).await.unwrap().unwrap();
+    assert_eq!(kept.author, Some(heir.id));
+
+    let req = post()
+        .uri(&format!("/account/users/{}/delete.html", second.id))
+        .cookie(cookie)
+        .set_form(&[("heir", "")])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+    assert!(harness.repo.drafts(second.id).await.unwrap().is_empty());
+    // the article stays, without anyone credited for it
+    let orphan = harness.repo.article(orphan.id).await.unwrap().unwrap();
+    assert_eq!(orphan.author, None);
+}
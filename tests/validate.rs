@@ -0,0 +1,124 @@
+mod common;
+
+use std::collections::HashSet;
+
+use actix_web::http::{header, StatusCode};
+use actix_web::test;
+
+use circus_backend::config::ValidationConfig;
+use circus_backend::validate;
+
+use common::{body, post, unique, Harness};
+
+#[test]
+fn usernames_follow_the_rules() {
+    let config = ValidationConfig::default();
+    let key = |name: &str| validate::username(&config, name).map(|error| error.key);
+    assert_eq!(key("ada.lovelace"), None);
+    assert_eq!(key("jd_42"), None);
+    assert_eq!(key("jd"), Some("validation_username_short"));
+    assert_eq!(key(&"a".repeat(33)), Some("validation_username_long"));
+    assert_eq!(key("ada lovelace"), Some("validation_username_chars"));
+    assert_eq!(key("<b>ada</b>"), Some("validation_username_chars"));
+    assert_eq!(key("{{{csrf}}}"), Some("validation_username_chars"));
+    assert_eq!(key(".ada"), Some("validation_username_start"));
+    assert_eq!(key("Admin"), Some("validation_username_reserved"));
+}
+
+#[test]
+fn emails_are_checked() {
+    for valid in &["ada@example.org", "a.b+c@mail.example.co.uk", "x_y@ex-ample.de"] {
+        assert!(validate::email(valid).is_none(), "{}", valid);
+    }
+    for invalid in &[
+        "",
+        "ada",
+        "ada@",
+        "@example.org",
+        "ada@localhost",
+        "ada@@example.org",
+        "ada..b@example.org",
+        "ada@-example.org",
+        "ada@example.o",
+        "ada lovelace@example.org",
+        "<ada>@example.org",
+    ] {
+        assert!(validate::email(invalid).is_some(), "{}", invalid);
+    }
+}
+
+#[test]
+fn passwords_have_to_be_strong() {
+    let config = ValidationConfig::default();
+    let common = ["password".to_string()].iter().cloned().collect::<HashSet<_>>();
+    let key = |password: &str| {
+        validate::password(&config, &common, password, "ada", "lovelace@example.org")
+            .map(|error| error.key)
+    };
+    assert_eq!(key("correct horse battery"), None);
+    assert_eq!(key("short"), Some("validation_password_short"));
+    assert_eq!(key(&"x".repeat(1025)), Some("validation_password_long"));
+    assert_eq!(key("PassWord"), Some("validation_password_common"));
+    assert_eq!(key("my name is ada"), Some("validation_password_personal"));
+    assert_eq!(key("lovelace1815"), Some("validation_password_personal"));
+}
+
+#[actix_rt::test]
+async fn create_shows_what_was_wrong() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let req = post()
+        .uri("/auth/create.html")
+        // the messages are compared in English
+        .header(header::ACCEPT_LANGUAGE, "en")
+        .set_form(&[
+            ("firstname", "<Ada>"),
+            ("lastname", ""),
+            ("username", "ada lovelace"),
+            ("email", "ada@localhost"),
+            ("password", "password1"),
+            ("password2", "password2"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let page = body(resp).await;
+    // what was entered is kept, but escaped
+    assert!(page.contains("value=\"&lt;Ada&gt;\""));
+    assert!(page.contains("value=\"ada lovelace\""));
+    assert!(page.contains("Only letters, digits and - _ . are allowed."));
+    assert!(page.contains("This is not a valid e-mail address."));
+    assert!(page.contains("This password is too common"));
+    assert!(page.contains("The passwords don't match."));
+    assert!(!page.contains("password1"));
+    assert!(harness.repo.user_by_name("ada lovelace").await.unwrap().is_none());
+}
+
+#[actix_rt::test]
+async fn usernames_are_unique_ignoring_case() {
+    let harness = Harness::new().await;
+    let mut app = harness.app().await;
+    let username = unique("grace");
+    harness.user(&username, "hunter2").await;
+    let req = post()
+        .uri("/auth/create.html")
+        .header(header::ACCEPT_LANGUAGE, "en")
+        .set_form(&[
+            ("firstname", ""),
+            ("lastname", ""),
+            ("username", username.to_uppercase().as_str()),
+            ("email", "grace@example.org"),
+            ("password", "correct horse battery"),
+            ("password2", "correct horse battery"),
+        ])
+        .to_request();
+    let resp = test::call_service(&mut app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    assert!(body(resp).await.contains("This user name is already taken."));
+    assert!(harness
+        .repo
+        .user_by_name(&username.to_uppercase())
+        .await
+        .unwrap()
+        .is_none());
+}